@@ -0,0 +1,267 @@
+use actix::{Actor, Handler, Message as ActixMessage, SyncContext};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::AnalysisConfig,
+    engine::{search, uci::uci_move, Limits},
+    game::{san, GameRecord, Move, Position},
+    puzzle::{mine, Puzzle},
+    storage::Storage,
+};
+
+// evaluations are capped here, so a mate is a large advantage rather than an endless one
+pub const MAX_EVAL: i32 = 1_500;
+// centipawns a move may give away against the engine's choice before it is
+// an inaccuracy, a mistake or a blunder
+const INACCURACY: i32 = 50;
+const MISTAKE: i32 = 100;
+const BLUNDER: i32 = 300;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Judgement {
+    // the engine's move, or one that gives away next to nothing against it
+    Best,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    fn of(loss: i32) -> Self {
+        match loss {
+            loss if loss >= BLUNDER => Judgement::Blunder,
+            loss if loss >= MISTAKE => Judgement::Mistake,
+            loss if loss >= INACCURACY => Judgement::Inaccuracy,
+            _ => Judgement::Best,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct AnalysedMove {
+    pub ply: usize,
+    pub uci: String,
+    pub san: String,
+    // what the engine preferred, None when it agrees with the move played
+    pub best: Option<String>,
+    // of the position after the move, in centipawns from white's point of view
+    pub eval: i32,
+    // centipawns given away against the engine's choice
+    pub loss: i32,
+    pub judgement: Judgement,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Debug)]
+pub struct PlayerReport {
+    // from 0 to 100, how much of their winning chances the moves kept on average
+    pub accuracy: f32,
+    pub average_loss: i32,
+    pub inaccuracies: u32,
+    pub mistakes: u32,
+    pub blunders: u32,
+}
+
+// the built in engine's verdict on every move of a finished game
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Analysis {
+    pub id: String,
+    pub depth: u32,
+    pub moves: Vec<AnalysedMove>,
+    // white's and black's
+    pub players: [PlayerReport; 2],
+}
+
+// chances of winning with a centipawn advantage, from 0 to 100
+fn win_percent(eval: i32) -> f32 {
+    50.0 + 50.0 * (2.0 / (1.0 + (-0.003_682_08 * eval as f32).exp()) - 1.0)
+}
+
+// the share of its winning chances a move kept, fitted so that giving away
+// nothing is 100 and a lost won position is near 0
+fn move_accuracy(before: i32, loss: i32) -> f32 {
+    let dropped = (win_percent(before) - win_percent(before - loss)).max(0.0);
+    (103.166_8 * (-0.043_54 * dropped).exp() - 3.166_9).clamp(0.0, 100.0)
+}
+
+// the side to move's score and the engine's move, if it has one
+pub fn score(position: &Position, limits: Limits) -> (i32, Option<Move>) {
+    if position.legal_moves().is_empty() {
+        let score = if position.in_check() { -MAX_EVAL } else { 0 };
+        return (score, None);
+    }
+    let result = search(position, limits);
+    (result.score.clamp(-MAX_EVAL, MAX_EVAL), result.best)
+}
+
+// searches every position of the game, so each move is judged by how much
+// worse it left the player than the engine's choice would have
+pub fn analyse(record: &GameRecord, limits: Limits) -> Analysis {
+    let mut position = record.variant.start();
+    let mut before = score(&position, limits);
+    let mut moves = vec![];
+    let mut accuracies: [Vec<f32>; 2] = [vec![], vec![]];
+    let mut players = [PlayerReport::default(); 2];
+    for (ply, played) in record.moves.iter().enumerate() {
+        let mv = played.as_move();
+        let side = position.turn;
+        let name = san(&position, mv);
+        let best = before.1.filter(|&best| best != mv);
+        let mut after = position.clone();
+        after.play(mv);
+        let next = score(&after, limits);
+        // what the move left its player with, against what was on offer
+        let loss = match best {
+            Some(_) => (before.0 + next.0).max(0),
+            None => 0,
+        };
+        let judgement = Judgement::of(loss);
+        let report = &mut players[side];
+        match judgement {
+            Judgement::Best => {}
+            Judgement::Inaccuracy => report.inaccuracies += 1,
+            Judgement::Mistake => report.mistakes += 1,
+            Judgement::Blunder => report.blunders += 1,
+        }
+        report.average_loss += loss;
+        accuracies[side].push(move_accuracy(before.0, loss));
+        moves.push(AnalysedMove {
+            ply: ply + 1,
+            uci: uci_move(mv),
+            san: name,
+            best: best.map(|best| san(&position, best)),
+            eval: if after.turn == 0 { next.0 } else { -next.0 },
+            loss,
+            judgement,
+        });
+        position = after;
+        before = next;
+    }
+    for (report, accuracies) in players.iter_mut().zip(&accuracies) {
+        let count = accuracies.len().max(1);
+        report.average_loss /= count as i32;
+        report.accuracy = match accuracies.is_empty() {
+            true => 100.0,
+            false => (accuracies.iter().sum::<f32>() / count as f32 * 10.0).round() / 10.0,
+        };
+    }
+    Analysis {
+        id: record.id.clone(),
+        depth: limits.depth,
+        moves,
+        players,
+    }
+}
+
+// works through finished games one at a time, on a thread of its own so the
+// searching never holds up live play. its mailbox is the queue of games
+pub struct Analyzer {
+    storage: Storage,
+    limits: Limits,
+}
+
+impl Analyzer {
+    pub fn new(storage: Storage, config: &AnalysisConfig) -> Self {
+        Self {
+            storage,
+            limits: Limits {
+                depth: config.depth,
+                nodes: u64::MAX,
+                time: config.movetime(),
+            },
+        }
+    }
+}
+
+impl Actor for Analyzer {
+    type Context = SyncContext<Self>;
+}
+
+// resolves to the puzzles found in the game, already saved
+#[derive(ActixMessage)]
+#[rtype(result = "Vec<Puzzle>")]
+pub struct Analyze(pub GameRecord);
+
+impl Handler<Analyze> for Analyzer {
+    type Result = Vec<Puzzle>;
+    fn handle(&mut self, msg: Analyze, _ctx: &mut Self::Context) -> Self::Result {
+        let analysis = analyse(&msg.0, self.limits);
+        match self.storage.save_analysis(&analysis) {
+            Ok(()) => info!("Analysed game {}", analysis.id),
+            Err(err) => error!("Could not save analysis of {}: {err}", analysis.id),
+        }
+        let puzzles = mine(&msg.0, &analysis, self.limits);
+        for puzzle in puzzles.iter() {
+            if let Err(err) = self.storage.save_puzzle(puzzle) {
+                error!("Could not save puzzle {}: {err}", puzzle.id);
+            }
+        }
+        puzzles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{record_from_uci, GameVariant};
+    use std::time::Duration;
+
+    fn limits() -> Limits {
+        Limits {
+            depth: 3,
+            nodes: u64::MAX,
+            time: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn judges_moves_by_what_they_give_away() {
+        assert_eq!(Judgement::of(0), Judgement::Best);
+        assert_eq!(Judgement::of(49), Judgement::Best);
+        assert_eq!(Judgement::of(50), Judgement::Inaccuracy);
+        assert_eq!(Judgement::of(150), Judgement::Mistake);
+        assert_eq!(Judgement::of(300), Judgement::Blunder);
+        assert!(move_accuracy(40, 0) > 99.9);
+        assert!(move_accuracy(40, 100) > move_accuracy(40, 400));
+        // a move that still wins easily loses little in winning chances
+        assert!(move_accuracy(1_200, 100) > move_accuracy(0, 100));
+    }
+
+    #[test]
+    fn fools_mate_is_a_blunder_then_mate() {
+        let analysis = analyse(
+            &record_from_uci(GameVariant::Standard, "f2f3 e7e5 g2g4 d8h4"),
+            limits(),
+        );
+        assert_eq!(analysis.moves.len(), 4);
+        let g4 = &analysis.moves[2];
+        assert_eq!(g4.san, "g4");
+        assert_eq!(g4.judgement, Judgement::Blunder);
+        let mate = &analysis.moves[3];
+        assert_eq!(
+            (mate.judgement, mate.best.as_deref()),
+            (Judgement::Best, None)
+        );
+        assert_eq!(mate.eval, -MAX_EVAL);
+        let [white, black] = analysis.players;
+        assert_eq!(white.blunders, 1);
+        assert_eq!(black.blunders, 0);
+        assert!(white.accuracy < black.accuracy);
+    }
+
+    #[test]
+    fn hanging_the_queen_is_caught() {
+        // 1. e4 d6 2. Qg4?? walks into the bishop
+        let analysis = analyse(
+            &record_from_uci(GameVariant::Standard, "e2e4 d7d6 d1g4"),
+            limits(),
+        );
+        let qg4 = &analysis.moves[2];
+        assert!(qg4.loss >= BLUNDER, "{qg4:?}");
+        assert!(qg4.best.is_some());
+        assert!(qg4.eval < -BLUNDER);
+        assert_eq!(analysis.players[0].blunders, 1);
+        assert_eq!(analysis.players[1].blunders, 0);
+    }
+}
@@ -0,0 +1,327 @@
+use actix::Addr;
+use actix_web::{
+    get,
+    http::{header, StatusCode},
+    web::{self, Data, Path, Query, ServiceConfig},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::uci::parse_move,
+    explorer::{ExplorerFilter, MoveStats},
+    game::{
+        fen, parse_fen, pgn, position_at, replay, GameRecord, GetRecord, Position, ReplayError,
+    },
+    profile::Category,
+    server::{Explore, GetHistory, GetPlayers, GetProfile, ListGames, LookupGame, Server},
+    storage::Storage,
+    tournament::{ListTournaments, LookupTournament, Tournaments},
+};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(get_players)
+        .service(list_games)
+        .service(get_game)
+        .service(get_game_pgn)
+        .service(get_game_position)
+        .service(get_game_evaluations)
+        .service(get_game_analysis)
+        .service(get_user)
+        .service(get_user_games)
+        .service(explore)
+        .service(list_tournaments)
+        .service(get_tournament);
+}
+
+#[derive(Serialize)]
+struct ApiError {
+    error: String,
+}
+
+fn error(status: StatusCode, message: &str) -> HttpResponse {
+    HttpResponse::build(status).json(ApiError {
+        error: message.to_owned(),
+    })
+}
+
+fn unavailable() -> HttpResponse {
+    error(StatusCode::SERVICE_UNAVAILABLE, "server is unavailable")
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum GameStatus {
+    Live,
+    Finished,
+}
+
+#[derive(Serialize)]
+struct GameView {
+    status: GameStatus,
+    fen: String,
+    #[serde(flatten)]
+    record: GameRecord,
+}
+
+// live games are asked for their state, anything else comes from the archive
+async fn find_game(
+    srv: &Addr<Server>,
+    storage: &Storage,
+    id: String,
+) -> Result<(GameStatus, GameRecord), HttpResponse> {
+    let live = srv
+        .send(LookupGame(id.clone()))
+        .await
+        .map_err(|_| unavailable())?;
+    if let Some(game) = live {
+        // the game may end between the lookup and this message, in which case
+        // it is in the archive by the time we look there
+        if let Ok(record) = game.send(GetRecord).await {
+            return Ok((GameStatus::Live, record));
+        }
+    }
+    let storage = storage.clone();
+    let archived = web::block(move || storage.load_game(&id))
+        .await
+        .map_err(|_| unavailable())?;
+    match archived {
+        Ok(Some(record)) => Ok((GameStatus::Finished, record)),
+        Ok(None) => Err(error(StatusCode::NOT_FOUND, "no such game")),
+        Err(err) => {
+            log::error!("Could not load game: {err}");
+            Err(error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "could not load game",
+            ))
+        }
+    }
+}
+
+#[get("/players")]
+async fn get_players(srv: Data<Addr<Server>>) -> HttpResponse {
+    match srv.send(GetPlayers {}).await {
+        Ok(players) => HttpResponse::Ok().json(players),
+        Err(_) => unavailable(),
+    }
+}
+
+#[get("/games")]
+async fn list_games(srv: Data<Addr<Server>>) -> HttpResponse {
+    match srv.send(ListGames).await {
+        Ok(games) => HttpResponse::Ok().json(games),
+        Err(_) => unavailable(),
+    }
+}
+
+#[get("/games/{id}")]
+async fn get_game(
+    srv: Data<Addr<Server>>,
+    storage: Data<Storage>,
+    id: Path<String>,
+) -> HttpResponse {
+    match find_game(&srv, &storage, id.into_inner()).await {
+        Ok((status, mut record)) => {
+            // the chat stays between the two players
+            record.chat.clear();
+            HttpResponse::Ok().json(GameView {
+                status,
+                fen: fen(&replay(record.variant.start(), &record.moves)),
+                record,
+            })
+        }
+        Err(response) => response,
+    }
+}
+
+#[get("/games/{id}/pgn")]
+async fn get_game_pgn(
+    srv: Data<Addr<Server>>,
+    storage: Data<Storage>,
+    id: Path<String>,
+) -> HttpResponse {
+    match find_game(&srv, &storage, id.into_inner()).await {
+        Ok((_, record)) => HttpResponse::Ok()
+            .content_type("application/x-chess-pgn")
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.pgn\"", record.id),
+            ))
+            .body(pgn(&record)),
+        Err(response) => response,
+    }
+}
+
+// the position after any ply, rebuilt by playing the game's moves through its rules
+#[get("/games/{id}/replay/{ply}")]
+async fn get_game_position(
+    srv: Data<Addr<Server>>,
+    storage: Data<Storage>,
+    path: Path<(String, usize)>,
+) -> HttpResponse {
+    let (id, ply) = path.into_inner();
+    let record = match find_game(&srv, &storage, id).await {
+        Ok((_, record)) => record,
+        Err(response) => return response,
+    };
+    match position_at(&record, ply) {
+        Ok(position) => HttpResponse::Ok().json(position),
+        Err(ReplayError::NoSuchPly { plies }) => error(
+            StatusCode::NOT_FOUND,
+            &format!("the game only has {plies} plies"),
+        ),
+        Err(err) => {
+            log::error!("Could not replay game {}: {err:?}", record.id);
+            error(StatusCode::INTERNAL_SERVER_ERROR, "could not replay game")
+        }
+    }
+}
+
+#[get("/games/{id}/evaluations")]
+async fn get_game_evaluations(storage: Data<Storage>, id: Path<String>) -> HttpResponse {
+    let storage = storage.get_ref().clone();
+    let id = id.into_inner();
+    match web::block(move || storage.load_evaluations(&id)).await {
+        Ok(Ok(Some(evaluations))) => HttpResponse::Ok().json(evaluations),
+        Ok(Ok(None)) => error(StatusCode::NOT_FOUND, "game has not been evaluated"),
+        Ok(Err(err)) => {
+            log::error!("Could not load evaluations: {err}");
+            error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "could not load evaluations",
+            )
+        }
+        Err(_) => unavailable(),
+    }
+}
+
+#[get("/games/{id}/analysis")]
+async fn get_game_analysis(storage: Data<Storage>, id: Path<String>) -> HttpResponse {
+    let storage = storage.get_ref().clone();
+    let id = id.into_inner();
+    match web::block(move || storage.load_analysis(&id)).await {
+        Ok(Ok(Some(analysis))) => HttpResponse::Ok().json(analysis),
+        Ok(Ok(None)) => error(StatusCode::NOT_FOUND, "game has not been analysed"),
+        Ok(Err(err)) => {
+            log::error!("Could not load analysis: {err}");
+            error(StatusCode::INTERNAL_SERVER_ERROR, "could not load analysis")
+        }
+        Err(_) => unavailable(),
+    }
+}
+
+#[get("/users/{name}")]
+async fn get_user(srv: Data<Addr<Server>>, name: Path<String>) -> HttpResponse {
+    match srv.send(GetProfile(name.into_inner())).await {
+        Ok(Some(profile)) => HttpResponse::Ok().json(profile),
+        Ok(None) => error(StatusCode::NOT_FOUND, "no such user"),
+        Err(_) => unavailable(),
+    }
+}
+
+#[derive(Deserialize)]
+struct Page {
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[get("/users/{name}/games")]
+async fn get_user_games(
+    srv: Data<Addr<Server>>,
+    name: Path<String>,
+    page: Query<Page>,
+) -> HttpResponse {
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return error(
+            StatusCode::BAD_REQUEST,
+            &format!("limit must be between 1 and {MAX_PAGE_SIZE}"),
+        );
+    }
+    let history = GetHistory {
+        username: name.into_inner(),
+        offset: page.offset.unwrap_or(0),
+        limit,
+    };
+    match srv.send(history).await {
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(_) => unavailable(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ExplorerQuery {
+    // the position to start from, the initial one if left out
+    fen: Option<String>,
+    // uci moves played from there, separated by commas
+    moves: Option<String>,
+    min_rating: Option<i32>,
+    max_rating: Option<i32>,
+    category: Option<Category>,
+}
+
+#[derive(Serialize)]
+struct ExplorerView {
+    fen: String,
+    games: u32,
+    moves: Vec<MoveStats>,
+}
+
+#[get("/explorer")]
+async fn explore(srv: Data<Addr<Server>>, query: Query<ExplorerQuery>) -> HttpResponse {
+    let query = query.into_inner();
+    let mut position = match &query.fen {
+        Some(text) => match parse_fen(text) {
+            Some(position) => position,
+            None => return error(StatusCode::BAD_REQUEST, "invalid fen"),
+        },
+        None => Position::default(),
+    };
+    for text in query.moves.iter().flat_map(|moves| moves.split(',')) {
+        match parse_move(&position, text.trim()) {
+            Some(mv) => {
+                position.play(mv);
+            }
+            None => return error(StatusCode::BAD_REQUEST, &format!("illegal move {text}")),
+        }
+    }
+    if let (Some(min), Some(max)) = (query.min_rating, query.max_rating) {
+        if min > max {
+            return error(StatusCode::BAD_REQUEST, "min_rating is above max_rating");
+        }
+    }
+    let filter = ExplorerFilter {
+        min_rating: query.min_rating,
+        max_rating: query.max_rating,
+        category: query.category,
+    };
+    let fen = fen(&position);
+    match srv.send(Explore { position, filter }).await {
+        Ok(moves) => HttpResponse::Ok().json(ExplorerView {
+            fen,
+            games: moves.iter().map(|stats| stats.games).sum(),
+            moves,
+        }),
+        Err(_) => unavailable(),
+    }
+}
+
+#[get("/tournaments")]
+async fn list_tournaments(tournaments: Data<Addr<Tournaments>>) -> HttpResponse {
+    match tournaments.send(ListTournaments).await {
+        Ok(list) => HttpResponse::Ok().json(list),
+        Err(_) => unavailable(),
+    }
+}
+
+#[get("/tournaments/{id}")]
+async fn get_tournament(tournaments: Data<Addr<Tournaments>>, id: Path<u64>) -> HttpResponse {
+    match tournaments.send(LookupTournament(id.into_inner())).await {
+        Ok(Some(tournament)) => HttpResponse::Ok().json(tournament),
+        Ok(None) => error(StatusCode::NOT_FOUND, "no such tournament"),
+        Err(_) => unavailable(),
+    }
+}
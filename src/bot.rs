@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{game::GameVariant, profile::Category};
+
+// which challenges a bot takes on its own, set with `AcceptChallenges`
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChallengeFilter {
+    pub humans: bool,
+    pub bots: bool,
+    // players who never logged in, and so have no rating
+    pub anonymous: bool,
+    // variants played besides standard chess, which not every engine knows.
+    // the setup of a chess960 entry does not matter
+    pub variants: Vec<GameVariant>,
+    // time control categories to play, empty for any
+    pub categories: Vec<Category>,
+    // bounds on the challenger's rating in the game's category
+    pub min_rating: Option<i32>,
+    pub max_rating: Option<i32>,
+}
+
+impl Default for ChallengeFilter {
+    fn default() -> Self {
+        Self {
+            humans: true,
+            bots: false,
+            anonymous: true,
+            variants: vec![],
+            categories: vec![],
+            min_rating: None,
+            max_rating: None,
+        }
+    }
+}
+
+// whoever is waiting for a game, as a bot sees them
+#[derive(Clone, Copy, Debug)]
+pub enum Challenger {
+    Anonymous,
+    Human { rating: i32 },
+    Bot { rating: i32 },
+}
+
+impl ChallengeFilter {
+    pub fn accepts(
+        &self,
+        challenger: Challenger,
+        category: Category,
+        variant: GameVariant,
+    ) -> bool {
+        if !self.categories.is_empty() && !self.categories.contains(&category) {
+            return false;
+        }
+        if variant != GameVariant::Standard && !self.variants.iter().any(|v| v.same_rules(variant))
+        {
+            return false;
+        }
+        let rating = match challenger {
+            Challenger::Anonymous => return self.anonymous,
+            Challenger::Human { rating } if self.humans => rating,
+            Challenger::Bot { rating } if self.bots => rating,
+            _ => return false,
+        };
+        self.min_rating.is_none_or(|min| rating >= min)
+            && self.max_rating.is_none_or(|max| rating <= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn takes(filter: &ChallengeFilter, challenger: Challenger, category: Category) -> bool {
+        filter.accepts(challenger, category, GameVariant::Standard)
+    }
+
+    #[test]
+    fn defaults_take_any_human() {
+        let filter = ChallengeFilter::default();
+        assert!(takes(&filter, Challenger::Anonymous, Category::Bullet));
+        assert!(takes(
+            &filter,
+            Challenger::Human { rating: 2900 },
+            Category::Classical
+        ));
+        // two bots answering each other forever is opt in
+        assert!(!takes(
+            &filter,
+            Challenger::Bot { rating: 1500 },
+            Category::Blitz
+        ));
+        let chess960 = GameVariant::Chess960(None);
+        assert!(!filter.accepts(Challenger::Anonymous, Category::Blitz, chess960));
+    }
+
+    #[test]
+    fn narrows_by_category_and_rating() {
+        let filter: ChallengeFilter = serde_json::from_str(
+            r#"{"bots": true, "anonymous": false, "categories": ["blitz"], "max_rating": 1800}"#,
+        )
+        .unwrap();
+        let human = |rating| Challenger::Human { rating };
+        assert!(takes(
+            &filter,
+            Challenger::Bot { rating: 1500 },
+            Category::Blitz
+        ));
+        assert!(takes(&filter, human(1800), Category::Blitz));
+        assert!(!takes(&filter, human(1801), Category::Blitz));
+        assert!(!takes(&filter, human(1500), Category::Rapid));
+        assert!(!takes(&filter, Challenger::Anonymous, Category::Blitz));
+    }
+
+    #[test]
+    fn plays_only_the_variants_listed() {
+        let filter: ChallengeFilter =
+            serde_json::from_str(r#"{"variants": [{"Chess960": null}, "Antichess"]}"#).unwrap();
+        let takes = |variant| filter.accepts(Challenger::Anonymous, Category::Blitz, variant);
+        assert!(takes(GameVariant::Standard));
+        assert!(takes(GameVariant::Chess960(Some(12))));
+        assert!(takes(GameVariant::Antichess));
+        assert!(!takes(GameVariant::ThreeCheck));
+    }
+}
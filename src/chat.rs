@@ -0,0 +1,218 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ChatConfig;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum ChatScope {
+    // everyone logged in who listens to the lobby
+    Lobby,
+    // the players of the game the sender is in
+    Game,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct ChatLine {
+    pub from: String,
+    pub text: String,
+    // unix seconds
+    pub at: u64,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum ChatError {
+    NotLoggedIn,
+    NotInGame,
+    Empty,
+    TooLong { max: usize },
+    RateLimited,
+    // the word filter turned the message down
+    Rejected,
+}
+
+// decides what may be said. returns the text to deliver, which may differ from
+// what was sent, or None to drop the message altogether
+pub trait WordFilter: Send {
+    fn filter(&self, text: &str) -> Option<String>;
+}
+
+// stars out every listed word, ignoring case
+pub struct BlockList {
+    words: Vec<String>,
+}
+
+impl BlockList {
+    pub fn new(words: &[String]) -> Self {
+        Self {
+            words: words.iter().map(|word| word.to_lowercase()).collect(),
+        }
+    }
+}
+
+impl WordFilter for BlockList {
+    fn filter(&self, text: &str) -> Option<String> {
+        let mut out = String::with_capacity(text.len());
+        let mut word = String::new();
+        let flush = |word: &mut String, out: &mut String| {
+            match self.words.contains(&word.to_lowercase()) {
+                true => out.extend(word.chars().map(|_| '*')),
+                false => out.push_str(word),
+            }
+            word.clear();
+        };
+        for c in text.chars() {
+            if c.is_alphanumeric() {
+                word.push(c);
+            } else {
+                flush(&mut word, &mut out);
+                out.push(c);
+            }
+        }
+        flush(&mut word, &mut out);
+        Some(out)
+    }
+}
+
+// at most `limit` messages per user in any `window`
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    sent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            sent: HashMap::new(),
+        }
+    }
+
+    pub fn allow(&mut self, username: &str, now: Instant) -> bool {
+        let sent = self.sent.entry(username.to_owned()).or_default();
+        while sent
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= self.window)
+        {
+            sent.pop_front();
+        }
+        if sent.len() >= self.limit {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+
+    pub fn forget(&mut self, username: &str) {
+        self.sent.remove(username);
+    }
+}
+
+// every check a message goes through before anyone sees it
+pub struct ChatPolicy {
+    max_length: usize,
+    limiter: RateLimiter,
+    filter: Box<dyn WordFilter>,
+}
+
+impl ChatPolicy {
+    pub fn new(config: &ChatConfig, filter: Box<dyn WordFilter>) -> Self {
+        Self {
+            max_length: config.max_length,
+            limiter: RateLimiter::new(config.rate_messages, config.rate_window()),
+            filter,
+        }
+    }
+
+    pub fn check(&mut self, username: &str, text: &str, now: Instant) -> Result<String, ChatError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(ChatError::Empty);
+        }
+        if text.chars().count() > self.max_length {
+            return Err(ChatError::TooLong {
+                max: self.max_length,
+            });
+        }
+        if !self.limiter.allow(username, now) {
+            return Err(ChatError::RateLimited);
+        }
+        self.filter.filter(text).ok_or(ChatError::Rejected)
+    }
+
+    pub fn forget(&mut self, username: &str) {
+        self.limiter.forget(username);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ChatPolicy {
+        let config = ChatConfig {
+            max_length: 10,
+            rate_messages: 2,
+            rate_window_secs: 5,
+            blocked_words: vec!["Darn".to_owned()],
+        };
+        ChatPolicy::new(&config, Box::new(BlockList::new(&config.blocked_words)))
+    }
+
+    #[test]
+    fn blocks_listed_words_only() {
+        let filter = BlockList::new(&["darn".to_owned()]);
+        assert_eq!(
+            filter.filter("DARN it, darned darn!").unwrap(),
+            "**** it, darned ****!"
+        );
+    }
+
+    #[test]
+    fn checks_length_and_emptiness() {
+        let mut policy = policy();
+        let now = Instant::now();
+        assert_eq!(policy.check("alice", "   ", now), Err(ChatError::Empty));
+        assert_eq!(
+            policy.check("alice", "far too long", now),
+            Err(ChatError::TooLong { max: 10 })
+        );
+        assert_eq!(policy.check("alice", " darn ", now).unwrap(), "****");
+    }
+
+    #[test]
+    fn limits_each_user_separately() {
+        let mut policy = policy();
+        let start = Instant::now();
+        assert!(policy.check("alice", "one", start).is_ok());
+        assert!(policy.check("alice", "two", start).is_ok());
+        assert_eq!(
+            policy.check("alice", "three", start),
+            Err(ChatError::RateLimited)
+        );
+        assert!(policy.check("bob", "one", start).is_ok());
+        assert!(policy
+            .check("alice", "four", start + Duration::from_secs(5))
+            .is_ok());
+    }
+
+    #[test]
+    fn filters_can_be_swapped() {
+        struct Silence;
+        impl WordFilter for Silence {
+            fn filter(&self, _text: &str) -> Option<String> {
+                None
+            }
+        }
+        let mut policy = ChatPolicy::new(&ChatConfig::default(), Box::new(Silence));
+        assert_eq!(
+            policy.check("alice", "hello", Instant::now()),
+            Err(ChatError::Rejected)
+        );
+    }
+}
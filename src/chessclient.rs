@@ -1,235 +1,754 @@
 use std::time::{Duration, Instant};
 
-use crate::codec::{FrameCodec, FrameError};
-use crate::game::{ForfeitGame, Game, MakeMove};
+use crate::chat::ChatError;
+use crate::codec::{self, Encoding, FrameCodec, FrameError};
+use crate::config::HeartbeatConfig;
+use crate::game::{
+    Berserk, DropPiece, ForfeitGame, Game, GameVariant, GetLegalMoves, MakeMove, MoveDetails,
+    MoveError, MuteOpponent, PushLegalMoves, Replay, SendSnapshot,
+};
 use crate::message::{
     ClientMessage::{self, *},
-    Login, Logout,
+    Login, Logout, SubscribeLobby,
+};
+use crate::message::{ClientResult, Connect, Disconnect, OutgoingMessage};
+use crate::metrics::{Transport, METRICS};
+use crate::puzzle::{Attempt, Progress};
+use crate::server::{
+    self, CancelSearch, FindGame, GetProfile, LoadGame, Opponent, PlayEngine, Server,
 };
-use crate::message::{ClientResult, Disconnect, OutgoingMessage};
-use crate::server::{CancelSearch, FindGame, Server};
+use crate::tournament::{self, Tournaments};
+use actix::dev::ToEnvelope;
 use actix::io::{FramedWrite, WriteHandler};
 use actix::{
-    Actor, ActorContext, Addr, AsyncContext, Context, Handler, Message as ActixMessage, Running,
-    StreamHandler,
+    fut, Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, Handler,
+    Message as ActixMessage, Running, SpawnHandle, StreamHandler, WrapFuture,
 };
 use actix_web_actors::ws::{self, WebsocketContext};
 use log::{info, warn};
-use serde_json::to_string;
-use tokio::io::WriteHalf;
-use tokio::net::TcpStream;
-use ws::Message::{Close, Ping, Text};
+use tokio::io::{AsyncRead, AsyncWrite, WriteHalf};
+use ws::Message::{Binary, Close, Ping, Text};
 
-static HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-static HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+// how fast a replay may be played back, in milliseconds per move
+const MIN_REPLAY_INTERVAL: u64 = 100;
+const MAX_REPLAY_INTERVAL: u64 = 10_000;
 
-pub struct ChessClient {
+// per connection state shared by every transport
+pub struct Session {
     username: Option<String>,
     heartbeat: Instant,
+    heartbeat_config: HeartbeatConfig,
     server: Addr<Server>,
+    tournaments: Addr<Tournaments>,
     game: Option<Addr<Game>>,
+    push_legal_moves: bool,
+    lobby: bool,
+    // presented a certificate signed by the configured client ca
+    authenticated: bool,
+    // the timer stepping through a replay, while one is playing
+    replay: Option<SpawnHandle>,
+    puzzle: Option<Attempt>,
 }
 
-impl ChessClient {
-    pub fn new(server: Addr<Server>) -> Self {
+impl Session {
+    pub fn new(
+        server: Addr<Server>,
+        tournaments: Addr<Tournaments>,
+        heartbeat_config: HeartbeatConfig,
+    ) -> Self {
         Self {
             username: None,
             heartbeat: Instant::now(),
+            heartbeat_config,
             server,
+            tournaments,
             game: None,
+            push_legal_moves: false,
+            lobby: true,
+            authenticated: false,
+            replay: None,
+            puzzle: None,
         }
     }
+}
+
+// protocol handling for a connected player, independent of how the bytes
+// reach us. transports only need to hand over their session and know how
+// to put an outgoing message on the wire
+pub trait Client: Actor + Handler<Message>
+where
+    Self::Context: AsyncContext<Self> + ToEnvelope<Self, Message>,
+{
+    fn session(&mut self) -> &mut Session;
+    fn transport(&self) -> Transport;
+    fn send(&mut self, message: OutgoingMessage, ctx: &mut Self::Context);
+    // switches the wire encoding, returns false if it is too late to do so
+    fn negotiate(&mut self, encoding: Encoding) -> bool;
+
+    fn hb(&mut self, ctx: &mut Self::Context) {
+        let interval = self.session().heartbeat_config.interval();
+        ctx.run_interval(interval, |act, ctx| {
+            let session = act.session();
+            if Instant::now().duration_since(session.heartbeat) > session.heartbeat_config.timeout()
+            {
+                METRICS.heartbeat_timeout();
+                match session.username.take() {
+                    Some(username) => {
+                        info!("Client {username} timeout! Disconnecting!");
+                        session.server.do_send(Logout { username });
+                    }
+                    None => info!("Client timeout! Disconnecting!"),
+                }
+                ctx.stop();
+            }
+        });
+    }
 
-    fn handle_message(&mut self, message: &str, ctx: &mut WebsocketContext<Self>) {
+    // lets the server reach this connection even before it logs in
+    fn connect(&mut self, ctx: &mut Self::Context) {
+        METRICS.client_connected(self.transport());
+        let player = ctx.address().recipient();
+        self.session().server.do_send(Connect { player });
+    }
+
+    fn disconnect(&mut self, ctx: &mut Self::Context) {
         let addr = ctx.address().recipient();
-        if let Ok(message) = serde_json::from_str::<ClientMessage>(message) {
-            self.heartbeat = Instant::now();
-            match message {
-                ClientMessage::Ping => {}
-                Login(username) => self.server.do_send(Login {
+        let session = self.session();
+        session.server.do_send(Disconnect { player: addr });
+        if let Some(username) = session.username.take() {
+            session.server.do_send(Logout { username });
+        }
+    }
+
+    fn login(&mut self, username: String, bot: bool, ctx: &mut Self::Context) {
+        if self.session().username.is_some() {
+            self.send(OutgoingMessage::Result(ClientResult::LoginError), ctx);
+            return;
+        }
+        let session = self.session();
+        let login = session
+            .server
+            .send(Login {
+                username: username.clone(),
+                client: ctx.address().recipient(),
+                lobby: session.lobby,
+                bot,
+                authenticated: session.authenticated,
+            })
+            .into_actor(self)
+            .then(move |res, act, _| {
+                if let Ok(true) = res {
+                    let session = act.session();
+                    match (bot, session.authenticated) {
+                        (true, _) => log::info!("bot logged in: {username}"),
+                        (false, true) => log::info!("logged in: {username} (client certificate)"),
+                        (false, false) => log::info!("logged in: {username}"),
+                    }
+                    session.username = Some(username);
+                }
+                fut::ready(())
+            });
+        ctx.wait(login);
+    }
+
+    fn join_tournament(&mut self, id: u64, join: bool, ctx: &mut Self::Context) {
+        let Some(username) = self.session().username.clone() else {
+            return self.send(OutgoingMessage::Result(ClientResult::NotLoggedIn), ctx);
+        };
+        self.session()
+            .tournaments
+            .do_send(tournament::JoinTournament {
+                username,
+                client: ctx.address().recipient(),
+                id,
+                join,
+            });
+    }
+
+    fn watch_replay(
+        &mut self,
+        id: String,
+        from_ply: usize,
+        interval: u64,
+        ctx: &mut Self::Context,
+    ) {
+        if !(MIN_REPLAY_INTERVAL..=MAX_REPLAY_INTERVAL).contains(&interval) {
+            return self.send(OutgoingMessage::Result(ClientResult::InvalidPace), ctx);
+        }
+        let load = self
+            .session()
+            .server
+            .send(LoadGame(id))
+            .into_actor(self)
+            .map(move |res, act, ctx| {
+                let Ok(Some(record)) = res else {
+                    return act.send(OutgoingMessage::Result(ClientResult::NoSuchGame), ctx);
+                };
+                let mut replay = Replay::new(record.variant, record.moves);
+                if let Err(err) = replay.seek(from_ply) {
+                    return act.send(OutgoingMessage::Result(ClientResult::ReplayError(err)), ctx);
+                }
+                // only one replay at a time, the latest asked for
+                act.stop_replay(ctx);
+                act.send(OutgoingMessage::Replay(Box::new(replay.current())), ctx);
+                if replay.finished() {
+                    return act.send(OutgoingMessage::ReplayFinished, ctx);
+                }
+                let every = Duration::from_millis(interval);
+                let handle = ctx.run_interval(every, move |act, ctx| {
+                    match replay.advance() {
+                        Ok(_) => act.send(OutgoingMessage::Replay(Box::new(replay.current())), ctx),
+                        Err(err) => {
+                            let err = ClientResult::ReplayError(err);
+                            act.send(OutgoingMessage::Result(err), ctx);
+                            return act.stop_replay(ctx);
+                        }
+                    }
+                    if replay.finished() {
+                        act.send(OutgoingMessage::ReplayFinished, ctx);
+                        act.stop_replay(ctx);
+                    }
+                });
+                act.session().replay = Some(handle);
+            });
+        ctx.spawn(load);
+    }
+
+    fn stop_replay(&mut self, ctx: &mut Self::Context) {
+        if let Some(handle) = self.session().replay.take() {
+            ctx.cancel_future(handle);
+        }
+    }
+
+    fn next_puzzle(&mut self, ctx: &mut Self::Context) {
+        let Some(username) = self.session().username.clone() else {
+            return self.send(OutgoingMessage::Result(ClientResult::NotLoggedIn), ctx);
+        };
+        self.finish_puzzle(false, ctx);
+        let next = self
+            .session()
+            .server
+            .send(server::NextPuzzle(username))
+            .into_actor(self)
+            .map(|res, act, ctx| {
+                let Some(attempt) = res.ok().flatten().and_then(Attempt::new) else {
+                    return act.send(OutgoingMessage::Result(ClientResult::NoPuzzle), ctx);
+                };
+                act.send(OutgoingMessage::Puzzle(Box::new(attempt.view())), ctx);
+                act.session().puzzle = Some(attempt);
+            });
+        ctx.spawn(next);
+    }
+
+    fn puzzle_move(&mut self, details: MoveDetails, ctx: &mut Self::Context) {
+        let Some(attempt) = self.session().puzzle.as_mut() else {
+            return self.send(OutgoingMessage::Result(ClientResult::NoPuzzle), ctx);
+        };
+        match attempt.play(&details) {
+            Ok(Progress::Reply(reply)) => self.send(OutgoingMessage::PuzzleReply(reply), ctx),
+            Ok(Progress::Solved) => self.finish_puzzle(true, ctx),
+            Ok(Progress::Failed) => self.finish_puzzle(false, ctx),
+            Err(err) => self.send(OutgoingMessage::Result(ClientResult::MoveError(err)), ctx),
+        }
+    }
+
+    // rates the puzzle in progress, if there is one
+    fn finish_puzzle(&mut self, solved: bool, ctx: &mut Self::Context) {
+        let session = self.session();
+        let (Some(attempt), Some(username)) = (session.puzzle.take(), session.username.clone())
+        else {
+            return;
+        };
+        let solution = attempt.solution();
+        let result = session
+            .server
+            .send(server::PuzzleResult {
+                username,
+                id: attempt.id().to_owned(),
+                solved,
+            })
+            .into_actor(self)
+            .map(move |res, act, ctx| {
+                if let Ok(Some(rating)) = res {
+                    let finished = OutgoingMessage::PuzzleFinished {
+                        solved,
+                        solution,
+                        rating,
+                    };
+                    act.send(finished, ctx);
+                }
+            });
+        ctx.spawn(result);
+    }
+
+    fn handle_message(&mut self, message: ClientMessage, ctx: &mut Self::Context) {
+        let addr = ctx.address().recipient();
+        self.session().heartbeat = Instant::now();
+        match message {
+            ClientMessage::Ping => {}
+            Hello(encoding) => {
+                if self.negotiate(encoding) {
+                    self.send(OutgoingMessage::Result(ClientResult::Ok), ctx);
+                }
+            }
+            Login(username) => self.login(username, false, ctx),
+            BotLogin(username) => match self.transport() {
+                Transport::WebSocket => {
+                    self.send(OutgoingMessage::Result(ClientResult::LoginError), ctx)
+                }
+                _ => self.login(username, true, ctx),
+            },
+            AcceptChallenges(filter) => {
+                let Some(username) = self.session().username.clone() else {
+                    return self.send(OutgoingMessage::Result(ClientResult::NotABot), ctx);
+                };
+                self.session().server.do_send(server::AcceptChallenges {
+                    username,
+                    client: addr,
+                    filter,
+                });
+            }
+            Enqueue => {
+                let username = self.session().username.clone();
+                self.session().server.do_send(FindGame {
+                    client: addr,
+                    username,
+                    variant: GameVariant::Standard,
+                    days_per_move: None,
+                });
+            }
+            Seek(variant) => {
+                let username = self.session().username.clone();
+                self.session().server.do_send(FindGame {
+                    client: addr,
+                    username,
+                    variant,
+                    days_per_move: None,
+                });
+            }
+            SeekCorrespondence { days, variant } => {
+                let username = self.session().username.clone();
+                self.session().server.do_send(FindGame {
+                    client: addr,
+                    username,
+                    variant,
+                    days_per_move: Some(days),
+                });
+            }
+            ListCorrespondence => {
+                let Some(username) = self.session().username.clone() else {
+                    return self.send(OutgoingMessage::Result(ClientResult::NotLoggedIn), ctx);
+                };
+                self.session().server.do_send(server::ListCorrespondence {
+                    username,
+                    client: addr,
+                });
+            }
+            OpenCorrespondence(id) => {
+                let Some(username) = self.session().username.clone() else {
+                    return self.send(OutgoingMessage::Result(ClientResult::NotLoggedIn), ctx);
+                };
+                self.session().server.do_send(server::OpenCorrespondence {
                     username,
                     client: addr,
+                    id,
+                });
+            }
+            Dequeue => {
+                self.session().server.do_send(CancelSearch(addr));
+            }
+            PlayComputer(level) => {
+                let username = self.session().username.clone();
+                self.session().server.do_send(PlayEngine {
+                    client: addr,
+                    username,
+                    opponent: Opponent::Builtin(level),
+                });
+            }
+            PlayUciEngine => {
+                let username = self.session().username.clone();
+                self.session().server.do_send(PlayEngine {
+                    client: addr,
+                    username,
+                    opponent: Opponent::Uci,
+                });
+            }
+            LeaveGame => {
+                if let Some(game) = &self.session().game {
+                    game.do_send(ForfeitGame(addr));
+                }
+            }
+            MakeMove(move_details) => match &self.session().game {
+                Some(game) => game.do_send(MakeMove {
+                    move_details,
+                    player: addr,
                 }),
-                Enqueue => {}
-                Dequeue => {}
-                LeaveGame => {}
-                MakeMove(move_details) => match &self.game {
-                    Some(game) => game.do_send(MakeMove {
-                        move_details,
+                None => {
+                    METRICS.move_rejected(MoveError::NotInGame);
+                    self.send(
+                        OutgoingMessage::Result(ClientResult::MoveError(MoveError::NotInGame)),
+                        ctx,
+                    )
+                }
+            },
+            DropPiece(details) => match &self.session().game {
+                Some(game) => game.do_send(DropPiece {
+                    details,
+                    player: addr,
+                }),
+                None => {
+                    METRICS.move_rejected(MoveError::NotInGame);
+                    self.send(
+                        OutgoingMessage::Result(ClientResult::MoveError(MoveError::NotInGame)),
+                        ctx,
+                    )
+                }
+            },
+            RequestSnapshot => match &self.session().game {
+                Some(game) => game.do_send(SendSnapshot(addr)),
+                None => self.send(
+                    OutgoingMessage::Result(ClientResult::MoveError(MoveError::NotInGame)),
+                    ctx,
+                ),
+            },
+            GetLegalMoves(from) => match &self.session().game {
+                Some(game) => game.do_send(GetLegalMoves { player: addr, from }),
+                None => self.send(
+                    OutgoingMessage::Result(ClientResult::MoveError(MoveError::NotInGame)),
+                    ctx,
+                ),
+            },
+            PushLegalMoves(enabled) => {
+                self.session().push_legal_moves = enabled;
+                if let Some(game) = &self.session().game {
+                    game.do_send(PushLegalMoves {
                         player: addr,
-                    }),
-                    None => ctx.text(
-                        to_string(&crate::message::OutgoingMessage::Result(
-                            ClientResult::MoveError(crate::game::MoveError::NotInGame),
-                        ))
-                        .unwrap(),
-                    ),
-                },
-                PlayAgain => {}
-                Disconnect => {
-                    self.server.do_send(Disconnect { player: addr });
-                    if let Some(username) = self.username.clone() {
-                        self.server.do_send(Logout { username });
-                    }
-                    log::info!("client disconnected!");
+                        enabled,
+                    });
                 }
             }
+            CreateTournament(settings) => {
+                let Some(username) = self.session().username.clone() else {
+                    return self.send(OutgoingMessage::Result(ClientResult::NotLoggedIn), ctx);
+                };
+                self.session()
+                    .tournaments
+                    .do_send(tournament::CreateTournament {
+                        username,
+                        client: addr,
+                        settings,
+                    });
+            }
+            JoinTournament(id) => self.join_tournament(id, true, ctx),
+            LeaveTournament(id) => self.join_tournament(id, false, ctx),
+            StartTournament(id) => {
+                let Some(username) = self.session().username.clone() else {
+                    return self.send(OutgoingMessage::Result(ClientResult::NotLoggedIn), ctx);
+                };
+                self.session()
+                    .tournaments
+                    .do_send(tournament::StartTournament {
+                        username,
+                        client: addr,
+                        id,
+                    });
+            }
+            ClientMessage::Berserk => match &self.session().game {
+                Some(game) => game.do_send(Berserk(addr)),
+                None => self.send(
+                    OutgoingMessage::Result(ClientResult::MoveError(MoveError::NotInGame)),
+                    ctx,
+                ),
+            },
+            WatchReplay {
+                game,
+                from_ply,
+                interval_ms,
+            } => self.watch_replay(game, from_ply, interval_ms, ctx),
+            StopReplay => self.stop_replay(ctx),
+            ClientMessage::NextPuzzle => self.next_puzzle(ctx),
+            PuzzleMove(details) => self.puzzle_move(details, ctx),
+            GetTournament(id) => self
+                .session()
+                .tournaments
+                .do_send(tournament::GetTournament { client: addr, id }),
+            ClientMessage::GetProfile(username) => {
+                let lookup = self
+                    .session()
+                    .server
+                    .send(GetProfile(username))
+                    .into_actor(self)
+                    .map(|res, act, ctx| match res {
+                        Ok(Some(profile)) => {
+                            act.send(OutgoingMessage::Profile(Box::new(profile)), ctx)
+                        }
+                        Ok(None) => {
+                            act.send(OutgoingMessage::Result(ClientResult::UnknownUser), ctx)
+                        }
+                        Err(_) => {}
+                    });
+                ctx.spawn(lookup);
+            }
+            ClientMessage::SubscribeLobby(enabled) => {
+                let session = self.session();
+                session.lobby = enabled;
+                // before login the choice just rides along with the login
+                if let Some(username) = session.username.clone() {
+                    session.server.do_send(SubscribeLobby { username, enabled });
+                }
+            }
+            ClientMessage::Chat { scope, text } => {
+                let session = self.session();
+                let Some(username) = session.username.clone() else {
+                    let err = ClientResult::ChatError(ChatError::NotLoggedIn);
+                    return self.send(OutgoingMessage::Result(err), ctx);
+                };
+                session.server.do_send(server::Chat {
+                    username,
+                    client: addr,
+                    scope,
+                    game: session.game.clone(),
+                    text,
+                });
+            }
+            ClientMessage::MuteOpponent(muted) => match &self.session().game {
+                Some(game) => game.do_send(MuteOpponent {
+                    player: addr,
+                    muted,
+                }),
+                None => self.send(
+                    OutgoingMessage::Result(ClientResult::ChatError(ChatError::NotInGame)),
+                    ctx,
+                ),
+            },
+            PlayAgain => self.session().server.do_send(server::Rematch {
+                client: addr,
+                variant: None,
+            }),
+            ClientMessage::Rematch(variant) => self.session().server.do_send(server::Rematch {
+                client: addr,
+                variant: Some(variant),
+            }),
+            Disconnect => {
+                self.disconnect(ctx);
+                log::info!("client disconnected!");
+            }
         }
     }
 
-    fn hb(&self, ctx: &mut WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            if Instant::now().duration_since(act.heartbeat) > HEARTBEAT_TIMEOUT {
-                if act.username.is_some() {
-                    let username = act.username.clone().unwrap();
-                    info!("Client {username} timeout! Disconnecting!");
-                    act.server.do_send(Logout { username });
-                    ctx.stop();
-                } else {
-                    info!("Client timeout! Disconnecting!");
-                    ctx.stop();
+    // keeps track of which game we are in before passing the message on
+    fn deliver(&mut self, msg: Message, ctx: &mut Self::Context) {
+        match msg.inner {
+            OutgoingMessage::WinGame(_)
+            | OutgoingMessage::LoseGame(_)
+            | OutgoingMessage::DrawGame(_)
+            | OutgoingMessage::GameAdjourned => {
+                self.session().game = None;
+            }
+            _ if msg.game.is_some() => {
+                // carry the push preference over into the game we just joined
+                if let (Some(game), true) = (&msg.game, self.session().push_legal_moves) {
+                    game.do_send(PushLegalMoves {
+                        player: ctx.address().recipient(),
+                        enabled: true,
+                    });
                 }
+                self.session().game = msg.game;
             }
-        });
+            _ => (),
+        }
+        self.send(msg.inner, ctx);
+    }
+}
+
+pub struct ChessClient {
+    session: Session,
+    encoding: Encoding,
+    negotiated: bool,
+}
+
+impl ChessClient {
+    pub fn new(
+        server: Addr<Server>,
+        tournaments: Addr<Tournaments>,
+        heartbeat: HeartbeatConfig,
+    ) -> Self {
+        Self {
+            session: Session::new(server, tournaments, heartbeat),
+            encoding: Encoding::Json,
+            negotiated: false,
+        }
+    }
+
+    fn handle_frame(&mut self, body: &[u8], encoding: Encoding, ctx: &mut WebsocketContext<Self>) {
+        match codec::decode(body, encoding) {
+            Ok(message) => {
+                self.handle_message(message, ctx);
+                self.negotiated = true;
+            }
+            Err(err) => {
+                METRICS.frame_error(&err);
+                warn!("Received bad frame: {err}");
+            }
+        }
+    }
+}
+
+impl Client for ChessClient {
+    fn session(&mut self) -> &mut Session {
+        &mut self.session
+    }
+
+    fn transport(&self) -> Transport {
+        Transport::WebSocket
+    }
+
+    // json goes out as text frames, everything else as binary frames
+    fn send(&mut self, message: OutgoingMessage, ctx: &mut WebsocketContext<Self>) {
+        match codec::encode(&message, self.encoding) {
+            Ok(body) => match self.encoding {
+                Encoding::Json => ctx.text(String::from_utf8_lossy(&body).into_owned()),
+                Encoding::MessagePack => ctx.binary(body),
+            },
+            Err(err) => warn!("{err}"),
+        }
+    }
+
+    fn negotiate(&mut self, encoding: Encoding) -> bool {
+        if !self.negotiated {
+            self.encoding = encoding;
+        }
+        !self.negotiated
     }
 }
 
 impl Actor for ChessClient {
     type Context = WebsocketContext<Self>;
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.connect(ctx);
         self.hb(ctx);
     }
-    fn stopping(&mut self, _: &mut Self::Context) -> Running {
-        match &self.username {
-            Some(username) => self.server.do_send(Logout {
-                username: username.to_string(),
-            }),
-            None => {}
-        };
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.disconnect(ctx);
         Running::Stop
     }
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        METRICS.client_disconnected(self.transport());
+    }
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChessClient {
     fn handle(&mut self, item: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         match item {
             Ok(Text(text)) => {
-                self.heartbeat = Instant::now();
-                self.handle_message(&text, ctx);
+                self.session.heartbeat = Instant::now();
+                self.handle_frame(text.as_bytes(), Encoding::Json, ctx);
+            }
+            Ok(Binary(bytes)) => {
+                self.session.heartbeat = Instant::now();
+                self.handle_frame(&bytes, Encoding::MessagePack, ctx);
             }
             Ok(Ping(message)) => {
-                self.heartbeat = Instant::now();
+                self.session.heartbeat = Instant::now();
                 ctx.pong(&message);
             }
             Ok(Close(reason)) => ctx.close(reason),
             _ => {
-                self.heartbeat = Instant::now();
+                self.session.heartbeat = Instant::now();
                 warn!("Received unrecognised message!");
             }
         }
     }
 }
 
-pub struct TcpClient {
-    username: Option<String>,
-    heartbeat: Instant,
-    server: Addr<Server>,
-    game: Option<Addr<Game>>,
-    framed: FramedWrite<OutgoingMessage, WriteHalf<TcpStream>, FrameCodec>,
+// anything the tcp transport can run over, plain sockets and tls streams alike
+pub trait ByteStream: AsyncRead + AsyncWrite + Unpin + 'static {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + 'static> ByteStream for S {}
+
+pub struct TcpClient<S: ByteStream> {
+    session: Session,
+    transport: Transport,
+    negotiated: bool,
+    framed: FramedWrite<OutgoingMessage, WriteHalf<S>, FrameCodec>,
 }
 
-impl StreamHandler<Result<ClientMessage, FrameError>> for TcpClient {
+impl<S: ByteStream> StreamHandler<Result<ClientMessage, FrameError>> for TcpClient<S> {
     fn handle(&mut self, item: Result<ClientMessage, FrameError>, ctx: &mut Self::Context) {
         match item {
-            Ok(message) => self.handle_message(message, ctx),
-            Err(_) => ctx.stop(),
+            Ok(message) => {
+                self.handle_message(message, ctx);
+                self.negotiated = true;
+            }
+            Err(err) => {
+                METRICS.frame_error(&err);
+                warn!("Dropping tcp client: {err}");
+                ctx.stop();
+            }
         }
     }
 }
 
-impl TcpClient {
+impl<S: ByteStream> TcpClient<S> {
     pub fn new(
         srv: Addr<Server>,
-        writer: FramedWrite<OutgoingMessage, WriteHalf<TcpStream>, FrameCodec>,
+        tournaments: Addr<Tournaments>,
+        writer: FramedWrite<OutgoingMessage, WriteHalf<S>, FrameCodec>,
+        heartbeat: HeartbeatConfig,
+        transport: Transport,
+        authenticated: bool,
     ) -> Self {
+        let mut session = Session::new(srv, tournaments, heartbeat);
+        session.authenticated = authenticated;
         TcpClient {
-            username: None,
-            heartbeat: Instant::now(),
-            server: srv,
-            game: None,
+            session,
+            transport,
+            negotiated: false,
             framed: writer,
         }
     }
-    fn hb(&self, ctx: &mut <Self as Actor>::Context) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            log::info!("Checking heartbeat");
-            if Instant::now().duration_since(act.heartbeat) > HEARTBEAT_TIMEOUT {
-                if act.username.is_some() {
-                    let username = act.username.clone().unwrap();
-                    log::info!("Client {username} timeout! Disconnecting!");
-                    act.server.do_send(Logout { username });
-                    ctx.stop();
-                } else {
-                    log::info!("Client timeout! Disconnecting!");
-                    ctx.stop();
-                }
-            }
-        });
+}
+
+impl<S: ByteStream> Client for TcpClient<S> {
+    fn session(&mut self) -> &mut Session {
+        &mut self.session
     }
 
-    fn handle_message(&mut self, message: ClientMessage, ctx: &mut <Self as Actor>::Context) {
-        let addr = ctx.address().recipient();
-        self.heartbeat = Instant::now();
-        match message {
-            ClientMessage::Ping => {}
-            Login(username) => {
-                let user = username.clone();
-                self.server.do_send(Login {
-                    username,
-                    client: addr,
-                });
-                log::info!("logged in: {user}");
-            }
-            Enqueue => {
-                self.server.do_send(FindGame(addr));
-            }
-            Dequeue => {
-                self.server.do_send(CancelSearch(addr));
-            }
-            LeaveGame => {
-                if let Some(game) = &self.game {
-                    game.do_send(ForfeitGame(addr));
-                }
-            }
-            MakeMove(move_details) => match &self.game {
-                Some(game) => game.do_send(MakeMove {
-                    move_details,
-                    player: addr,
-                }),
-                None => self.framed.write(OutgoingMessage::Result(
-                    crate::message::ClientResult::MoveError(crate::game::MoveError::NotInGame),
-                )),
-            },
-            PlayAgain => {}
-            Disconnect => {
-                self.server.do_send(Disconnect { player: addr });
-                if let Some(username) = self.username.clone() {
-                    self.server.do_send(Logout { username });
-                }
-                log::info!("client disconnected!");
-            }
-        }
+    fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    fn send(&mut self, message: OutgoingMessage, _ctx: &mut Context<Self>) {
+        self.framed.write(message);
+    }
+
+    // the codec has already switched encodings by the time we get here
+    fn negotiate(&mut self, _encoding: Encoding) -> bool {
+        !self.negotiated
     }
 }
 
-impl Actor for TcpClient {
+impl<S: ByteStream> Actor for TcpClient<S> {
     type Context = Context<Self>;
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.connect(ctx);
         self.hb(ctx);
     }
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.disconnect(ctx);
+        Running::Stop
+    }
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        METRICS.client_disconnected(self.transport());
+    }
 }
 
-impl WriteHandler<FrameError> for TcpClient {}
+impl<S: ByteStream> WriteHandler<FrameError> for TcpClient<S> {
+    fn error(&mut self, err: FrameError, _ctx: &mut Self::Context) -> Running {
+        warn!("Failed to write to tcp client: {err}");
+        Running::Stop
+    }
+}
 
 #[derive(ActixMessage)]
 #[rtype(result = "()")]
@@ -241,28 +760,13 @@ pub struct Message {
 impl Handler<Message> for ChessClient {
     type Result = ();
     fn handle(&mut self, msg: Message, ctx: &mut Self::Context) -> Self::Result {
-        match msg.inner {
-            OutgoingMessage::GameStarted(color) => {
-                self.game = msg.game;
-            }
-            OutgoingMessage::WinGame(_) => {
-                self.game = None;
-            }
-            OutgoingMessage::LoseGame(_) => {
-                self.game = None;
-            }
-            _ => (),
-        }
-        ctx.text(to_string(&msg.inner).unwrap() + "\n");
+        self.deliver(msg, ctx);
     }
 }
 
-impl Handler<Message> for TcpClient {
+impl<S: ByteStream> Handler<Message> for TcpClient<S> {
     type Result = ();
-    fn handle(&mut self, msg: Message, _ctx: &mut Self::Context) -> Self::Result {
-        if let Some(game) = msg.game {
-            self.game = Some(game);
-        }
-        self.framed.write(msg.inner);
+    fn handle(&mut self, msg: Message, ctx: &mut Self::Context) -> Self::Result {
+        self.deliver(msg, ctx);
     }
 }
@@ -1,28 +1,196 @@
-use bytes::{BufMut, BytesMut};
-use serde_json::{from_str, to_string};
+use std::{
+    cell::Cell,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    rc::Rc,
+    str::Utf8Error,
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use serde::{Deserialize, Serialize};
 use tokio_util::codec::{Decoder, Encoder};
 
 use crate::message::{ClientMessage, OutgoingMessage};
 
+// size of the big-endian length prefix in front of every binary frame
+const LENGTH_PREFIX: usize = 4;
+// largest frame body a client may send before we drop the connection
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 64 * 1024;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+#[derive(Debug)]
 pub enum FrameError {
-    ParseError(serde_json::Error),
-    ReadError(std::io::Error),
+    Oversize { length: usize, max: usize },
+    InvalidUtf8(Utf8Error),
+    Json(serde_json::Error),
+    MessagePack(rmp_serde::decode::Error),
+    Serialize(SerializeError),
+    Io(std::io::Error),
+}
+
+#[derive(Debug)]
+pub enum SerializeError {
+    Json(serde_json::Error),
+    MessagePack(rmp_serde::encode::Error),
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Oversize { length, max } => {
+                write!(f, "frame of {length} bytes exceeds the {max} byte limit")
+            }
+            FrameError::InvalidUtf8(err) => write!(f, "frame is not valid utf-8: {err}"),
+            FrameError::Json(err) => write!(f, "malformed json frame: {err}"),
+            FrameError::MessagePack(err) => write!(f, "malformed messagepack frame: {err}"),
+            FrameError::Serialize(err) => write!(f, "failed to serialize message: {err}"),
+            FrameError::Io(err) => write!(f, "connection error: {err}"),
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FrameError::Oversize { .. } => None,
+            FrameError::InvalidUtf8(err) => Some(err),
+            FrameError::Json(err) => Some(err),
+            FrameError::MessagePack(err) => Some(err),
+            FrameError::Serialize(err) => Some(err),
+            FrameError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl Display for SerializeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializeError::Json(err) => Display::fmt(err, f),
+            SerializeError::MessagePack(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl Error for SerializeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SerializeError::Json(err) => Some(err),
+            SerializeError::MessagePack(err) => Some(err),
+        }
+    }
 }
 
 impl From<std::io::Error> for FrameError {
     fn from(value: std::io::Error) -> Self {
-        FrameError::ReadError(value)
+        FrameError::Io(value)
+    }
+}
+
+impl From<Utf8Error> for FrameError {
+    fn from(value: Utf8Error) -> Self {
+        FrameError::InvalidUtf8(value)
+    }
+}
+
+impl From<serde_json::Error> for FrameError {
+    fn from(value: serde_json::Error) -> Self {
+        FrameError::Json(value)
+    }
+}
+
+impl From<rmp_serde::decode::Error> for FrameError {
+    fn from(value: rmp_serde::decode::Error) -> Self {
+        FrameError::MessagePack(value)
+    }
+}
+
+impl From<SerializeError> for FrameError {
+    fn from(value: SerializeError) -> Self {
+        FrameError::Serialize(value)
+    }
+}
+
+// serializes a single message body, without any framing
+pub fn encode(item: &OutgoingMessage, encoding: Encoding) -> Result<Vec<u8>, FrameError> {
+    match encoding {
+        Encoding::Json => {
+            let mut body = serde_json::to_vec(item).map_err(SerializeError::Json)?;
+            body.push(b'\n');
+            Ok(body)
+        }
+        Encoding::MessagePack => {
+            Ok(rmp_serde::to_vec_named(item).map_err(SerializeError::MessagePack)?)
+        }
+    }
+}
+
+// parses a single message body, without any framing
+pub fn decode(body: &[u8], encoding: Encoding) -> Result<ClientMessage, FrameError> {
+    match encoding {
+        Encoding::Json => Ok(serde_json::from_str(std::str::from_utf8(body)?)?),
+        Encoding::MessagePack => Ok(rmp_serde::from_slice(body)?),
+    }
+}
+
+// newline-delimited json until the client negotiates otherwise with a
+// `Hello` as its very first frame, after which both directions switch.
+// clones share the negotiated encoding so the read and write halves of
+// a connection stay in step
+#[derive(Clone)]
+pub struct FrameCodec {
+    encoding: Rc<Cell<Encoding>>,
+    negotiated: bool,
+    max_frame_length: usize,
+}
+
+impl FrameCodec {
+    pub fn new() -> Self {
+        Self::with_max_frame_length(DEFAULT_MAX_FRAME_LENGTH)
+    }
+
+    pub fn with_max_frame_length(max_frame_length: usize) -> Self {
+        Self {
+            encoding: Rc::new(Cell::new(Encoding::Json)),
+            negotiated: false,
+            max_frame_length,
+        }
+    }
+
+    fn check_length(&self, length: usize) -> Result<(), FrameError> {
+        if length > self.max_frame_length {
+            Err(FrameError::Oversize {
+                length,
+                max: self.max_frame_length,
+            })
+        } else {
+            Ok(())
+        }
     }
 }
 
-pub struct FrameCodec;
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Encoder<OutgoingMessage> for FrameCodec {
     type Error = FrameError;
     fn encode(&mut self, item: OutgoingMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let str = to_string(&item).unwrap() + "\n";
-        println!("{str}");
-        dst.put_slice(&str.as_bytes());
+        let encoding = self.encoding.get();
+        let body = encode(&item, encoding)?;
+        log::trace!("writing {} byte {encoding:?} frame", body.len());
+        if encoding == Encoding::MessagePack {
+            dst.reserve(LENGTH_PREFIX + body.len());
+            dst.put_u32(body.len() as u32);
+        }
+        dst.put_slice(&body);
         Ok(())
     }
 }
@@ -31,15 +199,198 @@ impl Decoder for FrameCodec {
     type Error = FrameError;
     type Item = ClientMessage;
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if let Some(pos) = src.iter().position(|&c| c == b'\n') {
-            let line = src.split_to(pos + 1);
-            let str = String::from_utf8_lossy(&line);
-            match from_str::<ClientMessage>(&str) {
-                Ok(message) => Ok(Some(message)),
-                Err(err) => Err(FrameError::ParseError(err)),
+        let encoding = self.encoding.get();
+        let body = match encoding {
+            Encoding::Json => match src.iter().position(|&c| c == b'\n') {
+                Some(pos) => {
+                    self.check_length(pos)?;
+                    src.split_to(pos + 1)
+                }
+                None => {
+                    // no terminator yet, so refuse to keep buffering past the limit
+                    self.check_length(src.len())?;
+                    return Ok(None);
+                }
+            },
+            Encoding::MessagePack => {
+                if src.len() < LENGTH_PREFIX {
+                    return Ok(None);
+                }
+                let length = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+                self.check_length(length)?;
+                if src.len() < LENGTH_PREFIX + length {
+                    src.reserve(LENGTH_PREFIX + length - src.len());
+                    return Ok(None);
+                }
+                src.advance(LENGTH_PREFIX);
+                src.split_to(length)
             }
-        } else {
-            Ok(None)
+        };
+        let message = decode(&body, encoding)?;
+        if !self.negotiated {
+            self.negotiated = true;
+            if let ClientMessage::Hello(encoding) = message {
+                self.encoding.set(encoding);
+            }
+        }
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(codec: &mut FrameCodec, bytes: &[u8]) -> Result<Vec<ClientMessage>, FrameError> {
+        let mut src = BytesMut::from(bytes);
+        let mut out = vec![];
+        while let Some(message) = codec.decode(&mut src)? {
+            out.push(message);
+        }
+        Ok(out)
+    }
+
+    fn feed_err(codec: &mut FrameCodec, bytes: &[u8]) -> FrameError {
+        feed(codec, bytes).expect_err("expected a frame error")
+    }
+
+    fn binary_frame(body: &[u8]) -> Vec<u8> {
+        let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    #[test]
+    fn decodes_several_json_frames_in_one_read() {
+        let mut codec = FrameCodec::new();
+        let messages = feed(&mut codec, b"\"Ping\"\n\"Enqueue\"\n\"Dequ").unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(matches!(messages[0], ClientMessage::Ping));
+        assert!(matches!(messages[1], ClientMessage::Enqueue));
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_split_json_frame() {
+        let mut codec = FrameCodec::new();
+        let mut src = BytesMut::from(&b"{\"Login\":\"al"[..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(b"ice\"}\n");
+        match codec.decode(&mut src).unwrap() {
+            Some(ClientMessage::Login(name)) => assert_eq!(name, "alice"),
+            _ => panic!("expected a login"),
         }
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn rejects_unterminated_json_past_the_limit() {
+        let mut codec = FrameCodec::with_max_frame_length(16);
+        let err = feed_err(&mut codec, &[b'a'; 17]);
+        assert!(matches!(err, FrameError::Oversize { length: 17, max: 16 }));
+    }
+
+    #[test]
+    fn rejects_terminated_json_line_past_the_limit() {
+        let mut codec = FrameCodec::with_max_frame_length(8);
+        let err = feed_err(&mut codec, b"{\"Login\":\"mallory\"}\n");
+        assert!(matches!(err, FrameError::Oversize { max: 8, .. }));
+    }
+
+    #[test]
+    fn accepts_json_frame_exactly_at_the_limit() {
+        let mut codec = FrameCodec::with_max_frame_length(6);
+        let messages = feed(&mut codec, b"\"Ping\"\n").unwrap();
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_utf8() {
+        let mut codec = FrameCodec::new();
+        let err = feed_err(&mut codec, b"\"Pi\xff\xfeng\"\n");
+        assert!(matches!(err, FrameError::InvalidUtf8(_)));
+    }
+
+    #[test]
+    fn rejects_malformed_json() {
+        let mut codec = FrameCodec::new();
+        let err = feed_err(&mut codec, b"{\"Login\":\n");
+        assert!(matches!(err, FrameError::Json(_)));
+        let err = feed_err(&mut codec, b"\"Resign\"\n");
+        assert!(matches!(err, FrameError::Json(_)));
+    }
+
+    #[test]
+    fn hello_switches_both_directions_to_messagepack() {
+        let mut reader = FrameCodec::new();
+        let mut writer = reader.clone();
+        let mut stream = b"{\"Hello\":\"MessagePack\"}\n".to_vec();
+        stream.extend(binary_frame(&rmp_serde::to_vec_named(&ClientMessage::Ping).unwrap()));
+        let messages = feed(&mut reader, &stream).unwrap();
+        assert!(matches!(messages[0], ClientMessage::Hello(Encoding::MessagePack)));
+        assert!(matches!(messages[1], ClientMessage::Ping));
+
+        let mut dst = BytesMut::new();
+        writer
+            .encode(OutgoingMessage::Result(crate::message::ClientResult::Ok), &mut dst)
+            .unwrap();
+        let length = u32::from_be_bytes([dst[0], dst[1], dst[2], dst[3]]) as usize;
+        assert_eq!(length, dst.len() - LENGTH_PREFIX);
+    }
+
+    #[test]
+    fn hello_is_only_honoured_as_the_first_frame() {
+        let mut codec = FrameCodec::new();
+        feed(&mut codec, b"\"Ping\"\n{\"Hello\":\"MessagePack\"}\n").unwrap();
+        // still speaking json
+        let messages = feed(&mut codec, b"\"Ping\"\n").unwrap();
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn rejects_declared_binary_length_past_the_limit_without_buffering() {
+        let mut codec = FrameCodec::with_max_frame_length(1024);
+        feed(&mut codec, b"{\"Hello\":\"MessagePack\"}\n").unwrap();
+        let mut src = BytesMut::from(&u32::MAX.to_be_bytes()[..]);
+        let err = codec.decode(&mut src).expect_err("expected a frame error");
+        assert!(matches!(err, FrameError::Oversize { max: 1024, .. }));
+        assert!(src.capacity() < 1024);
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_split_binary_frame() {
+        let mut codec = FrameCodec::new();
+        feed(&mut codec, b"{\"Hello\":\"MessagePack\"}\n").unwrap();
+        let frame = binary_frame(&rmp_serde::to_vec_named(&ClientMessage::Enqueue).unwrap());
+        let mut src = BytesMut::new();
+        for byte in &frame[..frame.len() - 1] {
+            src.extend_from_slice(&[*byte]);
+            assert!(codec.decode(&mut src).unwrap().is_none());
+        }
+        src.extend_from_slice(&frame[frame.len() - 1..]);
+        assert!(matches!(
+            codec.decode(&mut src).unwrap(),
+            Some(ClientMessage::Enqueue)
+        ));
+    }
+
+    #[test]
+    fn rejects_garbage_binary_frames() {
+        let mut codec = FrameCodec::new();
+        feed(&mut codec, b"{\"Hello\":\"MessagePack\"}\n").unwrap();
+        let err = feed_err(&mut codec, &binary_frame(&[0xc1, 0xff, 0x00]));
+        assert!(matches!(err, FrameError::MessagePack(_)));
+    }
+
+    #[test]
+    fn errors_render_as_readable_text() {
+        let err = FrameError::Oversize {
+            length: 10,
+            max: 5,
+        };
+        assert_eq!(err.to_string(), "frame of 10 bytes exceeds the 5 byte limit");
+        assert!(err.source().is_none());
+        let err = feed_err(&mut FrameCodec::new(), b"\xff\n");
+        assert!(err.to_string().starts_with("frame is not valid utf-8"));
+        assert!(err.source().is_some());
     }
 }
@@ -0,0 +1,578 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    net::ToSocketAddrs,
+    path::PathBuf,
+    time::Duration,
+};
+
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::{codec::DEFAULT_MAX_FRAME_LENGTH, game::TimeControl};
+
+// used when no --config flag or CHESS_CONFIG is given, silently skipped if missing
+const DEFAULT_CONFIG_PATH: &str = "chess-backend.toml";
+
+// command line flags, each of which can also come from the environment.
+// anything set here wins over the config file
+#[derive(Parser, Debug, Default)]
+#[command(version, about)]
+pub struct Args {
+    /// path to a TOML config file
+    #[arg(long, env = "CHESS_CONFIG")]
+    config: Option<PathBuf>,
+    /// address the http and websocket server binds to
+    #[arg(long, env = "CHESS_HTTP_BIND")]
+    http_bind: Option<String>,
+    #[arg(long, env = "CHESS_HTTP_ENABLED")]
+    http_enabled: Option<bool>,
+    /// address the raw tcp game server binds to
+    #[arg(long, env = "CHESS_TCP_BIND")]
+    tcp_bind: Option<String>,
+    #[arg(long, env = "CHESS_TCP_ENABLED")]
+    tcp_enabled: Option<bool>,
+    /// serve the tcp listener over tls
+    #[arg(long, env = "CHESS_TLS_ENABLED")]
+    tls_enabled: Option<bool>,
+    /// PEM certificate chain presented to tcp clients
+    #[arg(long, env = "CHESS_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key for the certificate
+    #[arg(long, env = "CHESS_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+    /// PEM ca whose client certificates mark trusted bots
+    #[arg(long, env = "CHESS_TLS_CLIENT_CA")]
+    tls_client_ca: Option<PathBuf>,
+    /// turn away tcp clients without a certificate from the client ca
+    #[arg(long, env = "CHESS_TLS_REQUIRE_CLIENT_CERT")]
+    tls_require_client_cert: Option<bool>,
+    /// largest frame a client may send, in bytes
+    #[arg(long, env = "CHESS_MAX_FRAME_LENGTH")]
+    max_frame_length: Option<usize>,
+    /// number of http worker threads
+    #[arg(long, env = "CHESS_WORKERS")]
+    workers: Option<usize>,
+    /// seconds between heartbeat checks
+    #[arg(long, env = "CHESS_HEARTBEAT_INTERVAL")]
+    heartbeat_interval: Option<u64>,
+    /// seconds of silence before a client is dropped
+    #[arg(long, env = "CHESS_HEARTBEAT_TIMEOUT")]
+    heartbeat_timeout: Option<u64>,
+    /// default time control as minutes+increment, e.g. 10+5
+    #[arg(long, env = "CHESS_TIME_CONTROL")]
+    time_control: Option<TimeControl>,
+    /// directory adjourned games are saved to
+    #[arg(long, env = "CHESS_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// seconds running games get to finish on shutdown before being adjourned
+    #[arg(long, env = "CHESS_SHUTDOWN_GRACE")]
+    shutdown_grace: Option<u64>,
+    /// uci engine binary players can challenge and games are evaluated with
+    #[arg(long, env = "CHESS_UCI_COMMAND")]
+    uci_command: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub bind: String,
+    pub workers: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bind: "localhost:3000".to_owned(),
+            workers: 2,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TcpConfig {
+    pub enabled: bool,
+    pub bind: String,
+    pub tls: TlsConfig,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bind: "127.0.0.1:9000".to_owned(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // enables mutual tls, clients with a certificate from this ca are trusted bots
+    pub client_ca: Option<PathBuf>,
+    pub require_client_cert: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 5,
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    pub time_control: TimeControl,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("data"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    pub grace_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self { grace_secs: 30 }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn grace(&self) -> Duration {
+        Duration::from_secs(self.grace_secs)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatConfig {
+    // in characters
+    pub max_length: usize,
+    // messages a user may send within the window
+    pub rate_messages: usize,
+    pub rate_window_secs: u64,
+    // starred out wherever they appear as a whole word
+    pub blocked_words: Vec<String>,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            max_length: 500,
+            rate_messages: 5,
+            rate_window_secs: 10,
+            blocked_words: vec![],
+        }
+    }
+}
+
+impl ChatConfig {
+    pub fn rate_window(&self) -> Duration {
+        Duration::from_secs(self.rate_window_secs)
+    }
+}
+
+// a local engine binary speaking uci, e.g. stockfish
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct UciConfig {
+    // empty leaves the uci engine off
+    pub command: PathBuf,
+    pub args: Vec<String>,
+    // sent as setoption before the first search
+    pub options: BTreeMap<String, String>,
+    // thinking time per move when playing
+    pub movetime_ms: u64,
+    pub analysis_depth: u32,
+    // evaluate every finished game once it is archived
+    pub evaluate_games: bool,
+}
+
+impl Default for UciConfig {
+    fn default() -> Self {
+        Self {
+            command: PathBuf::new(),
+            args: vec![],
+            options: BTreeMap::new(),
+            movetime_ms: 1_000,
+            analysis_depth: 12,
+            evaluate_games: false,
+        }
+    }
+}
+
+impl UciConfig {
+    pub fn enabled(&self) -> bool {
+        !self.command.as_os_str().is_empty()
+    }
+
+    pub fn movetime(&self) -> Duration {
+        Duration::from_millis(self.movetime_ms)
+    }
+}
+
+// post-game analysis with the built in engine, away from the game server
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalysisConfig {
+    // analyse every finished game the built in engine knows the rules of, which
+    // is also where puzzles come from
+    pub enabled: bool,
+    // search limits for each position of a game
+    pub depth: u32,
+    pub movetime_ms: u64,
+    // games analysed at once, each on a thread of its own
+    pub workers: usize,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            depth: 4,
+            movetime_ms: 200,
+            workers: 1,
+        }
+    }
+}
+
+impl AnalysisConfig {
+    pub fn movetime(&self) -> Duration {
+        Duration::from_millis(self.movetime_ms)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotConfig {
+    // clock for games between two bots, which have no human reaction times to allow for
+    pub time_control: TimeControl,
+    // only clients with a certificate from tcp.tls.client_ca may log in as bots
+    pub require_certificate: bool,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            time_control: TimeControl {
+                initial_secs: 60,
+                increment_secs: 0,
+                days_per_move: None,
+            },
+            require_certificate: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // applies to tcp frames and websocket messages alike
+    pub max_frame_length: usize,
+    pub http: HttpConfig,
+    pub tcp: TcpConfig,
+    pub heartbeat: HeartbeatConfig,
+    pub game: GameConfig,
+    pub storage: StorageConfig,
+    pub shutdown: ShutdownConfig,
+    pub chat: ChatConfig,
+    pub uci: UciConfig,
+    pub analysis: AnalysisConfig,
+    pub bots: BotConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+            http: HttpConfig::default(),
+            tcp: TcpConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            game: GameConfig::default(),
+            storage: StorageConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            chat: ChatConfig::default(),
+            uci: UciConfig::default(),
+            analysis: AnalysisConfig::default(),
+            bots: BotConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "cannot read {}: {err}", path.display()),
+            ConfigError::Parse(path, err) => write!(f, "cannot parse {}: {err}", path.display()),
+            ConfigError::Invalid(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    // file, then environment, then flags, then a sanity check of the result
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::from_args(Args::parse())
+    }
+
+    pub fn from_args(args: Args) -> Result<Self, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => Self::read(path)?,
+            None => {
+                let path = PathBuf::from(DEFAULT_CONFIG_PATH);
+                match path.exists() {
+                    true => Self::read(&path)?,
+                    false => Config::default(),
+                }
+            }
+        };
+        config.apply(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn read(path: &PathBuf) -> Result<Self, ConfigError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|err| ConfigError::Read(path.clone(), err))?;
+        toml::from_str(&text).map_err(|err| ConfigError::Parse(path.clone(), err))
+    }
+
+    fn apply(&mut self, args: Args) {
+        if let Some(bind) = args.http_bind {
+            self.http.bind = bind;
+        }
+        if let Some(enabled) = args.http_enabled {
+            self.http.enabled = enabled;
+        }
+        if let Some(bind) = args.tcp_bind {
+            self.tcp.bind = bind;
+        }
+        if let Some(enabled) = args.tcp_enabled {
+            self.tcp.enabled = enabled;
+        }
+        if let Some(enabled) = args.tls_enabled {
+            self.tcp.tls.enabled = enabled;
+        }
+        if let Some(cert) = args.tls_cert {
+            self.tcp.tls.cert = cert;
+        }
+        if let Some(key) = args.tls_key {
+            self.tcp.tls.key = key;
+        }
+        if let Some(ca) = args.tls_client_ca {
+            self.tcp.tls.client_ca = Some(ca);
+        }
+        if let Some(require) = args.tls_require_client_cert {
+            self.tcp.tls.require_client_cert = require;
+        }
+        if let Some(length) = args.max_frame_length {
+            self.max_frame_length = length;
+        }
+        if let Some(workers) = args.workers {
+            self.http.workers = workers;
+        }
+        if let Some(interval) = args.heartbeat_interval {
+            self.heartbeat.interval_secs = interval;
+        }
+        if let Some(timeout) = args.heartbeat_timeout {
+            self.heartbeat.timeout_secs = timeout;
+        }
+        if let Some(time_control) = args.time_control {
+            self.game.time_control = time_control;
+        }
+        if let Some(dir) = args.data_dir {
+            self.storage.dir = dir;
+        }
+        if let Some(grace) = args.shutdown_grace {
+            self.shutdown.grace_secs = grace;
+        }
+        if let Some(command) = args.uci_command {
+            self.uci.command = command;
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: String| Err(ConfigError::Invalid(reason));
+        if !self.http.enabled && !self.tcp.enabled {
+            return invalid("at least one of http and tcp must be enabled".to_owned());
+        }
+        for (name, enabled, bind) in [
+            ("http.bind", self.http.enabled, &self.http.bind),
+            ("tcp.bind", self.tcp.enabled, &self.tcp.bind),
+        ] {
+            if enabled && bind.to_socket_addrs().is_err() {
+                return invalid(format!("{name} `{bind}` is not a host:port address"));
+            }
+        }
+        let tls = &self.tcp.tls;
+        if tls.enabled && (tls.cert.as_os_str().is_empty() || tls.key.as_os_str().is_empty()) {
+            return invalid("tcp.tls needs both cert and key when enabled".to_owned());
+        }
+        if tls.require_client_cert && tls.client_ca.is_none() {
+            return invalid("tcp.tls.require_client_cert needs a client_ca".to_owned());
+        }
+        if self.http.workers == 0 {
+            return invalid("http.workers must be at least 1".to_owned());
+        }
+        if self.heartbeat.interval_secs == 0 {
+            return invalid("heartbeat.interval_secs must be at least 1".to_owned());
+        }
+        if self.heartbeat.timeout_secs <= self.heartbeat.interval_secs {
+            return invalid(format!(
+                "heartbeat.timeout_secs ({}) must be longer than heartbeat.interval_secs ({})",
+                self.heartbeat.timeout_secs, self.heartbeat.interval_secs
+            ));
+        }
+        if self.game.time_control.initial_secs == 0 {
+            return invalid("game.time_control.initial_secs must be at least 1".to_owned());
+        }
+        if self.game.time_control.days_per_move.is_some()
+            || self.bots.time_control.days_per_move.is_some()
+        {
+            return invalid("days_per_move is picked per correspondence seek".to_owned());
+        }
+        if self.storage.dir.as_os_str().is_empty() {
+            return invalid("storage.dir must not be empty".to_owned());
+        }
+        if self.chat.max_length == 0 || self.chat.rate_messages == 0 {
+            return invalid("chat.max_length and chat.rate_messages must be at least 1".to_owned());
+        }
+        if self.chat.rate_window_secs == 0 {
+            return invalid("chat.rate_window_secs must be at least 1".to_owned());
+        }
+        if self.uci.evaluate_games && !self.uci.enabled() {
+            return invalid("uci.evaluate_games needs a uci.command".to_owned());
+        }
+        if self.uci.movetime_ms == 0 || self.uci.analysis_depth == 0 {
+            return invalid("uci.movetime_ms and uci.analysis_depth must be at least 1".to_owned());
+        }
+        let analysis = &self.analysis;
+        if analysis.depth == 0 || analysis.movetime_ms == 0 || analysis.workers == 0 {
+            return invalid(
+                "analysis.depth, analysis.movetime_ms and analysis.workers must be at least 1"
+                    .to_owned(),
+            );
+        }
+        if self.bots.time_control.initial_secs == 0 {
+            return invalid("bots.time_control.initial_secs must be at least 1".to_owned());
+        }
+        if self.bots.require_certificate && tls.client_ca.is_none() {
+            return invalid("bots.require_certificate needs a tcp.tls.client_ca".to_owned());
+        }
+        if self.max_frame_length == 0 {
+            return invalid("max_frame_length must be at least 1".to_owned());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn empty_file_gives_the_defaults() {
+        let config = parse("");
+        assert_eq!(config.tcp.bind, "127.0.0.1:9000");
+        assert_eq!(config.http.workers, 2);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let mut config = parse("[http]\nworkers = 8\n[heartbeat]\ninterval_secs = 3\n");
+        config.apply(Args {
+            workers: Some(4),
+            time_control: Some("3+2".parse().unwrap()),
+            ..Args::default()
+        });
+        assert_eq!(config.http.workers, 4);
+        assert_eq!(config.heartbeat.interval_secs, 3);
+        assert_eq!(config.game.time_control.initial_secs, 180);
+        assert_eq!(config.game.time_control.increment_secs, 2);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<Config>("[tcp]\nport = 9000\n").is_err());
+    }
+
+    #[test]
+    fn rejects_inconsistent_settings() {
+        let mut config = parse("[http]\nenabled = false\n[tcp]\nenabled = false\n");
+        assert!(config.validate().is_err());
+        config = parse("[heartbeat]\ninterval_secs = 10\ntimeout_secs = 5\n");
+        assert!(config.validate().is_err());
+        config = parse("[tcp]\nbind = \"nonsense\"\n");
+        assert!(config.validate().is_err());
+        config = parse("[http]\nworkers = 0\n");
+        assert!(config.validate().is_err());
+        config = parse("[tcp.tls]\nenabled = true\ncert = \"cert.pem\"\n");
+        assert!(config.validate().is_err());
+        config =
+            parse("[tcp.tls]\ncert = \"c.pem\"\nkey = \"k.pem\"\nrequire_client_cert = true\n");
+        assert!(config.validate().is_err());
+        config = parse("[chat]\nrate_messages = 0\n");
+        assert!(config.validate().is_err());
+        config = parse("[uci]\nevaluate_games = true\n");
+        assert!(config.validate().is_err());
+        config = parse("[analysis]\nworkers = 0\n");
+        assert!(config.validate().is_err());
+        config = parse("[bots]\nrequire_certificate = true\n");
+        assert!(config.validate().is_err());
+    }
+}
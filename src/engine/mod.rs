@@ -0,0 +1,124 @@
+use actix::{
+    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, Handler, WrapFuture,
+};
+
+use crate::{
+    chessclient::Message,
+    game::{replay, Game, GameSnapshot, MakeMove, MoveDetails, Pos, PushLegalMoves, SendSnapshot},
+    message::{Color, OutgoingMessage},
+};
+
+mod search;
+pub mod uci;
+
+pub use search::{search, Limits};
+
+// spend at most this share of the remaining clock on one move
+const CLOCK_SHARE: u32 = 20;
+
+// a computer opponent. it sits in a game exactly where a client would and
+// only talks to it through the same messages
+pub struct Engine {
+    limits: Limits,
+    game: Option<Addr<Game>>,
+    color: Option<Color>,
+    // plies played when the last search started, so a position is searched once
+    searched: Option<usize>,
+}
+
+impl Engine {
+    pub fn new(level: u8) -> Self {
+        Self {
+            limits: Limits::level(level),
+            game: None,
+            color: None,
+            searched: None,
+        }
+    }
+
+    fn think(&mut self, snapshot: &GameSnapshot, ctx: &mut Context<Self>) {
+        let (Some(game), Some(color)) = (self.game.clone(), self.color) else {
+            return;
+        };
+        let ply = snapshot.moves.len();
+        if snapshot.turn != color || self.searched == Some(ply) {
+            return;
+        }
+        self.searched = Some(ply);
+        let position = replay(snapshot.variant.start(), &snapshot.moves);
+        let clock = std::time::Duration::from_millis(snapshot.clocks[color.index()]);
+        let limits = Limits {
+            time: self.limits.time.min(clock / CLOCK_SHARE),
+            ..self.limits
+        };
+        // searching blocks, so it runs away from the actors' thread
+        let thinking = tokio::task::spawn_blocking(move || {
+            let result = search(&position, limits);
+            (position, result)
+        })
+        .into_actor(self)
+        .map(move |res, _act, ctx| {
+            let Ok((position, result)) = res else {
+                return;
+            };
+            let Some(mv) = result.best else {
+                return;
+            };
+            let Some(piece) = position.board[mv.from] else {
+                return;
+            };
+            log::debug!(
+                "engine plays {}->{} at depth {} ({} nodes, score {})",
+                mv.from,
+                mv.to,
+                result.depth,
+                result.nodes,
+                result.score
+            );
+            game.do_send(MakeMove {
+                move_details: MoveDetails {
+                    piece,
+                    from: Pos::from_index(mv.from),
+                    to: Pos::from_index(mv.to),
+                    promotion: mv.promotion,
+                },
+                player: ctx.address().recipient(),
+            });
+        });
+        ctx.spawn(thinking);
+    }
+}
+
+impl Actor for Engine {
+    type Context = Context<Self>;
+}
+
+impl Handler<Message> for Engine {
+    type Result = ();
+    fn handle(&mut self, msg: Message, ctx: &mut Self::Context) -> Self::Result {
+        match msg.inner {
+            OutgoingMessage::GameStarted(color) => {
+                self.color = Some(color);
+                self.game = msg.game;
+                // legal moves arrive at every turn change, which is our cue to look
+                if let Some(game) = &self.game {
+                    game.do_send(PushLegalMoves {
+                        player: ctx.address().recipient(),
+                        enabled: true,
+                    });
+                }
+            }
+            OutgoingMessage::LegalMoves(_) => {
+                if let Some(game) = &self.game {
+                    game.do_send(SendSnapshot(ctx.address().recipient()));
+                }
+            }
+            OutgoingMessage::Snapshot(snapshot) => self.think(&snapshot, ctx),
+            OutgoingMessage::WinGame(_)
+            | OutgoingMessage::LoseGame(_)
+            | OutgoingMessage::DrawGame(_)
+            | OutgoingMessage::GameAdjourned => ctx.stop(),
+            _ => {}
+        }
+    }
+}
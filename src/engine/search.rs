@@ -0,0 +1,343 @@
+use std::time::{Duration, Instant};
+
+use crate::game::{Move, PieceVariant, Position};
+
+const MATE: i32 = 100_000;
+// captures searched past the nominal depth so the last move is never a blunder into a recapture
+const QUIESCENCE_DEPTH: u32 = 4;
+// how often the clock and node budget are looked at
+const CHECK_EVERY: u64 = 1_024;
+
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = 8;
+
+// whichever limit is hit first ends the search
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub depth: u32,
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+impl Limits {
+    // strength levels from 1 to 8, anything outside that is clamped
+    pub fn level(level: u8) -> Self {
+        let (depth, nodes, millis) = match level.clamp(MIN_LEVEL, MAX_LEVEL) {
+            1 => (1, 500, 50),
+            2 => (2, 2_000, 100),
+            3 => (2, 10_000, 250),
+            4 => (3, 40_000, 500),
+            5 => (4, 150_000, 1_000),
+            6 => (5, 500_000, 2_000),
+            7 => (6, 2_000_000, 4_000),
+            _ => (64, u64::MAX, 8_000),
+        };
+        Self {
+            depth,
+            nodes,
+            time: Duration::from_millis(millis),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchResult {
+    // None only when the side to move has no legal move
+    pub best: Option<Move>,
+    // centipawns from the point of view of the side to move
+    pub score: i32,
+    // deepest iteration that finished
+    pub depth: u32,
+    pub nodes: u64,
+}
+
+fn value(variant: PieceVariant) -> i32 {
+    match variant {
+        PieceVariant::Pawn => 100,
+        PieceVariant::Knight => 320,
+        PieceVariant::Bishop => 330,
+        PieceVariant::Rook => 500,
+        PieceVariant::Queen => 900,
+        PieceVariant::King => 0,
+    }
+}
+
+// small positional nudges on top of material: pawns want to advance,
+// minor pieces want the centre
+fn placement(variant: PieceVariant, side: usize, square: usize) -> i32 {
+    let (file, rank) = ((square % 8) as i32, (square / 8) as i32);
+    let advanced = if side == 0 { rank } else { 7 - rank };
+    let centre = 6 - ((2 * file - 7).abs() + (2 * rank - 7).abs()) / 2;
+    match variant {
+        PieceVariant::Pawn => advanced * 5 + if (2..=5).contains(&file) { centre } else { 0 },
+        PieceVariant::Knight => centre * 5,
+        PieceVariant::Bishop => centre * 3,
+        PieceVariant::Queen => centre,
+        PieceVariant::Rook | PieceVariant::King => 0,
+    }
+}
+
+// static evaluation from the point of view of the side to move
+pub fn evaluate(position: &Position) -> i32 {
+    let mut score = 0;
+    for (square, piece) in position.board.iter().enumerate() {
+        if let Some(piece) = piece {
+            let worth = value(piece.variant()) + placement(piece.variant(), piece.side(), square);
+            score += if piece.side() == position.turn {
+                worth
+            } else {
+                -worth
+            };
+        }
+    }
+    score
+}
+
+struct Searcher {
+    limits: Limits,
+    started: Instant,
+    nodes: u64,
+    next_check: u64,
+    aborted: bool,
+}
+
+impl Searcher {
+    fn out_of_budget(&mut self) -> bool {
+        if !self.aborted && self.nodes >= self.next_check {
+            self.next_check = self.nodes + CHECK_EVERY;
+            self.aborted =
+                self.nodes >= self.limits.nodes || self.started.elapsed() >= self.limits.time;
+        }
+        self.aborted
+    }
+
+    // captures of valuable pieces by cheap ones first
+    fn ordered(position: &Position, mut moves: Vec<Move>, first: Option<Move>) -> Vec<Move> {
+        moves.sort_by_key(|mv| {
+            if Some(*mv) == first {
+                return i32::MIN;
+            }
+            let victim = position.board[mv.to].map_or(0, |p| value(p.variant()));
+            let attacker = position.board[mv.from].map_or(0, |p| value(p.variant()));
+            let promotion = mv.promotion.map_or(0, value);
+            -(victim * 10 - attacker / 10 + promotion)
+        });
+        moves
+    }
+
+    fn quiesce(&mut self, position: &Position, mut alpha: i32, beta: i32, depth: u32) -> i32 {
+        self.nodes += 1;
+        let stand_pat = evaluate(position);
+        if depth == 0 || stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+        let captures = position
+            .pseudo_legal_moves()
+            .into_iter()
+            .filter(|mv| position.board[mv.to].is_some() && position.is_legal(*mv))
+            .collect();
+        for mv in Self::ordered(position, captures, None) {
+            if self.out_of_budget() {
+                break;
+            }
+            let mut after = position.clone();
+            after.play(mv);
+            let score = -self.quiesce(&after, -beta, -alpha, depth - 1);
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    fn negamax(
+        &mut self,
+        position: &Position,
+        depth: u32,
+        ply: i32,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        self.nodes += 1;
+        let moves = position.legal_moves();
+        if moves.is_empty() {
+            return match position.in_check() {
+                true => -MATE + ply,
+                false => 0,
+            };
+        }
+        if position.halfmove_clock >= 100 {
+            return 0;
+        }
+        if depth == 0 {
+            return self.quiesce(position, alpha, beta, QUIESCENCE_DEPTH);
+        }
+        let mut best = -MATE - 1;
+        for mv in Self::ordered(position, moves, None) {
+            if self.out_of_budget() {
+                break;
+            }
+            let mut after = position.clone();
+            after.play(mv);
+            let score = -self.negamax(&after, depth - 1, ply + 1, -beta, -alpha);
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+// iterative deepening, so running out of budget still leaves the best move
+// of the last finished iteration
+pub fn search(position: &Position, limits: Limits) -> SearchResult {
+    let mut searcher = Searcher {
+        limits,
+        started: Instant::now(),
+        nodes: 0,
+        next_check: 0,
+        aborted: false,
+    };
+    let moves = position.legal_moves();
+    let mut result = SearchResult {
+        best: moves.first().copied(),
+        score: evaluate(position),
+        depth: 0,
+        nodes: 0,
+    };
+    if moves.len() < 2 {
+        return result;
+    }
+    for depth in 1..=limits.depth.max(1) {
+        let mut alpha = -MATE - 1;
+        let mut best = None;
+        for mv in Searcher::ordered(position, moves.clone(), result.best) {
+            let mut after = position.clone();
+            after.play(mv);
+            let score = -searcher.negamax(&after, depth - 1, 1, -MATE - 1, -alpha);
+            if searcher.aborted {
+                break;
+            }
+            if score > alpha {
+                alpha = score;
+                best = Some(mv);
+            }
+        }
+        if searcher.aborted {
+            // the previous best move is searched first, so anything a cut short
+            // iteration settled on already beat it at the greater depth
+            if best.is_some() {
+                result.best = best;
+                result.score = alpha;
+            }
+            break;
+        }
+        result.best = best;
+        result.score = alpha;
+        result.depth = depth;
+        // a forced mate will not get any better by looking deeper
+        if alpha.abs() >= MATE - 64 {
+            break;
+        }
+    }
+    result.nodes = searcher.nodes;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{CastlingRights, ChessPiece};
+
+    fn sq(name: &str) -> usize {
+        let bytes = name.as_bytes();
+        (bytes[1] - b'1') as usize * 8 + (bytes[0] - b'a') as usize
+    }
+
+    fn empty(turn: usize) -> Position {
+        Position {
+            board: [None; 64],
+            turn,
+            castling: CastlingRights {
+                white_kingside: false,
+                white_queenside: false,
+                black_kingside: false,
+                black_queenside: false,
+            },
+            ..Position::default()
+        }
+    }
+
+    fn unlimited(depth: u32) -> Limits {
+        Limits {
+            depth,
+            nodes: u64::MAX,
+            time: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn start_position_is_balanced() {
+        assert_eq!(evaluate(&Position::default()), 0);
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        // back rank mate with the rook
+        let mut position = empty(0);
+        position.board[sq("g1")] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[sq("a1")] = Some(ChessPiece::White(PieceVariant::Rook));
+        position.board[sq("g8")] = Some(ChessPiece::Black(PieceVariant::King));
+        for square in ["f7", "g7", "h7"] {
+            position.board[sq(square)] = Some(ChessPiece::Black(PieceVariant::Pawn));
+        }
+        let result = search(&position, unlimited(3));
+        assert_eq!(
+            result.best,
+            Some(Move {
+                from: sq("a1"),
+                to: sq("a8"),
+                promotion: None
+            })
+        );
+        assert!(result.score >= MATE - 64);
+    }
+
+    #[test]
+    fn takes_a_hanging_queen() {
+        let mut position = empty(0);
+        position.board[sq("e1")] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[sq("c3")] = Some(ChessPiece::White(PieceVariant::Knight));
+        position.board[sq("e8")] = Some(ChessPiece::Black(PieceVariant::King));
+        position.board[sq("d5")] = Some(ChessPiece::Black(PieceVariant::Queen));
+        let result = search(&position, unlimited(2));
+        assert_eq!(result.best.map(|mv| mv.to), Some(sq("d5")));
+    }
+
+    #[test]
+    fn stops_at_the_node_budget() {
+        let limits = Limits {
+            nodes: 2_000,
+            ..unlimited(64)
+        };
+        let result = search(&Position::default(), limits);
+        assert!(result.best.is_some());
+        assert!(result.nodes < 2_000 + CHECK_EVERY);
+    }
+
+    #[test]
+    fn levels_get_stronger() {
+        for level in MIN_LEVEL..MAX_LEVEL {
+            let (weak, strong) = (Limits::level(level), Limits::level(level + 1));
+            assert!(weak.depth <= strong.depth);
+            assert!(weak.nodes < strong.nodes);
+            assert!(weak.time < strong.time);
+        }
+        assert_eq!(Limits::level(0), Limits::level(MIN_LEVEL));
+        assert_eq!(Limits::level(200), Limits::level(MAX_LEVEL));
+    }
+}
@@ -0,0 +1,554 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+    process::Stdio,
+    time::Duration,
+};
+
+use actix::{
+    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, Handler, WrapFuture,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    time::timeout,
+};
+
+use crate::{
+    chessclient::Message,
+    config::UciConfig,
+    game::{
+        fen, san, ForfeitGame, Game, GameRecord, GameSnapshot, MakeMove, Move, MoveDetails,
+        MoveRecord, PieceVariant, Pos, Position, PushLegalMoves, SendSnapshot,
+    },
+    message::{Color, OutgoingMessage},
+};
+
+// how long the engine gets to answer anything that is not a search
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+// slack on top of the move time before an engine counts as hung
+const SEARCH_SLACK: Duration = Duration::from_secs(10);
+// depth limited searches have no natural bound, so they get a generous one
+const ANALYSIS_TIMEOUT: Duration = Duration::from_secs(120);
+// spend at most this share of the remaining clock on one move
+const CLOCK_SHARE: u32 = 20;
+
+#[derive(Debug)]
+pub enum UciError {
+    Spawn(io::Error),
+    Io(io::Error),
+    // the engine exited or closed its output
+    Closed,
+    Timeout(&'static str),
+    // the engine answered with something that is not a legal move
+    IllegalMove(String),
+}
+
+impl Display for UciError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UciError::Spawn(err) => write!(f, "cannot start engine: {err}"),
+            UciError::Io(err) => write!(f, "engine i/o failed: {err}"),
+            UciError::Closed => write!(f, "engine exited"),
+            UciError::Timeout(waiting_for) => {
+                write!(f, "engine did not send {waiting_for} in time")
+            }
+            UciError::IllegalMove(mv) => write!(f, "engine played illegal move `{mv}`"),
+        }
+    }
+}
+
+impl std::error::Error for UciError {}
+
+// an evaluation as engines report it. stored from white's point of view
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Score {
+    // centipawns
+    Cp(i32),
+    // moves until mate, negative when the other side mates
+    Mate(i32),
+}
+
+impl Score {
+    fn flipped(self) -> Self {
+        match self {
+            Score::Cp(cp) => Score::Cp(-cp),
+            Score::Mate(moves) => Score::Mate(-moves),
+        }
+    }
+
+    fn for_white(self, turn: usize) -> Self {
+        match turn {
+            0 => self,
+            _ => self.flipped(),
+        }
+    }
+}
+
+// long algebraic notation as uci uses it, e.g. e2e4 or e7e8q
+pub fn uci_move(mv: Move) -> String {
+    let square = |sq: usize| format!("{}{}", (b'a' + (sq % 8) as u8) as char, sq / 8 + 1);
+    let promotion = match mv.promotion {
+        Some(PieceVariant::Queen) => "q",
+        Some(PieceVariant::Rook) => "r",
+        Some(PieceVariant::Bishop) => "b",
+        Some(PieceVariant::Knight) => "n",
+        _ => "",
+    };
+    format!("{}{}{promotion}", square(mv.from), square(mv.to))
+}
+
+// the legal move in the position that the text names, if any
+pub fn parse_move(position: &Position, text: &str) -> Option<Move> {
+    position
+        .legal_moves()
+        .into_iter()
+        .find(|mv| uci_move(*mv) == text)
+}
+
+// the score of an `info` line, from the point of view of the side to move
+fn parse_score(line: &str) -> Option<Score> {
+    let mut words = line.split_whitespace();
+    while let Some(word) = words.next() {
+        if word == "score" {
+            let kind = words.next()?;
+            let value = words.next()?.parse().ok()?;
+            return match kind {
+                "cp" => Some(Score::Cp(value)),
+                "mate" => Some(Score::Mate(value)),
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum GoLimit {
+    MoveTime(Duration),
+    Depth(u32),
+}
+
+// a running engine process spoken to over stdin and stdout
+pub struct UciEngine {
+    // dropping the child kills the process
+    _child: Child,
+    stdin: ChildStdin,
+    lines: Lines<BufReader<ChildStdout>>,
+    pub name: String,
+}
+
+impl UciEngine {
+    pub async fn start(config: &UciConfig) -> Result<Self, UciError> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(UciError::Spawn)?;
+        let stdin = child.stdin.take().ok_or(UciError::Closed)?;
+        let stdout = child.stdout.take().ok_or(UciError::Closed)?;
+        let mut engine = Self {
+            _child: child,
+            stdin,
+            lines: BufReader::new(stdout).lines(),
+            name: config.command.display().to_string(),
+        };
+        engine.send("uci").await?;
+        loop {
+            let line = engine.read_line(REPLY_TIMEOUT, "uciok").await?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_owned();
+            }
+            if line.trim() == "uciok" {
+                break;
+            }
+        }
+        for (name, value) in config.options.iter() {
+            engine
+                .send(&format!("setoption name {name} value {value}"))
+                .await?;
+        }
+        engine.ready().await?;
+        Ok(engine)
+    }
+
+    async fn send(&mut self, line: &str) -> Result<(), UciError> {
+        log::trace!("uci > {line}");
+        self.stdin
+            .write_all(format!("{line}\n").as_bytes())
+            .await
+            .map_err(UciError::Io)?;
+        self.stdin.flush().await.map_err(UciError::Io)
+    }
+
+    async fn read_line(
+        &mut self,
+        wait: Duration,
+        waiting_for: &'static str,
+    ) -> Result<String, UciError> {
+        match timeout(wait, self.lines.next_line()).await {
+            Ok(Ok(Some(line))) => {
+                log::trace!("uci < {line}");
+                Ok(line)
+            }
+            Ok(Ok(None)) => Err(UciError::Closed),
+            Ok(Err(err)) => Err(UciError::Io(err)),
+            Err(_) => Err(UciError::Timeout(waiting_for)),
+        }
+    }
+
+    async fn ready(&mut self) -> Result<(), UciError> {
+        self.send("isready").await?;
+        while self.read_line(REPLY_TIMEOUT, "readyok").await?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    // searches the position after the moves from the start. the move is None
+    // when the side to move has none, the score is from its point of view
+    pub async fn go(
+        &mut self,
+        start: &Position,
+        moves: &[Move],
+        limit: GoLimit,
+    ) -> Result<(Option<Move>, Option<Score>), UciError> {
+        let start_fen = fen(start);
+        let mut position = match start_fen == fen(&Position::default()) {
+            true => String::from("position startpos"),
+            false => format!("position fen {start_fen}"),
+        };
+        if !moves.is_empty() {
+            position.push_str(" moves");
+            for mv in moves {
+                position.push(' ');
+                position.push_str(&uci_move(*mv));
+            }
+        }
+        self.send(&position).await?;
+        let wait = match limit {
+            GoLimit::MoveTime(time) => {
+                self.send(&format!("go movetime {}", time.as_millis()))
+                    .await?;
+                time + SEARCH_SLACK
+            }
+            GoLimit::Depth(depth) => {
+                self.send(&format!("go depth {depth}")).await?;
+                ANALYSIS_TIMEOUT
+            }
+        };
+        let mut score = None;
+        loop {
+            let line = self.read_line(wait, "bestmove").await?;
+            if line.starts_with("info") {
+                score = parse_score(&line).or(score);
+            } else if let Some(rest) = line.strip_prefix("bestmove") {
+                let text = rest.split_whitespace().next().unwrap_or("(none)");
+                if text == "(none)" || text == "0000" {
+                    return Ok((None, score));
+                }
+                return match parse_move(&replay_moves(start, moves), text) {
+                    Some(mv) => Ok((Some(mv), score)),
+                    None => Err(UciError::IllegalMove(text.to_owned())),
+                };
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MoveEvaluation {
+    pub ply: usize,
+    pub uci: String,
+    pub san: String,
+    // of the position after the move, from white's point of view.
+    // None once the game is over on the board
+    pub score: Option<Score>,
+}
+
+// engine evaluations of every move of a finished game
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Evaluations {
+    pub id: String,
+    pub engine: String,
+    pub depth: u32,
+    pub moves: Vec<MoveEvaluation>,
+}
+
+pub async fn evaluate_game(
+    config: &UciConfig,
+    record: &GameRecord,
+) -> Result<Evaluations, UciError> {
+    let mut engine = UciEngine::start(config).await?;
+    let start = record.variant.start();
+    // chess960 castling is written king takes rook, which engines only read in this mode
+    if start.chess960_castling() {
+        engine
+            .send("setoption name UCI_Chess960 value true")
+            .await?;
+    }
+    let mut position = start.clone();
+    let mut played = vec![];
+    let mut evaluations = vec![];
+    for (ply, record) in record.moves.iter().enumerate() {
+        let mv = record.as_move();
+        let name = san(&position, mv);
+        position.play(mv);
+        played.push(mv);
+        let score = match position.legal_moves().is_empty() {
+            true => None,
+            false => {
+                let (_, score) = engine
+                    .go(&start, &played, GoLimit::Depth(config.analysis_depth))
+                    .await?;
+                score.map(|score| score.for_white(position.turn))
+            }
+        };
+        evaluations.push(MoveEvaluation {
+            ply: ply + 1,
+            uci: uci_move(mv),
+            san: name,
+            score,
+        });
+    }
+    let _ = engine.send("quit").await;
+    Ok(Evaluations {
+        id: record.id.clone(),
+        engine: engine.name,
+        depth: config.analysis_depth,
+        moves: evaluations,
+    })
+}
+
+// a local engine binary seated in a game. like the built in engine it only
+// ever talks to the game through the messages a client would get
+pub struct UciPlayer {
+    // taken out for the length of a search and handed back afterwards
+    engine: Option<UciEngine>,
+    movetime: Duration,
+    game: Option<Addr<Game>>,
+    color: Option<Color>,
+    searched: Option<usize>,
+}
+
+impl UciPlayer {
+    pub fn new(engine: UciEngine, movetime: Duration) -> Self {
+        Self {
+            engine: Some(engine),
+            movetime,
+            game: None,
+            color: None,
+            searched: None,
+        }
+    }
+
+    fn think(&mut self, snapshot: &GameSnapshot, ctx: &mut Context<Self>) {
+        let (Some(game), Some(color)) = (self.game.clone(), self.color) else {
+            return;
+        };
+        let ply = snapshot.moves.len();
+        if snapshot.turn != color || self.searched == Some(ply) {
+            return;
+        }
+        let Some(mut engine) = self.engine.take() else {
+            return;
+        };
+        self.searched = Some(ply);
+        let start = snapshot.variant.start();
+        let moves: Vec<Move> = snapshot.moves.iter().map(MoveRecord::as_move).collect();
+        let clock = Duration::from_millis(snapshot.clocks[color.index()]);
+        let limit = GoLimit::MoveTime(self.movetime.min(clock / CLOCK_SHARE));
+        let thinking = async move {
+            let result = engine.go(&start, &moves, limit).await;
+            (engine, start, moves, result)
+        }
+        .into_actor(self)
+        .map(move |(engine, start, moves, result), act, ctx| {
+            act.engine = Some(engine);
+            let player = ctx.address().recipient();
+            match result {
+                Ok((Some(mv), _)) => {
+                    let position = replay_moves(&start, &moves);
+                    let Some(piece) = position.board[mv.from] else {
+                        return;
+                    };
+                    game.do_send(MakeMove {
+                        move_details: MoveDetails {
+                            piece,
+                            from: Pos::from_index(mv.from),
+                            to: Pos::from_index(mv.to),
+                            promotion: mv.promotion,
+                        },
+                        player,
+                    });
+                }
+                // the game sees the end of the game itself
+                Ok((None, _)) => {}
+                Err(err) => {
+                    log::error!("{err}, resigning");
+                    game.do_send(ForfeitGame(player));
+                }
+            }
+        });
+        ctx.spawn(thinking);
+    }
+}
+
+fn replay_moves(start: &Position, moves: &[Move]) -> Position {
+    let mut position = start.clone();
+    for mv in moves {
+        position.play(*mv);
+    }
+    position
+}
+
+impl Actor for UciPlayer {
+    type Context = Context<Self>;
+}
+
+impl Handler<Message> for UciPlayer {
+    type Result = ();
+    fn handle(&mut self, msg: Message, ctx: &mut Self::Context) -> Self::Result {
+        match msg.inner {
+            OutgoingMessage::GameStarted(color) => {
+                self.color = Some(color);
+                self.game = msg.game;
+                if let Some(game) = &self.game {
+                    game.do_send(PushLegalMoves {
+                        player: ctx.address().recipient(),
+                        enabled: true,
+                    });
+                }
+            }
+            OutgoingMessage::LegalMoves(_) => {
+                if let Some(game) = &self.game {
+                    game.do_send(SendSnapshot(ctx.address().recipient()));
+                }
+            }
+            OutgoingMessage::Snapshot(snapshot) => self.think(&snapshot, ctx),
+            OutgoingMessage::WinGame(_)
+            | OutgoingMessage::LoseGame(_)
+            | OutgoingMessage::DrawGame(_)
+            | OutgoingMessage::GameAdjourned => ctx.stop(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::game::{record_from_uci, GameVariant};
+    use std::{collections::BTreeMap, os::unix::fs::PermissionsExt, path::PathBuf};
+
+    // answers every search with the king's pawn opening or its mirror, and a
+    // fixed score from the side to move's point of view
+    const FAKE_ENGINE: &str = r#"#!/bin/sh
+while read -r line; do
+  case "$line" in
+    uci) echo "id name Fake Engine 1.0"; echo "option name Hash type spin default 16"; echo "uciok" ;;
+    isready) echo "readyok" ;;
+    position*) last="$line" ;;
+    go*)
+      echo "info depth 1 score cp 10 pv e2e4"
+      echo "info depth 2 score cp 30 pv e2e4"
+      case "$last" in
+        *e2e4*e7e5*) echo "bestmove (none)" ;;
+        *e2e4*) echo "bestmove e7e5" ;;
+        *) echo "bestmove e2e4" ;;
+      esac ;;
+    quit) exit 0 ;;
+  esac
+done
+"#;
+
+    fn fake_engine(name: &str) -> UciConfig {
+        let path = std::env::temp_dir().join(format!("fake-uci-{name}-{}", std::process::id()));
+        std::fs::write(&path, FAKE_ENGINE).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        UciConfig {
+            command: path,
+            options: BTreeMap::from([("Hash".to_owned(), "32".to_owned())]),
+            ..UciConfig::default()
+        }
+    }
+
+    fn sq(name: &str) -> usize {
+        let bytes = name.as_bytes();
+        (bytes[1] - b'1') as usize * 8 + (bytes[0] - b'a') as usize
+    }
+
+    #[test]
+    fn parses_scores_and_moves() {
+        assert_eq!(
+            parse_score("info depth 12 seldepth 15 score cp -34 nodes 1000 pv d7d5"),
+            Some(Score::Cp(-34))
+        );
+        assert_eq!(
+            parse_score("info score mate 3 pv h5f7"),
+            Some(Score::Mate(3))
+        );
+        assert_eq!(parse_score("info string hello"), None);
+        let start = Position::default();
+        assert_eq!(parse_move(&start, "g1f3").map(|mv| mv.from), Some(sq("g1")));
+        assert_eq!(parse_move(&start, "e2e5"), None);
+        let promotion = Move {
+            from: sq("b7"),
+            to: sq("b8"),
+            promotion: Some(PieceVariant::Knight),
+        };
+        assert_eq!(uci_move(promotion), "b7b8n");
+    }
+
+    #[tokio::test]
+    async fn plays_the_engines_best_move() {
+        let config = fake_engine("play");
+        let mut engine = UciEngine::start(&config).await.unwrap();
+        assert_eq!(engine.name, "Fake Engine 1.0");
+        let (mv, score) = engine
+            .go(
+                &Position::default(),
+                &[],
+                GoLimit::MoveTime(Duration::from_millis(10)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(mv.map(uci_move).as_deref(), Some("e2e4"));
+        assert_eq!(score, Some(Score::Cp(30)));
+        std::fs::remove_file(&config.command).unwrap();
+    }
+
+    #[tokio::test]
+    async fn evaluates_every_move_from_whites_side() {
+        let config = fake_engine("evaluate");
+        let record = record_from_uci(GameVariant::Standard, "e2e4 e7e5");
+        let evaluations = evaluate_game(&config, &record).await.unwrap();
+        assert_eq!(evaluations.engine, "Fake Engine 1.0");
+        let moves: Vec<_> = evaluations
+            .moves
+            .iter()
+            .map(|m| (m.san.as_str(), m.score))
+            .collect();
+        // black is to move after 1. e4, so the engine's +30 is good for black
+        assert_eq!(
+            moves,
+            [("e4", Some(Score::Cp(-30))), ("e5", Some(Score::Cp(30)))]
+        );
+        std::fs::remove_file(&config.command).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_binaries_fail_to_start() {
+        let config = UciConfig {
+            command: PathBuf::from("/nonexistent/engine"),
+            ..UciConfig::default()
+        };
+        assert!(matches!(
+            UciEngine::start(&config).await,
+            Err(UciError::Spawn(_))
+        ));
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::uci::uci_move,
+    game::{san, GameRecord, GameResult, GameVariant, Move, Position},
+    profile::Category,
+};
+
+// how far into its games the explorer follows them
+pub const OPENING_PLIES: usize = 30;
+
+// which games to count. ratings are the average of both players', and
+// games without ratings only count when no rating bound is given
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+pub struct ExplorerFilter {
+    pub min_rating: Option<i32>,
+    pub max_rating: Option<i32>,
+    pub category: Option<Category>,
+}
+
+impl ExplorerFilter {
+    fn admits(&self, game: &IndexedGame) -> bool {
+        if self
+            .category
+            .is_some_and(|category| category != game.category)
+        {
+            return false;
+        }
+        if self.min_rating.is_none() && self.max_rating.is_none() {
+            return true;
+        }
+        game.rating.is_some_and(|rating| {
+            self.min_rating.is_none_or(|min| rating >= min)
+                && self.max_rating.is_none_or(|max| rating <= max)
+        })
+    }
+}
+
+struct IndexedGame {
+    result: GameResult,
+    category: Category,
+    rating: Option<i32>,
+}
+
+// how the games that went on with one move turned out
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct MoveStats {
+    pub uci: String,
+    pub san: String,
+    pub games: u32,
+    pub white_wins: u32,
+    pub draws: u32,
+    pub black_wins: u32,
+    pub white_percent: f32,
+    pub draw_percent: f32,
+    pub black_percent: f32,
+}
+
+impl MoveStats {
+    fn new(position: &Position, mv: Move) -> Self {
+        Self {
+            uci: uci_move(mv),
+            san: san(position, mv),
+            games: 0,
+            white_wins: 0,
+            draws: 0,
+            black_wins: 0,
+            white_percent: 0.0,
+            draw_percent: 0.0,
+            black_percent: 0.0,
+        }
+    }
+
+    fn count(&mut self, result: GameResult) {
+        self.games += 1;
+        match result {
+            GameResult::WhiteWins => self.white_wins += 1,
+            GameResult::Draw => self.draws += 1,
+            GameResult::BlackWins => self.black_wins += 1,
+        }
+    }
+
+    // to one decimal place
+    fn settle(&mut self) {
+        let percent = |n: u32| (n as f32 * 1000.0 / self.games as f32).round() / 10.0;
+        self.white_percent = percent(self.white_wins);
+        self.draw_percent = percent(self.draws);
+        self.black_percent = percent(self.black_wins);
+    }
+}
+
+// the openings of every finished game of standard chess, by position. games
+// are added as they finish, and a lookup only reads the moves seen from the
+// one position it is asked about
+#[derive(Default)]
+pub struct Explorer {
+    games: Vec<IndexedGame>,
+    // position key to each move played from it and the game it was played in
+    positions: HashMap<u64, Vec<(Move, u32)>>,
+}
+
+impl Explorer {
+    pub fn add(&mut self, record: &GameRecord) {
+        let Some(result) = record.result else {
+            return;
+        };
+        if record.variant != GameVariant::Standard || record.moves.is_empty() {
+            return;
+        }
+        let game = self.games.len() as u32;
+        self.games.push(IndexedGame {
+            result,
+            category: Category::of(&record.time_control),
+            rating: record.ratings.map(|[white, black]| (white + black) / 2),
+        });
+        let mut position = Position::default();
+        // a position the game came back to is only counted the first time
+        let mut seen = HashSet::new();
+        for record in record.moves.iter().take(OPENING_PLIES) {
+            let mv = record.as_move();
+            if seen.insert(position.key()) {
+                self.positions
+                    .entry(position.key())
+                    .or_default()
+                    .push((mv, game));
+            }
+            position.play(mv);
+        }
+    }
+
+    // the moves played from a position, most played first
+    pub fn explore(&self, position: &Position, filter: &ExplorerFilter) -> Vec<MoveStats> {
+        let Some(played) = self.positions.get(&position.key()) else {
+            return vec![];
+        };
+        let mut moves: Vec<(Move, MoveStats)> = vec![];
+        for &(mv, game) in played {
+            let game = &self.games[game as usize];
+            if !filter.admits(game) {
+                continue;
+            }
+            let index = match moves.iter().position(|(other, _)| *other == mv) {
+                Some(index) => index,
+                None => {
+                    moves.push((mv, MoveStats::new(position, mv)));
+                    moves.len() - 1
+                }
+            };
+            moves[index].1.count(game.result);
+        }
+        let mut moves: Vec<MoveStats> = moves.into_iter().map(|(_, stats)| stats).collect();
+        for stats in moves.iter_mut() {
+            stats.settle();
+        }
+        moves.sort_by(|a, b| b.games.cmp(&a.games).then_with(|| a.san.cmp(&b.san)));
+        moves
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{parse_fen, record_from_uci, TimeControl};
+
+    fn game(
+        moves: &str,
+        result: GameResult,
+        initial_secs: u64,
+        ratings: Option<[i32; 2]>,
+    ) -> GameRecord {
+        GameRecord {
+            time_control: TimeControl {
+                initial_secs,
+                increment_secs: 0,
+                days_per_move: None,
+            },
+            result: Some(result),
+            ratings,
+            ..record_from_uci(GameVariant::Standard, moves)
+        }
+    }
+
+    fn explorer() -> Explorer {
+        let mut explorer = Explorer::default();
+        for record in [
+            game(
+                "e2e4 e7e5 g1f3",
+                GameResult::WhiteWins,
+                60,
+                Some([1500, 1500]),
+            ),
+            game("e2e4 c7c5", GameResult::BlackWins, 300, Some([1900, 2100])),
+            game("e2e4 e7e5 f1c4", GameResult::Draw, 300, None),
+            game("d2d4 d7d5", GameResult::Draw, 300, Some([1300, 1500])),
+        ] {
+            explorer.add(&record);
+        }
+        explorer
+    }
+
+    #[test]
+    fn counts_moves_and_results_from_a_position() {
+        let moves = explorer().explore(&Position::default(), &ExplorerFilter::default());
+        let summary: Vec<_> = moves.iter().map(|m| (m.san.as_str(), m.games)).collect();
+        assert_eq!(summary, [("e4", 3), ("d4", 1)]);
+        let e4 = &moves[0];
+        assert_eq!((e4.white_wins, e4.draws, e4.black_wins), (1, 1, 1));
+        assert_eq!(e4.white_percent, 33.3);
+        assert_eq!(e4.uci, "e2e4");
+        let after = parse_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2");
+        let moves = explorer().explore(&after.unwrap(), &ExplorerFilter::default());
+        let summary: Vec<_> = moves.iter().map(|m| m.san.as_str()).collect();
+        assert_eq!(summary, ["Bc4", "Nf3"]);
+    }
+
+    #[test]
+    fn filters_by_rating_and_time_control() {
+        let explorer = explorer();
+        let start = Position::default();
+        let blitz = ExplorerFilter {
+            category: Some(Category::Blitz),
+            ..ExplorerFilter::default()
+        };
+        let moves = explorer.explore(&start, &blitz);
+        assert_eq!(moves.iter().map(|m| m.games).sum::<u32>(), 3);
+        // only rated games count once a rating is asked for
+        let strong = ExplorerFilter {
+            min_rating: Some(1500),
+            ..ExplorerFilter::default()
+        };
+        let moves = explorer.explore(&start, &strong);
+        assert_eq!(moves.len(), 1);
+        assert_eq!((moves[0].games, moves[0].white_wins), (2, 1));
+        let weak = ExplorerFilter {
+            max_rating: Some(1500),
+            category: Some(Category::Bullet),
+            ..ExplorerFilter::default()
+        };
+        assert_eq!(explorer.explore(&start, &weak)[0].games, 1);
+    }
+
+    #[test]
+    fn transpositions_meet_in_one_position() {
+        let mut explorer = Explorer::default();
+        explorer.add(&game(
+            "g1f3 g8f6 b1c3 b8c6 e2e4",
+            GameResult::WhiteWins,
+            60,
+            None,
+        ));
+        explorer.add(&game(
+            "b1c3 b8c6 g1f3 g8f6 d2d4",
+            GameResult::Draw,
+            60,
+            None,
+        ));
+        let position =
+            parse_fen("r1bqkb1r/pppppppp/2n2n2/8/8/2N2N2/PPPPPPPP/R1BQKB1R w KQkq - 4 3");
+        let moves = explorer.explore(&position.unwrap(), &ExplorerFilter::default());
+        assert_eq!(moves.len(), 2);
+        // e4 leaves no black pawn beside it, so the fen may leave out e3
+        let mut explorer = Explorer::default();
+        explorer.add(&game("e2e4 e7e5", GameResult::Draw, 60, None));
+        let position = parse_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+        assert_eq!(
+            explorer
+                .explore(&position.unwrap(), &ExplorerFilter::default())
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,1090 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix::{
+    Actor, ActorContext, Addr, AsyncContext, Context, Handler, Message as ActixMessage,
+    MessageResult, Recipient, SpawnHandle,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat::{ChatError, ChatLine, ChatScope},
+    chessclient::Message,
+    message::{ClientResult, Color, OutgoingMessage},
+    metrics::METRICS,
+    server::{GameOver, SaveCorrespondence, Server},
+    tournament::TournamentError,
+};
+
+mod notation;
+mod position;
+mod replay;
+mod variant;
+
+pub use notation::{fen, parse_fen, pgn, san};
+
+pub use position::{
+    CastlingRights, ChessPiece, Move, Outcome, PieceVariant, Position, SquareMoves,
+};
+
+pub use replay::{position_at, replay, Replay, ReplayError, ReplayPly};
+
+pub use variant::{GameVariant, Variant};
+
+pub struct Player {
+    pub client: Recipient<Message>,
+    pub username: Option<String>,
+    // send the side to move its legal moves at every turn change
+    pub push_legal_moves: bool,
+    // chat from the opponent is not delivered
+    pub muted: bool,
+}
+
+impl Player {
+    pub fn new(client: Recipient<Message>, username: Option<String>) -> Self {
+        Self {
+            client,
+            username,
+            push_legal_moves: false,
+            muted: false,
+        }
+    }
+}
+
+// manages game state
+// associated with a server
+// cannot exist independantly
+pub struct Game {
+    server: Addr<Server>,
+    id: String,
+    // unix seconds
+    started_at: u64,
+    players: [Player; 2],
+    discarded: Vec<ChessPiece>,
+    position: Position,
+    moves: Vec<MoveRecord>,
+    time_control: TimeControl,
+    variant: GameVariant,
+    rules: Box<dyn Variant>,
+    clocks: [Duration; 2],
+    turn_started: Instant,
+    flag_timer: Option<SpawnHandle>,
+    result: Option<(GameResult, String)>,
+    chat: Vec<ChatLine>,
+    // Some in arena games, which sides gave up half their clock
+    berserk: Option<[bool; 2]>,
+}
+
+impl Game {
+    pub fn new(
+        server: Addr<Server>,
+        id: String,
+        players: [Player; 2],
+        time_control: TimeControl,
+        variant: GameVariant,
+    ) -> Self {
+        let clock = Duration::from_secs(time_control.initial_secs);
+        let rules = variant.rules();
+        Game {
+            server,
+            id,
+            started_at: unix_now(),
+            players,
+            discarded: vec![],
+            position: rules.start(),
+            moves: vec![],
+            time_control,
+            variant,
+            rules,
+            clocks: [clock, clock],
+            turn_started: Instant::now(),
+            flag_timer: None,
+            result: None,
+            chat: vec![],
+            berserk: None,
+        }
+    }
+
+    // lets either player go berserk before their first move
+    pub fn with_berserk(mut self) -> Self {
+        self.berserk = Some([false; 2]);
+        self
+    }
+
+    // picks an adjourned game back up by replaying its moves from the start
+    pub fn resume(server: Addr<Server>, players: [Player; 2], saved: &AdjournedGame) -> Self {
+        let mut game = Game::new(
+            server,
+            saved.id.clone(),
+            players,
+            saved.time_control,
+            saved.variant,
+        );
+        game.started_at = saved.started_at;
+        // the rules see every move again, some keep count of things
+        for record in &saved.moves {
+            game.position.play(record.as_move());
+            game.rules.played(&game.position);
+        }
+        game.discarded = saved.moves.iter().filter_map(|m| m.captured).collect();
+        game.moves = saved.moves.clone();
+        game.clocks = saved.clocks.map(Duration::from_millis);
+        // a correspondence clock kept running while the game sat on disk
+        if saved.time_control.days_per_move.is_some() {
+            let away = Duration::from_secs(unix_now().saturating_sub(saved.saved_at));
+            let turn = game.turn();
+            game.clocks[turn] = game.clocks[turn].saturating_sub(away);
+        }
+        game.chat = saved.chat.clone();
+        game
+    }
+
+    fn turn(&self) -> usize {
+        self.position.turn
+    }
+
+    fn broadcast(&self, inner: OutgoingMessage) {
+        for player in self.players.iter() {
+            player.client.do_send(Message {
+                inner: inner.clone(),
+                game: None,
+            });
+        }
+    }
+
+    // time left on a player's clock, counting the turn in progress
+    fn remaining(&self, player: usize) -> Duration {
+        if player == self.turn() {
+            self.clocks[player].saturating_sub(self.turn_started.elapsed())
+        } else {
+            self.clocks[player]
+        }
+    }
+
+    pub fn snapshot(&self) -> GameSnapshot {
+        GameSnapshot {
+            board: self.position.board.to_vec(),
+            turn: Color::from_index(self.turn()),
+            castling: self.position.castling,
+            en_passant: self.position.en_passant,
+            moves: self.moves.clone(),
+            captured: self.discarded.clone(),
+            clocks: [0, 1].map(|p| self.remaining(p).as_millis() as u64),
+            time_control: self.time_control,
+            variant: self.variant,
+            pockets: self.position.pockets.clone(),
+            players: [0, 1].map(|p| PlayerInfo {
+                username: self.players[p].username.clone(),
+                color: Color::from_index(p),
+            }),
+        }
+    }
+
+    fn send_snapshot(&self, to: &Recipient<Message>, game: Option<Addr<Game>>) {
+        to.do_send(Message {
+            inner: OutgoingMessage::Snapshot(Box::new(self.snapshot())),
+            game,
+        });
+    }
+
+    fn send_legal_moves(&self, to: &Recipient<Message>, from: Option<usize>) {
+        let moves: Vec<Move> = self
+            .rules
+            .legal_moves(&self.position)
+            .into_iter()
+            // a drop would read as a piece standing still, pockets are in the snapshot
+            .filter(|mv| mv.dropped().is_none())
+            .filter(|mv| from.is_none_or(|from| mv.from == from))
+            .collect();
+        to.do_send(Message {
+            inner: OutgoingMessage::LegalMoves(position::group_by_square(&moves)),
+            game: None,
+        });
+    }
+
+    fn start_flag_timer(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.flag_timer.take() {
+            ctx.cancel_future(handle);
+        }
+        let remaining = self.remaining(self.turn());
+        self.flag_timer = Some(ctx.run_later(remaining, |act, ctx| {
+            let loser = act.turn();
+            act.finish(loser, "Timeout", ctx);
+        }));
+    }
+
+    pub fn record(&self) -> GameRecord {
+        GameRecord {
+            id: self.id.clone(),
+            players: [0, 1].map(|p| self.players[p].username.clone()),
+            time_control: self.time_control,
+            variant: self.variant,
+            started_at: self.started_at,
+            ended_at: self.result.as_ref().map(|_| unix_now()),
+            result: self.result.as_ref().map(|(result, _)| *result),
+            reason: self.result.as_ref().map(|(_, reason)| reason.clone()),
+            clocks: [0, 1].map(|p| self.remaining(p).as_millis() as u64),
+            moves: self.moves.clone(),
+            chat: self.chat.clone(),
+            berserk: self.berserk,
+            ratings: None,
+        }
+    }
+
+    fn finish(&mut self, loser: usize, reason: &str, ctx: &mut Context<Self>) {
+        self.result = Some((GameResult::win_for(1 - loser), reason.to_owned()));
+        self.players[loser].client.do_send(Message {
+            inner: OutgoingMessage::LoseGame(reason.to_owned()),
+            game: None,
+        });
+        self.players[(loser + 1) % 2].client.do_send(Message {
+            inner: OutgoingMessage::WinGame(reason.to_owned()),
+            game: None,
+        });
+        ctx.stop();
+    }
+
+    fn draw(&mut self, reason: &str, ctx: &mut Context<Self>) {
+        self.result = Some((GameResult::Draw, reason.to_owned()));
+        self.broadcast(OutgoingMessage::DrawGame(reason.to_owned()));
+        ctx.stop();
+    }
+
+    fn make_move(&mut self, details: &MoveDetails) -> Result<(), MoveError> {
+        let mv = details.to_move(&self.position)?;
+        let (from, to) = (mv.from, mv.to);
+        self.rules.check_move(&self.position, mv)?;
+        let applied = self.position.play(mv);
+        self.rules.played(&self.position);
+        if let Some((at, piece)) = applied.captured {
+            self.discarded.push(piece);
+            self.broadcast(OutgoingMessage::RemovePiece { at });
+        }
+        match applied.castle_rook {
+            // in chess960 the king can land where its rook stood, so the rook is
+            // lifted off first and set down once the king is in place
+            Some((rook_from, rook_to)) if applied.to == rook_from => {
+                self.broadcast(OutgoingMessage::RemovePiece { at: rook_from });
+                if from != applied.to {
+                    self.broadcast(OutgoingMessage::MovePiece {
+                        from,
+                        to: applied.to,
+                    });
+                }
+                self.broadcast(OutgoingMessage::PlacePiece {
+                    at: rook_to,
+                    piece: ChessPiece::new(applied.piece.side(), PieceVariant::Rook),
+                });
+            }
+            castle_rook => {
+                if from != applied.to {
+                    self.broadcast(OutgoingMessage::MovePiece {
+                        from,
+                        to: applied.to,
+                    });
+                }
+                if let Some((from, to)) = castle_rook.filter(|(from, to)| from != to) {
+                    self.broadcast(OutgoingMessage::MovePiece { from, to });
+                }
+            }
+        }
+        if let Some(piece) = applied.promoted {
+            self.broadcast(OutgoingMessage::PromotePiece { at: to, piece });
+        }
+        self.moves.push(MoveRecord {
+            piece: applied.piece,
+            from,
+            to,
+            captured: applied.captured.map(|(_, piece)| piece),
+            promotion: mv.promotion,
+        });
+        Ok(())
+    }
+
+    fn make_drop(&mut self, details: &DropDetails) -> Result<(), MoveError> {
+        let Some(to) = details.to.index() else {
+            return Err(MoveError::InvalidPosition);
+        };
+        if details.piece.side() != self.turn() {
+            return Err(MoveError::PieceMismatch);
+        }
+        let in_pocket = self
+            .position
+            .pockets
+            .as_ref()
+            .is_some_and(|pockets| pockets[self.turn()].contains(&details.piece.variant()));
+        if !in_pocket {
+            return Err(MoveError::NotInPocket);
+        }
+        if self.position.board[to].is_some() {
+            return Err(MoveError::SpaceOccupied);
+        }
+        let mv = Move::drop(details.piece.variant(), to);
+        self.rules.check_move(&self.position, mv)?;
+        let applied = self.position.play(mv);
+        self.rules.played(&self.position);
+        self.broadcast(OutgoingMessage::PlacePiece {
+            at: to,
+            piece: applied.piece,
+        });
+        self.moves.push(MoveRecord {
+            piece: applied.piece,
+            from: mv.from,
+            to,
+            captured: None,
+            promotion: mv.promotion,
+        });
+        Ok(())
+    }
+
+    // runs a move or drop for whoever sent it, then settles the clocks
+    fn take_turn(
+        &mut self,
+        player: &Recipient<Message>,
+        ctx: &mut Context<Self>,
+        play: impl FnOnce(&mut Self) -> Result<(), MoveError>,
+    ) -> Result<(), MoveError> {
+        let result = match self.players.iter().position(|p| p.client == *player) {
+            // a move that arrives after the flag fell loses on time, unplayed
+            Some(pos) if pos == self.turn() && self.remaining(pos).is_zero() => {
+                self.finish(pos, "Timeout", ctx);
+                return Err(MoveError::OutOfTime);
+            }
+            Some(pos) if pos == self.turn() => play(self).map(|()| pos),
+            _ => Err(MoveError::InvalidTurn),
+        };
+        match result {
+            Ok(pos) => {
+                log::debug!("Moved");
+                METRICS.move_played();
+                self.press_clock(pos);
+                self.after_move(pos, ctx);
+                self.report();
+                Ok(())
+            }
+            Err(err) => {
+                log::debug!("rejected move: {err:?}");
+                METRICS.move_rejected(err);
+                player.do_send(Message {
+                    inner: OutgoingMessage::Result(ClientResult::MoveError(err)),
+                    game: None,
+                });
+                Err(err)
+            }
+        }
+    }
+
+    // ends the game if the move just played decided it, otherwise hands over the turn
+    fn after_move(&mut self, mover: usize, ctx: &mut Context<Self>) {
+        match self.rules.outcome(&self.position) {
+            Some(Outcome::Checkmate { winner }) => {
+                self.broadcast(OutgoingMessage::Checkmate { winner });
+                self.finish(1 - winner, "Checkmate", ctx);
+            }
+            Some(Outcome::Stalemate) => self.draw("Stalemate", ctx),
+            Some(Outcome::FiftyMoves) => self.draw("FiftyMoves", ctx),
+            Some(Outcome::InsufficientMaterial) => self.draw("InsufficientMaterial", ctx),
+            Some(Outcome::VariantWin { winner, reason }) => self.finish(1 - winner, reason, ctx),
+            None => {
+                if self.rules.in_check(&self.position) {
+                    self.broadcast(OutgoingMessage::Check { checker: mover });
+                }
+                let next = &self.players[self.turn()];
+                if next.push_legal_moves {
+                    self.send_legal_moves(&next.client, None);
+                }
+                self.start_flag_timer(ctx);
+            }
+        }
+    }
+
+    // charges the elapsed time to the player who just moved, which was
+    // less than they had left
+    fn press_clock(&mut self, player: usize) {
+        let spent = self.turn_started.elapsed();
+        self.clocks[player] = match self.time_control.days_per_move {
+            // a correspondence deadline starts over with every move made in time
+            Some(_) => Duration::from_secs(self.time_control.initial_secs),
+            // berserk players lose their increment too
+            _ if self.berserk.is_some_and(|berserk| berserk[player]) => {
+                self.clocks[player].saturating_sub(spent)
+            }
+            _ => {
+                self.clocks[player].saturating_sub(spent)
+                    + Duration::from_secs(self.time_control.increment_secs)
+            }
+        };
+        self.turn_started = Instant::now();
+    }
+
+    // the game as it stands, if both players are named and it can be saved
+    fn saved(&self) -> Option<AdjournedGame> {
+        let [white, black] = [0, 1].map(|p| self.players[p].username.clone());
+        Some(AdjournedGame {
+            id: self.id.clone(),
+            started_at: self.started_at,
+            players: [white?, black?],
+            time_control: self.time_control,
+            variant: self.variant,
+            clocks: [0, 1].map(|p| self.remaining(p).as_millis() as u64),
+            moves: self.moves.clone(),
+            chat: self.chat.clone(),
+            saved_at: unix_now(),
+        })
+    }
+
+    // correspondence games are written out whenever they change, as nobody
+    // may be connected when the server goes down
+    fn report(&self) {
+        if self.time_control.days_per_move.is_none() || self.result.is_some() {
+            return;
+        }
+        if let Some(saved) = self.saved() {
+            self.server.do_send(SaveCorrespondence(saved));
+        }
+    }
+}
+
+impl Actor for Game {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        METRICS.game_started();
+        for (index, player) in self.players.iter().enumerate() {
+            player.client.do_send(Message {
+                inner: OutgoingMessage::GameStarted(Color::from_index(index)),
+                game: Some(ctx.address()),
+            });
+            self.send_snapshot(&player.client, None);
+        }
+        self.turn_started = Instant::now();
+        self.start_flag_timer(ctx);
+        self.report();
+    }
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        METRICS.game_stopped();
+        self.server.do_send(GameOver {
+            game: ctx.address(),
+            id: self.id.clone(),
+            clients: [0, 1].map(|p| self.players[p].client.clone()),
+            // adjourned games are not over yet and keep no record
+            record: self.result.as_ref().map(|_| self.record()),
+        });
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct Pos {
+    x: u8,
+    y: u8,
+}
+
+impl Pos {
+    pub fn from_index(index: usize) -> Self {
+        Self {
+            x: (index % 8) as u8,
+            y: (index / 8) as u8,
+        }
+    }
+
+    fn index(&self) -> Option<usize> {
+        match self.x < 8 && self.y < 8 {
+            true => Some((self.y * 8 + self.x) as usize),
+            false => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct MoveDetails {
+    pub piece: ChessPiece,
+    pub from: Pos,
+    pub to: Pos,
+    // piece a pawn becomes on the last rank, defaults to a queen
+    #[serde(default)]
+    pub promotion: Option<PieceVariant>,
+}
+
+impl MoveDetails {
+    // the move the side to move means by this, before the rules have a say
+    pub fn to_move(&self, position: &Position) -> Result<Move, MoveError> {
+        let (from, to) = match (self.from.index(), self.to.index()) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Err(MoveError::InvalidPosition),
+        };
+        match position.board[from] {
+            Some(piece) if piece.side() == position.turn && piece == self.piece => {}
+            _ => return Err(MoveError::PieceMismatch),
+        }
+        let castling = position.castle(Move {
+            from,
+            to,
+            promotion: None,
+        });
+        if castling.is_none() && position.board[to].is_some_and(|p| p.side() == position.turn) {
+            return Err(MoveError::SpaceOccupied);
+        }
+        let promotion = match self.piece.variant() {
+            PieceVariant::Pawn if to / 8 == 0 || to / 8 == 7 => {
+                Some(self.promotion.unwrap_or(PieceVariant::Queen))
+            }
+            _ => None,
+        };
+        Ok(Move {
+            from,
+            to,
+            promotion,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DropDetails {
+    pub piece: ChessPiece,
+    pub to: Pos,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TimeControl {
+    pub initial_secs: u64,
+    pub increment_secs: u64,
+    // correspondence games give each move this many days, instead of
+    // spending from one budget for the whole game
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days_per_move: Option<u32>,
+}
+
+impl TimeControl {
+    pub fn correspondence(days: u32) -> Self {
+        Self {
+            initial_secs: u64::from(days) * 86_400,
+            increment_secs: 0,
+            days_per_move: Some(days),
+        }
+    }
+}
+
+// the usual minutes+increment shorthand, e.g. 3+2
+impl FromStr for TimeControl {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (minutes, increment) = s
+            .split_once('+')
+            .ok_or_else(|| format!("expected minutes+increment, got `{s}`"))?;
+        let parse = |part: &str| {
+            part.trim()
+                .parse::<u64>()
+                .map_err(|_| format!("`{part}` is not a whole number"))
+        };
+        Ok(Self {
+            initial_secs: parse(minutes)? * 60,
+            increment_secs: parse(increment)?,
+            days_per_move: None,
+        })
+    }
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        Self {
+            initial_secs: 600,
+            increment_secs: 0,
+            days_per_move: None,
+        }
+    }
+}
+
+// drops are kept as a move from the target square to itself, with the
+// dropped piece as the promotion
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct MoveRecord {
+    pub piece: ChessPiece,
+    pub from: usize,
+    pub to: usize,
+    pub captured: Option<ChessPiece>,
+    pub promotion: Option<PieceVariant>,
+}
+
+impl MoveRecord {
+    pub fn as_move(&self) -> Move {
+        Move {
+            from: self.from,
+            to: self.to,
+            promotion: self.promotion,
+        }
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameResult {
+    #[serde(rename = "1-0")]
+    WhiteWins,
+    #[serde(rename = "0-1")]
+    BlackWins,
+    #[serde(rename = "1/2-1/2")]
+    Draw,
+}
+
+impl GameResult {
+    pub fn win_for(side: usize) -> Self {
+        match side {
+            0 => GameResult::WhiteWins,
+            _ => GameResult::BlackWins,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        }
+    }
+}
+
+// a game as kept in the archive once it is over, or as it stands while live
+#[derive(Deserialize, Serialize, Clone)]
+pub struct GameRecord {
+    pub id: String,
+    // usernames of white and black, None for anonymous players
+    pub players: [Option<String>; 2],
+    pub time_control: TimeControl,
+    #[serde(default)]
+    pub variant: GameVariant,
+    // unix seconds
+    pub started_at: u64,
+    pub ended_at: Option<u64>,
+    pub result: Option<GameResult>,
+    pub reason: Option<String>,
+    // milliseconds left for white and black
+    pub clocks: [u64; 2],
+    pub moves: Vec<MoveRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chat: Vec<ChatLine>,
+    // arena games only, which sides went berserk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub berserk: Option<[bool; 2]>,
+    // white's and black's ratings going into a game between two rated accounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ratings: Option<[i32; 2]>,
+}
+
+// an anonymous finished game of the variant with the given uci moves,
+// for tests to fill in whatever else they care about
+#[cfg(test)]
+pub(crate) fn record_from_uci(variant: GameVariant, moves: &str) -> GameRecord {
+    let mut position = variant.start();
+    let moves = moves
+        .split_whitespace()
+        .map(|text| {
+            let mv = crate::engine::uci::parse_move(&position, text).unwrap();
+            let applied = position.play(mv);
+            MoveRecord {
+                piece: applied.piece,
+                from: mv.from,
+                to: mv.to,
+                captured: applied.captured.map(|(_, piece)| piece),
+                promotion: mv.promotion,
+            }
+        })
+        .collect();
+    GameRecord {
+        id: "test".to_owned(),
+        players: [None, None],
+        time_control: TimeControl::default(),
+        variant,
+        started_at: 0,
+        ended_at: Some(0),
+        result: None,
+        reason: None,
+        clocks: [0, 0],
+        moves,
+        chat: vec![],
+        berserk: None,
+        ratings: None,
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct PlayerInfo {
+    pub username: Option<String>,
+    pub color: Color,
+}
+
+// everything a client needs to redraw a game from scratch
+#[derive(Serialize, Clone)]
+pub struct GameSnapshot {
+    pub board: Vec<Option<ChessPiece>>,
+    pub turn: Color,
+    pub castling: CastlingRights,
+    pub en_passant: Option<usize>,
+    pub moves: Vec<MoveRecord>,
+    pub captured: Vec<ChessPiece>,
+    // milliseconds left for white and black
+    pub clocks: [u64; 2],
+    pub time_control: TimeControl,
+    pub variant: GameVariant,
+    // pieces in hand for white and black in crazyhouse
+    pub pockets: Option<[Vec<PieceVariant>; 2]>,
+    pub players: [PlayerInfo; 2],
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum MoveError {
+    PieceMismatch,
+    InvalidPosition,
+    SpaceOccupied,
+    KingInCheck,
+    // antichess makes taking compulsory
+    MustCapture,
+    // crazyhouse drops of a piece the player does not hold
+    NotInPocket,
+    InvalidTurn,
+    NotInGame,
+    // the mover's clock ran out before the move arrived
+    OutOfTime,
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "Result<(), MoveError>")]
+pub struct MakeMove {
+    pub move_details: MoveDetails,
+    pub player: Recipient<Message>,
+}
+
+impl Handler<MakeMove> for Game {
+    type Result = Result<(), MoveError>;
+    fn handle(&mut self, msg: MakeMove, ctx: &mut Self::Context) -> Self::Result {
+        self.take_turn(&msg.player, ctx, |game| game.make_move(&msg.move_details))
+    }
+}
+
+// a crazyhouse player putting a piece from their pocket on the board
+#[derive(ActixMessage)]
+#[rtype(result = "Result<(), MoveError>")]
+pub struct DropPiece {
+    pub details: DropDetails,
+    pub player: Recipient<Message>,
+}
+
+impl Handler<DropPiece> for Game {
+    type Result = Result<(), MoveError>;
+    fn handle(&mut self, msg: DropPiece, ctx: &mut Self::Context) -> Self::Result {
+        self.take_turn(&msg.player, ctx, |game| game.make_drop(&msg.details))
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct ForfeitGame(pub Recipient<Message>);
+
+impl Handler<ForfeitGame> for Game {
+    type Result = ();
+    fn handle(&mut self, msg: ForfeitGame, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(player) = self.players.iter().position(|p| p.client == msg.0) {
+            self.finish(player, "Forfeit", ctx);
+        }
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct SendSnapshot(pub Recipient<Message>);
+
+impl Handler<SendSnapshot> for Game {
+    type Result = ();
+    fn handle(&mut self, msg: SendSnapshot, _ctx: &mut Self::Context) -> Self::Result {
+        if self.players.iter().any(|p| p.client == msg.0) {
+            self.send_snapshot(&msg.0, None);
+        }
+    }
+}
+
+// what gets written to storage when a game is cut short by a shutdown
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AdjournedGame {
+    pub id: String,
+    #[serde(default)]
+    pub started_at: u64,
+    // usernames of white and black
+    pub players: [String; 2],
+    pub time_control: TimeControl,
+    #[serde(default)]
+    pub variant: GameVariant,
+    // milliseconds left for white and black
+    pub clocks: [u64; 2],
+    pub moves: Vec<MoveRecord>,
+    #[serde(default)]
+    pub chat: Vec<ChatLine>,
+    // unix seconds, correspondence clocks keep running from here
+    #[serde(default)]
+    pub saved_at: u64,
+}
+
+// stops the game where it stands, resolves to the state worth saving.
+// games with an anonymous player cannot be resumed and are simply dropped
+#[derive(ActixMessage)]
+#[rtype(result = "Option<AdjournedGame>")]
+pub struct Adjourn;
+
+impl Handler<Adjourn> for Game {
+    type Result = Option<AdjournedGame>;
+    fn handle(&mut self, _msg: Adjourn, ctx: &mut Self::Context) -> Self::Result {
+        self.broadcast(OutgoingMessage::GameAdjourned);
+        ctx.stop();
+        self.saved()
+    }
+}
+
+// the game as it stands, for the http api
+#[derive(ActixMessage)]
+#[rtype(result = "GameRecord")]
+pub struct GetRecord;
+
+impl Handler<GetRecord> for Game {
+    type Result = MessageResult<GetRecord>;
+    fn handle(&mut self, _msg: GetRecord, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.record())
+    }
+}
+
+// an arena player halves their clock for an extra point on a win
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Berserk(pub Recipient<Message>);
+
+impl Handler<Berserk> for Game {
+    type Result = ();
+    fn handle(&mut self, msg: Berserk, ctx: &mut Self::Context) -> Self::Result {
+        let Some(seat) = self.players.iter().position(|p| p.client == msg.0) else {
+            return;
+        };
+        // white has moved once there is a move, black once there are two
+        match self.berserk.as_mut() {
+            Some(berserk) if !berserk[seat] && self.moves.len() <= seat => berserk[seat] = true,
+            _ => {
+                let err = ClientResult::TournamentError(TournamentError::CannotBerserk);
+                return msg.0.do_send(Message {
+                    inner: OutgoingMessage::Result(err),
+                    game: None,
+                });
+            }
+        }
+        let left = self.remaining(seat) / 2;
+        match seat == self.turn() {
+            true => {
+                self.clocks[seat] = left + self.turn_started.elapsed();
+                self.start_flag_timer(ctx);
+            }
+            false => self.clocks[seat] = left,
+        }
+        self.broadcast(OutgoingMessage::Berserk(Color::from_index(seat)));
+    }
+}
+
+// a logged in player came back on a new connection
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Rejoin {
+    pub username: String,
+    pub client: Recipient<Message>,
+}
+
+impl Handler<Rejoin> for Game {
+    type Result = ();
+    fn handle(&mut self, msg: Rejoin, ctx: &mut Self::Context) -> Self::Result {
+        let seat = self
+            .players
+            .iter()
+            .position(|p| p.username.as_ref() == Some(&msg.username));
+        if let Some(seat) = seat {
+            self.players[seat].client = msg.client;
+            self.send_snapshot(&self.players[seat].client, Some(ctx.address()));
+        }
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct GetLegalMoves {
+    pub player: Recipient<Message>,
+    pub from: Option<Pos>,
+}
+
+impl Handler<GetLegalMoves> for Game {
+    type Result = ();
+    fn handle(&mut self, msg: GetLegalMoves, _ctx: &mut Self::Context) -> Self::Result {
+        match msg.from.map(|pos| pos.index()) {
+            Some(None) => msg.player.do_send(Message {
+                inner: OutgoingMessage::Result(ClientResult::MoveError(MoveError::InvalidPosition)),
+                game: None,
+            }),
+            from => self.send_legal_moves(&msg.player, from.flatten()),
+        }
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct PushLegalMoves {
+    pub player: Recipient<Message>,
+    pub enabled: bool,
+}
+
+impl Handler<PushLegalMoves> for Game {
+    type Result = ();
+    fn handle(&mut self, msg: PushLegalMoves, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(seat) = self.players.iter().position(|p| p.client == msg.player) {
+            self.players[seat].push_legal_moves = msg.enabled;
+            if msg.enabled && seat == self.turn() {
+                self.send_legal_moves(&msg.player, None);
+            }
+        }
+    }
+}
+
+// a chat line that already passed the server's checks
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct GameChat {
+    pub player: Recipient<Message>,
+    pub line: ChatLine,
+}
+
+impl Handler<GameChat> for Game {
+    type Result = ();
+    fn handle(&mut self, msg: GameChat, _ctx: &mut Self::Context) -> Self::Result {
+        let Some(seat) = self.players.iter().position(|p| p.client == msg.player) else {
+            msg.player.do_send(Message {
+                inner: OutgoingMessage::Result(ClientResult::ChatError(ChatError::NotInGame)),
+                game: None,
+            });
+            return;
+        };
+        for (index, player) in self.players.iter().enumerate() {
+            if index != seat && player.muted {
+                continue;
+            }
+            player.client.do_send(Message {
+                inner: OutgoingMessage::Chat {
+                    scope: ChatScope::Game,
+                    line: msg.line.clone(),
+                },
+                game: None,
+            });
+        }
+        self.chat.push(msg.line);
+        self.report();
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct MuteOpponent {
+    pub player: Recipient<Message>,
+    pub muted: bool,
+}
+
+impl Handler<MuteOpponent> for Game {
+    type Result = ();
+    fn handle(&mut self, msg: MuteOpponent, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(seat) = self.players.iter().position(|p| p.client == msg.player) {
+            self.players[seat].muted = msg.muted;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chat::{BlockList, ChatPolicy},
+        config::{BotConfig, ChatConfig, UciConfig},
+        storage::Storage,
+    };
+    use std::sync::{Arc, Mutex};
+
+    // a connection that keeps what it is sent
+    struct Client(Arc<Mutex<Vec<OutgoingMessage>>>);
+
+    impl Actor for Client {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Message> for Client {
+        type Result = ();
+        fn handle(&mut self, msg: Message, _ctx: &mut Self::Context) -> Self::Result {
+            self.0.lock().unwrap().push(msg.inner);
+        }
+    }
+
+    #[actix::test]
+    async fn a_move_after_the_flag_falls_loses_on_time() {
+        let dir = std::env::temp_dir().join(format!("chess-game-flag-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let server = Server::new(
+            TimeControl::default(),
+            Storage::open(&dir).unwrap(),
+            ChatPolicy::new(&ChatConfig::default(), Box::new(BlockList::new(&[]))),
+            UciConfig::default(),
+            BotConfig::default(),
+            None,
+        )
+        .start();
+        let received = [(); 2].map(|()| Arc::new(Mutex::new(vec![])));
+        let players =
+            [0, 1].map(|p| Player::new(Client(received[p].clone()).start().recipient(), None));
+        let white = players[0].client.clone();
+        let time_control = TimeControl {
+            initial_secs: 60,
+            increment_secs: 5,
+            days_per_move: None,
+        };
+        let mut game = Game::new(
+            server,
+            "test".to_owned(),
+            players,
+            time_control,
+            GameVariant::Standard,
+        );
+        // white is down to their last moment
+        game.clocks[0] = Duration::from_millis(50);
+        let game = game.start();
+        let unmute = MuteOpponent {
+            player: white.clone(),
+            muted: false,
+        };
+        game.send(unmute).await.unwrap();
+        // the move arrives late, before the flag timer gets to run
+        std::thread::sleep(Duration::from_millis(60));
+        let e4 = MakeMove {
+            move_details: MoveDetails {
+                piece: ChessPiece::new(0, PieceVariant::Pawn),
+                from: Pos::from_index(12),
+                to: Pos::from_index(28),
+                promotion: None,
+            },
+            player: white,
+        };
+        // the increment must not bring white back from zero
+        assert_eq!(game.send(e4).await.unwrap(), Err(MoveError::OutOfTime));
+        actix::clock::sleep(Duration::from_millis(20)).await;
+        let white = received[0].lock().unwrap();
+        assert!(white
+            .iter()
+            .any(|msg| matches!(msg, OutgoingMessage::LoseGame(reason) if reason == "Timeout")));
+        assert!(!white
+            .iter()
+            .any(|msg| matches!(msg, OutgoingMessage::MovePiece { .. })));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,601 @@
+use std::fmt::Write;
+
+use super::{
+    position::{CastlingRights, ChessPiece, Move, PieceVariant, Position},
+    GameRecord, GameVariant, MoveRecord, TimeControl, Variant,
+};
+
+fn square_name(square: usize) -> String {
+    format!("{}{}", (b'a' + (square % 8) as u8) as char, square / 8 + 1)
+}
+
+fn piece_letter(variant: PieceVariant) -> char {
+    match variant {
+        PieceVariant::King => 'K',
+        PieceVariant::Queen => 'Q',
+        PieceVariant::Rook => 'R',
+        PieceVariant::Bishop => 'B',
+        PieceVariant::Knight => 'N',
+        PieceVariant::Pawn => 'P',
+    }
+}
+
+// x-fen: K and Q while the castling rook is the outermost one on its wing,
+// otherwise the rook's file, as in shredder-fen
+fn castling_letter(position: &Position, side: usize, rook: usize, letter: char) -> char {
+    let own_rook = Some(ChessPiece::new(side, PieceVariant::Rook));
+    let outside = match rook > position.home.king {
+        true => rook + 1..8,
+        false => 0..rook,
+    };
+    if outside
+        .into_iter()
+        .all(|file| position.board[side * 56 + file] != own_rook)
+    {
+        return letter;
+    }
+    let file = (b'A' + rook as u8) as char;
+    match side {
+        0 => file,
+        _ => file.to_ascii_lowercase(),
+    }
+}
+
+fn fen_letter(piece: ChessPiece) -> char {
+    let letter = piece_letter(piece.variant());
+    match piece.side() {
+        0 => letter,
+        _ => letter.to_ascii_lowercase(),
+    }
+}
+
+pub fn fen(position: &Position) -> String {
+    let mut out = String::new();
+    for rank in (0..8).rev() {
+        let mut empty = 0;
+        for file in 0..8 {
+            match position.board[rank * 8 + file] {
+                Some(piece) => {
+                    if empty > 0 {
+                        let _ = write!(out, "{empty}");
+                        empty = 0;
+                    }
+                    out.push(fen_letter(piece));
+                    // crazyhouse marks promoted pieces, they go back as pawns
+                    let square = rank * 8 + file;
+                    if position.pockets.is_some() && position.promoted & (1 << square) != 0 {
+                        out.push('~');
+                    }
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            let _ = write!(out, "{empty}");
+        }
+        if rank > 0 {
+            out.push('/');
+        }
+    }
+    if let Some(pockets) = &position.pockets {
+        out.push('[');
+        for (side, pocket) in pockets.iter().enumerate() {
+            out.extend(pocket.iter().map(|&p| fen_letter(ChessPiece::new(side, p))));
+        }
+        out.push(']');
+    }
+    let castling = &position.castling;
+    let home = &position.home;
+    let mut rights = String::new();
+    for (allowed, side, rook, letter) in [
+        (castling.white_kingside, 0, home.king_rook, 'K'),
+        (castling.white_queenside, 0, home.queen_rook, 'Q'),
+        (castling.black_kingside, 1, home.king_rook, 'k'),
+        (castling.black_queenside, 1, home.queen_rook, 'q'),
+    ] {
+        if allowed {
+            rights.push(castling_letter(position, side, rook, letter));
+        }
+    }
+    if rights.is_empty() {
+        rights.push('-');
+    }
+    let en_passant = position.en_passant.map_or("-".to_owned(), square_name);
+    let turn = if position.turn == 0 { 'w' } else { 'b' };
+    format!(
+        "{out} {turn} {rights} {en_passant} {} {}",
+        position.halfmove_clock, position.fullmove_number
+    )
+}
+
+fn parse_square(name: &str) -> Option<usize> {
+    match name.as_bytes() {
+        &[file @ b'a'..=b'h', rank @ b'1'..=b'8'] => {
+            Some((rank - b'1') as usize * 8 + (file - b'a') as usize)
+        }
+        _ => None,
+    }
+}
+
+// a position of standard chess from its fen, None unless it is one that can
+// be played on from. the move counters may be left off
+pub fn parse_fen(text: &str) -> Option<Position> {
+    let mut fields = text.split_whitespace();
+    let (placement, turn, castling, en_passant) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    let halfmove_clock = fields.next().map_or(Some(0), |field| field.parse().ok())?;
+    let fullmove_number = fields.next().map_or(Some(1), |field| field.parse().ok())?;
+    if fields.next().is_some() {
+        return None;
+    }
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != 8 {
+        return None;
+    }
+    let mut board = [None; 64];
+    for (row, rank) in ranks.iter().enumerate() {
+        let mut file = 0;
+        for letter in rank.chars() {
+            if let Some(empty) = letter.to_digit(10).filter(|n| (1..=8).contains(n)) {
+                file += empty as usize;
+                continue;
+            }
+            let variant = match letter.to_ascii_uppercase() {
+                'K' => PieceVariant::King,
+                'Q' => PieceVariant::Queen,
+                'R' => PieceVariant::Rook,
+                'B' => PieceVariant::Bishop,
+                'N' => PieceVariant::Knight,
+                'P' => PieceVariant::Pawn,
+                _ => return None,
+            };
+            if file >= 8 {
+                return None;
+            }
+            let side = usize::from(letter.is_ascii_lowercase());
+            board[(7 - row) * 8 + file] = Some(ChessPiece::new(side, variant));
+            file += 1;
+        }
+        if file != 8 {
+            return None;
+        }
+    }
+    let pawns_home = (0..8)
+        .chain(56..64)
+        .any(|square| board[square].is_some_and(|p| p.variant() == PieceVariant::Pawn));
+    if pawns_home {
+        return None;
+    }
+    let mut position = Position {
+        board,
+        turn: match turn {
+            "w" => 0,
+            "b" => 1,
+            _ => return None,
+        },
+        castling: CastlingRights {
+            white_kingside: false,
+            white_queenside: false,
+            black_kingside: false,
+            black_queenside: false,
+        },
+        halfmove_clock,
+        fullmove_number,
+        ..Position::default()
+    };
+    for side in 0..2 {
+        let kings = board
+            .iter()
+            .filter(|&&piece| piece == Some(ChessPiece::new(side, PieceVariant::King)))
+            .count();
+        if kings != 1 {
+            return None;
+        }
+    }
+    // the side that just moved cannot have left its king to be taken
+    let waiting = 1 - position.turn;
+    if position.is_attacked(position.king_square(waiting)?, position.turn) {
+        return None;
+    }
+    if castling != "-" {
+        for letter in castling.chars() {
+            let (side, rook, right) = match letter {
+                'K' => (0, 7, &mut position.castling.white_kingside),
+                'Q' => (0, 0, &mut position.castling.white_queenside),
+                'k' => (1, 63, &mut position.castling.black_kingside),
+                'q' => (1, 56, &mut position.castling.black_queenside),
+                _ => return None,
+            };
+            let king = side * 56 + 4;
+            let home = board[king] == Some(ChessPiece::new(side, PieceVariant::King))
+                && board[rook] == Some(ChessPiece::new(side, PieceVariant::Rook));
+            if *right || !home {
+                return None;
+            }
+            *right = true;
+        }
+    }
+    if en_passant != "-" {
+        let square = parse_square(en_passant)?;
+        // the square a pawn of the side that just moved skipped over
+        if square / 8 != [5, 2][position.turn] {
+            return None;
+        }
+        position.en_passant = Some(square);
+    }
+    Some(position)
+}
+
+// standard algebraic notation for a legal move in the given position
+pub fn san(position: &Position, mv: Move) -> String {
+    san_by(GameVariant::Standard.rules().as_ref(), position, mv)
+}
+
+// the rules decide which other pieces need telling apart and what is check
+pub(super) fn san_by(rules: &dyn Variant, position: &Position, mv: Move) -> String {
+    let mut out = String::new();
+    if let Some(dropped) = mv.dropped() {
+        out.push(piece_letter(dropped));
+        out.push('@');
+        out.push_str(&square_name(mv.to));
+    } else if let Some(castle) = position.castle(mv) {
+        out.push_str(if castle.kingside { "O-O" } else { "O-O-O" });
+    } else {
+        let piece = position.board[mv.from].expect("no piece on the origin square");
+        let capture = position.board[mv.to].is_some()
+            || (piece.variant() == PieceVariant::Pawn && Some(mv.to) == position.en_passant);
+        if piece.variant() == PieceVariant::Pawn {
+            if capture {
+                out.push((b'a' + (mv.from % 8) as u8) as char);
+            }
+        } else {
+            out.push(piece_letter(piece.variant()));
+            // other pieces of the same kind that could also reach the square
+            let rivals: Vec<usize> = rules
+                .legal_moves(position)
+                .into_iter()
+                .filter(|other| {
+                    other.to == mv.to
+                        && other.from != mv.from
+                        && position.board[other.from] == Some(piece)
+                })
+                .map(|other| other.from)
+                .collect();
+            if !rivals.is_empty() {
+                let file = (b'a' + (mv.from % 8) as u8) as char;
+                let rank = (b'1' + (mv.from / 8) as u8) as char;
+                if rivals.iter().all(|from| from % 8 != mv.from % 8) {
+                    out.push(file);
+                } else if rivals.iter().all(|from| from / 8 != mv.from / 8) {
+                    out.push(rank);
+                } else {
+                    out.push(file);
+                    out.push(rank);
+                }
+            }
+        }
+        if capture {
+            out.push('x');
+        }
+        out.push_str(&square_name(mv.to));
+        if let Some(promotion) = mv.promotion {
+            out.push('=');
+            out.push(piece_letter(promotion));
+        }
+    }
+    let mut after = position.clone();
+    after.play(mv);
+    if rules.in_check(&after) {
+        out.push(match rules.legal_moves(&after).is_empty() {
+            true => '#',
+            false => '+',
+        });
+    }
+    out
+}
+
+// replays the moves from the start to name each one
+pub fn san_moves(variant: GameVariant, moves: &[MoveRecord]) -> Vec<String> {
+    let rules = variant.rules();
+    let mut position = rules.start();
+    moves
+        .iter()
+        .map(|record| {
+            let mv = record.as_move();
+            let san = san_by(rules.as_ref(), &position, mv);
+            position.play(mv);
+            san
+        })
+        .collect()
+}
+
+// days since the epoch to a proleptic gregorian date, see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_date(unix_secs: u64) -> (i64, u32, u32) {
+    let days = (unix_secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// moves/seconds is how the spec writes one move per deadline
+fn time_control_tag(time_control: &TimeControl) -> String {
+    match time_control.days_per_move {
+        Some(_) => format!("1/{}", time_control.initial_secs),
+        None => format!(
+            "{}+{}",
+            time_control.initial_secs, time_control.increment_secs
+        ),
+    }
+}
+
+pub fn pgn(record: &GameRecord) -> String {
+    let (year, month, day) = civil_date(record.started_at);
+    let result = record.result.map_or("*", |result| result.as_str());
+    let player = |side: usize| record.players[side].as_deref().unwrap_or("?").to_owned();
+    let mut out = String::new();
+    let mut tags = vec![
+        ("Event", "Casual game".to_owned()),
+        ("Site", "chess-backend".to_owned()),
+        ("Date", format!("{year:04}.{month:02}.{day:02}")),
+        ("Round", "-".to_owned()),
+        ("White", player(0)),
+        ("Black", player(1)),
+        ("Result", result.to_owned()),
+        ("TimeControl", time_control_tag(&record.time_control)),
+    ];
+    if let Some(reason) = &record.reason {
+        tags.push(("Termination", reason.clone()));
+    }
+    if record.variant != GameVariant::Standard {
+        tags.push(("Variant", record.variant.name().to_owned()));
+    }
+    if let GameVariant::Chess960(_) = record.variant {
+        tags.push(("SetUp", "1".to_owned()));
+        tags.push(("FEN", fen(&record.variant.start())));
+    }
+    for (name, value) in tags {
+        let _ = writeln!(out, "[{name} \"{}\"]", escape_tag(&value));
+    }
+    out.push('\n');
+    // movetext, wrapped to stay under the 80 column limit from the spec
+    let mut line = String::new();
+    let mut tokens = vec![];
+    for (ply, san) in san_moves(record.variant, &record.moves)
+        .into_iter()
+        .enumerate()
+    {
+        if ply % 2 == 0 {
+            tokens.push(format!("{}.", ply / 2 + 1));
+        }
+        tokens.push(san);
+    }
+    tokens.push(result.to_owned());
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > 79 {
+            out.push_str(&line);
+            out.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    out.push_str(&line);
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{position::Home, record_from_uci, CastlingRights, ChessPiece, GameResult};
+
+    fn sq(name: &str) -> usize {
+        let bytes = name.as_bytes();
+        (bytes[1] - b'1') as usize * 8 + (bytes[0] - b'a') as usize
+    }
+
+    fn mv(from: &str, to: &str) -> Move {
+        Move {
+            from: sq(from),
+            to: sq(to),
+            promotion: None,
+        }
+    }
+
+    fn play_all(moves: &[(&str, &str)]) -> (Position, Vec<String>) {
+        let mut position = Position::default();
+        let mut sans = vec![];
+        for (from, to) in moves {
+            let mv = mv(from, to);
+            sans.push(san(&position, mv));
+            position.play(mv);
+        }
+        (position, sans)
+    }
+
+    #[test]
+    fn start_position_fen() {
+        assert_eq!(
+            fen(&Position::default()),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+        let (position, _) = play_all(&[("e2", "e4"), ("c7", "c5"), ("g1", "f3")]);
+        assert_eq!(
+            fen(&position),
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+    }
+
+    #[test]
+    fn fen_reads_back_what_it_wrote() {
+        for text in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w Kq f6 0 3",
+            "8/8/4k3/8/8/8/4K3/8 b - - 40 60",
+        ] {
+            assert_eq!(fen(&parse_fen(text).unwrap()), text);
+        }
+        // the move counters are optional
+        let short = parse_fen("8/8/4k3/8/8/8/4K3/8 w - -").unwrap();
+        assert_eq!((short.halfmove_clock, short.fullmove_number), (0, 1));
+        for text in [
+            "",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+            // no rook on h1 to castle with
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN1 w KQkq - 0 1",
+            // en passant on the wrong rank
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e4 0 1",
+            // two white kings, and then white to move with black in check
+            "4k3/8/8/8/8/8/8/3KK3 w - - 0 1",
+            "4k3/8/8/8/8/8/4Q3/4K3 w - - 0 1",
+        ] {
+            assert!(parse_fen(text).is_none(), "{text}");
+        }
+    }
+
+    #[test]
+    fn san_covers_captures_castling_and_mate() {
+        let (_, sans) = play_all(&[
+            ("e2", "e4"),
+            ("e7", "e5"),
+            ("g1", "f3"),
+            ("b8", "c6"),
+            ("f1", "c4"),
+            ("g8", "f6"),
+            ("e1", "g1"),
+            ("f6", "e4"),
+        ]);
+        assert_eq!(
+            sans,
+            ["e4", "e5", "Nf3", "Nc6", "Bc4", "Nf6", "O-O", "Nxe4"]
+        );
+
+        let (_, sans) = play_all(&[("f2", "f3"), ("e7", "e5"), ("g2", "g4"), ("d8", "h4")]);
+        assert_eq!(sans[3], "Qh4#");
+    }
+
+    #[test]
+    fn san_disambiguates_and_promotes() {
+        let mut position = Position {
+            board: [None; 64],
+            castling: CastlingRights {
+                white_kingside: false,
+                white_queenside: false,
+                black_kingside: false,
+                black_queenside: false,
+            },
+            ..Position::default()
+        };
+        position.board[sq("a1")] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[sq("h8")] = Some(ChessPiece::Black(PieceVariant::King));
+        position.board[sq("b1")] = Some(ChessPiece::White(PieceVariant::Knight));
+        position.board[sq("f1")] = Some(ChessPiece::White(PieceVariant::Knight));
+        position.board[sq("b7")] = Some(ChessPiece::White(PieceVariant::Pawn));
+        assert_eq!(san(&position, mv("b1", "d2")), "Nbd2");
+        let promotion = Move {
+            promotion: Some(PieceVariant::Queen),
+            ..mv("b7", "b8")
+        };
+        assert_eq!(san(&position, promotion), "b8=Q+");
+    }
+
+    #[test]
+    fn chess960_castling_in_fen_and_san() {
+        let start = Position::chess960(0);
+        assert_eq!(
+            fen(&start),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1"
+        );
+
+        let mut position = Position {
+            board: [None; 64],
+            home: Home {
+                king: 1,
+                queen_rook: 0,
+                king_rook: 4,
+            },
+            ..Position::default()
+        };
+        position.castling.black_kingside = false;
+        position.castling.black_queenside = false;
+        position.board[sq("b1")] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[sq("a1")] = Some(ChessPiece::White(PieceVariant::Rook));
+        position.board[sq("e1")] = Some(ChessPiece::White(PieceVariant::Rook));
+        position.board[sq("h1")] = Some(ChessPiece::White(PieceVariant::Rook));
+        position.board[sq("g8")] = Some(ChessPiece::Black(PieceVariant::King));
+        // another rook further out means the castling rook is named by file
+        assert!(fen(&position).contains(" w EQ - "));
+        assert_eq!(san(&position, mv("b1", "e1")), "O-O");
+        assert_eq!(san(&position, mv("b1", "a1")), "O-O-O");
+    }
+
+    #[test]
+    fn crazyhouse_pockets_and_drops() {
+        let mut position = GameVariant::Crazyhouse.start();
+        for (from, to) in [("e2", "e4"), ("d7", "d5"), ("e4", "d5"), ("g8", "f6")] {
+            position.play(mv(from, to));
+        }
+        assert_eq!(
+            fen(&position),
+            "rnbqkb1r/ppp1pppp/5n2/3P4/8/8/PPPP1PPP/RNBQKBNR[P] w KQkq - 1 3"
+        );
+        assert_eq!(
+            san(&position, Move::drop(PieceVariant::Pawn, sq("e6"))),
+            "P@e6"
+        );
+        let mut position = Position {
+            pockets: Some([vec![PieceVariant::Knight], vec![]]),
+            ..Position::default()
+        };
+        position.play(mv("f2", "f3"));
+        position.play(mv("e7", "e5"));
+        position.play(mv("g2", "g4"));
+        assert_eq!(san(&position, mv("d8", "h4")), "Qh4+");
+    }
+
+    #[test]
+    fn pgn_has_tags_and_movetext() {
+        let record = GameRecord {
+            players: [Some("alice".to_owned()), None],
+            started_at: 1_682_899_200,
+            ended_at: Some(1_682_899_260),
+            result: Some(GameResult::BlackWins),
+            reason: Some("Checkmate".to_owned()),
+            ..record_from_uci(GameVariant::Standard, "f2f3 e7e5 g2g4 d8h4")
+        };
+        let chess960 = GameRecord {
+            variant: GameVariant::Chess960(Some(0)),
+            moves: vec![],
+            ..record.clone()
+        };
+        assert!(pgn(&chess960).contains(
+            "[Variant \"Chess960\"]\n[SetUp \"1\"]\n\
+             [FEN \"bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1\"]\n"
+        ));
+        let pgn = pgn(&record);
+        assert!(pgn.contains("[Date \"2023.05.01\"]\n"));
+        assert!(pgn.contains("[White \"alice\"]\n[Black \"?\"]\n[Result \"0-1\"]\n"));
+        assert!(pgn.ends_with("\n\n1. f3 e5 2. g4 Qh4# 0-1\n"));
+    }
+}
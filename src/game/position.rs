@@ -0,0 +1,1159 @@
+use serde::{Deserialize, Serialize};
+
+// board squares are indexed y * 8 + x with a1 = 0 and h8 = 63,
+// white starts on the low ranks and moves towards higher indices
+
+const KNIGHT_STEPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_STEPS: [(i8, i8); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+const PROMOTIONS: [PieceVariant; 4] = [
+    PieceVariant::Queen,
+    PieceVariant::Rook,
+    PieceVariant::Bishop,
+    PieceVariant::Knight,
+];
+// what can sit in a crazyhouse pocket, kings are never captured
+const POCKET_PIECES: [PieceVariant; 5] = [
+    PieceVariant::Pawn,
+    PieceVariant::Knight,
+    PieceVariant::Bishop,
+    PieceVariant::Rook,
+    PieceVariant::Queen,
+];
+
+// random numbers for zobrist keys. they come from a fixed seed so a
+// position has the same key in every build
+struct Zobrist {
+    // by side * 6 + variant, then square
+    pieces: [[u64; 64]; 12],
+    black_to_move: u64,
+    // white kingside, white queenside, black kingside, black queenside
+    castling: [u64; 4],
+    // by file
+    en_passant: [u64; 8],
+    // by side, variant and how many of it are in the pocket, less one
+    pockets: [[[u64; 16]; 6]; 2],
+}
+
+const fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+const ZOBRIST: Zobrist = {
+    let mut state = 0;
+    let mut pieces = [[0; 64]; 12];
+    let mut piece = 0;
+    while piece < 12 {
+        let mut square = 0;
+        while square < 64 {
+            pieces[piece][square] = splitmix64(&mut state);
+            square += 1;
+        }
+        piece += 1;
+    }
+    let black_to_move = splitmix64(&mut state);
+    let mut castling = [0; 4];
+    let mut i = 0;
+    while i < 4 {
+        castling[i] = splitmix64(&mut state);
+        i += 1;
+    }
+    let mut en_passant = [0; 8];
+    let mut i = 0;
+    while i < 8 {
+        en_passant[i] = splitmix64(&mut state);
+        i += 1;
+    }
+    let mut pockets = [[[0; 16]; 6]; 2];
+    let mut side = 0;
+    while side < 2 {
+        let mut variant = 0;
+        while variant < 6 {
+            let mut count = 0;
+            while count < 16 {
+                pockets[side][variant][count] = splitmix64(&mut state);
+                count += 1;
+            }
+            variant += 1;
+        }
+        side += 1;
+    }
+    Zobrist {
+        pieces,
+        black_to_move,
+        castling,
+        en_passant,
+        pockets,
+    }
+};
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, PartialOrd, Clone, Copy, Debug, Hash)]
+pub enum PieceVariant {
+    Bishop,
+    King,
+    Knight,
+    Pawn,
+    Queen,
+    Rook,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, PartialOrd, Clone, Copy, Debug, Hash)]
+pub enum ChessPiece {
+    White(PieceVariant),
+    Black(PieceVariant),
+}
+
+impl ChessPiece {
+    pub fn new(side: usize, variant: PieceVariant) -> Self {
+        match side {
+            0 => ChessPiece::White(variant),
+            _ => ChessPiece::Black(variant),
+        }
+    }
+
+    // index of the owning player, white moves first
+    pub fn side(&self) -> usize {
+        match self {
+            ChessPiece::White(_) => 0,
+            ChessPiece::Black(_) => 1,
+        }
+    }
+
+    pub fn variant(&self) -> PieceVariant {
+        match self {
+            ChessPiece::White(variant) | ChessPiece::Black(variant) => *variant,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CastlingRights {
+    pub white_kingside: bool,
+    pub white_queenside: bool,
+    pub black_kingside: bool,
+    pub black_queenside: bool,
+}
+
+impl Default for CastlingRights {
+    fn default() -> Self {
+        Self {
+            white_kingside: true,
+            white_queenside: true,
+            black_kingside: true,
+            black_queenside: true,
+        }
+    }
+}
+
+impl CastlingRights {
+    fn kingside(&self, side: usize) -> bool {
+        match side {
+            0 => self.white_kingside,
+            _ => self.black_kingside,
+        }
+    }
+
+    fn queenside(&self, side: usize) -> bool {
+        match side {
+            0 => self.white_queenside,
+            _ => self.black_queenside,
+        }
+    }
+}
+
+// castling is a king move of two squares, the rook follows implicitly. in
+// chess960 positions the king instead moves onto the rook it castles with,
+// which stays unambiguous however close the two start. a crazyhouse drop is
+// a move from the target square to itself, with the dropped piece in promotion
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Move {
+    pub from: usize,
+    pub to: usize,
+    pub promotion: Option<PieceVariant>,
+}
+
+impl Move {
+    pub fn drop(piece: PieceVariant, to: usize) -> Self {
+        Self {
+            from: to,
+            to,
+            promotion: Some(piece),
+        }
+    }
+
+    pub fn dropped(&self) -> Option<PieceVariant> {
+        self.promotion.filter(|_| self.from == self.to)
+    }
+}
+
+// what actually changed on the board, so the game can tell the clients
+pub struct Applied {
+    pub piece: ChessPiece,
+    // where the piece ended up, which for castling need not be the move's target
+    pub to: usize,
+    pub captured: Option<(usize, ChessPiece)>,
+    pub castle_rook: Option<(usize, usize)>,
+    pub promoted: Option<ChessPiece>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+    Checkmate { winner: usize },
+    Stalemate,
+    FiftyMoves,
+    InsufficientMaterial,
+    // a win only some variant's rules allow for
+    VariantWin { winner: usize, reason: &'static str },
+}
+
+fn offset(square: usize, dx: i8, dy: i8) -> Option<usize> {
+    let x = (square % 8) as i8 + dx;
+    let y = (square / 8) as i8 + dy;
+    match (0..8).contains(&x) && (0..8).contains(&y) {
+        true => Some((y * 8 + x) as usize),
+        false => None,
+    }
+}
+
+// rank the side's pawns start on, counted from white's side
+fn pawn_rank(side: usize) -> usize {
+    match side {
+        0 => 1,
+        _ => 6,
+    }
+}
+
+fn pawn_direction(side: usize) -> i8 {
+    match side {
+        0 => 1,
+        _ => -1,
+    }
+}
+
+// files the king and the two castling rooks start on, the same for both sides
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Home {
+    pub king: usize,
+    pub queen_rook: usize,
+    pub king_rook: usize,
+}
+
+impl Default for Home {
+    fn default() -> Self {
+        Self {
+            king: 4,
+            queen_rook: 0,
+            king_rook: 7,
+        }
+    }
+}
+
+impl Home {
+    // squares of the king and rooks that castling depends on for a side
+    fn squares(&self, side: usize) -> (usize, usize, usize) {
+        let base = side * 56;
+        (
+            base + self.king,
+            base + self.queen_rook,
+            base + self.king_rook,
+        )
+    }
+}
+
+// where a castling king and rook go, wherever they started
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Castle {
+    pub kingside: bool,
+    pub king: (usize, usize),
+    pub rook: (usize, usize),
+}
+
+// the knight pairs of the chess960 numbering scheme, as indices into the
+// five files left once bishops and queen are placed
+const KNIGHT_PAIRS: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+pub const CHESS960_POSITIONS: u16 = 960;
+// the index of the usual starting position
+pub const STANDARD_INDEX: u16 = 518;
+
+#[derive(Clone)]
+pub struct Position {
+    pub board: [Option<ChessPiece>; 64],
+    pub turn: usize,
+    pub castling: CastlingRights,
+    pub home: Home,
+    pub en_passant: Option<usize>,
+    // plies since the last capture or pawn move
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+    // pieces in hand for white and black, None outside crazyhouse
+    pub pockets: Option<[Vec<PieceVariant>; 2]>,
+    // squares holding promoted pieces, which go back to the pocket as pawns
+    pub promoted: u64,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        let back_rank = [
+            PieceVariant::Rook,
+            PieceVariant::Knight,
+            PieceVariant::Bishop,
+            PieceVariant::Queen,
+            PieceVariant::King,
+            PieceVariant::Bishop,
+            PieceVariant::Knight,
+            PieceVariant::Rook,
+        ];
+        let mut board = [None; 64];
+        for (file, variant) in back_rank.into_iter().enumerate() {
+            board[file] = Some(ChessPiece::White(variant));
+            board[8 + file] = Some(ChessPiece::White(PieceVariant::Pawn));
+            board[48 + file] = Some(ChessPiece::Black(PieceVariant::Pawn));
+            board[56 + file] = Some(ChessPiece::Black(variant));
+        }
+        Self {
+            board,
+            turn: 0,
+            castling: CastlingRights::default(),
+            home: Home::default(),
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            pockets: None,
+            promoted: 0,
+        }
+    }
+}
+
+impl Position {
+    // one of the 960 fischer random setups by its standard number, counted
+    // from 0. anything past the last is wrapped around
+    pub fn chess960(index: u16) -> Self {
+        let mut n = (index % CHESS960_POSITIONS) as usize;
+        let mut back_rank = [None; 8];
+        back_rank[(n % 4) * 2 + 1] = Some(PieceVariant::Bishop);
+        n /= 4;
+        back_rank[(n % 4) * 2] = Some(PieceVariant::Bishop);
+        n /= 4;
+        let free = |rank: &[Option<PieceVariant>; 8]| -> Vec<usize> {
+            (0..8).filter(|&file| rank[file].is_none()).collect()
+        };
+        back_rank[free(&back_rank)[n % 6]] = Some(PieceVariant::Queen);
+        n /= 6;
+        let (first, second) = KNIGHT_PAIRS[n];
+        let files = free(&back_rank);
+        back_rank[files[first]] = Some(PieceVariant::Knight);
+        back_rank[files[second]] = Some(PieceVariant::Knight);
+        // the king always ends up between the rooks
+        let [queen_rook, king, king_rook] = free(&back_rank)[..] else {
+            unreachable!("three files are left for the king and rooks");
+        };
+        back_rank[queen_rook] = Some(PieceVariant::Rook);
+        back_rank[king] = Some(PieceVariant::King);
+        back_rank[king_rook] = Some(PieceVariant::Rook);
+        let mut position = Position::default();
+        for (file, variant) in back_rank.into_iter().enumerate() {
+            let variant = variant.expect("every file is filled");
+            position.board[file] = Some(ChessPiece::White(variant));
+            position.board[56 + file] = Some(ChessPiece::Black(variant));
+        }
+        position.home = Home {
+            king,
+            queen_rook,
+            king_rook,
+        };
+        position
+    }
+
+    // whether castling is written as the king taking its own rook
+    pub fn chess960_castling(&self) -> bool {
+        self.home != Home::default()
+    }
+
+    // the castle a king move stands for, if it is one. only the squares are
+    // looked at, callers check the move is legal
+    pub fn castle(&self, mv: Move) -> Option<Castle> {
+        let piece = self.board[mv.from]?;
+        let side = piece.side();
+        let (king, queen_rook, king_rook) = self.home.squares(side);
+        if piece.variant() != PieceVariant::King || mv.from != king {
+            return None;
+        }
+        let kingside = match self.chess960_castling() {
+            true if self.board[mv.to] == Some(ChessPiece::new(side, PieceVariant::Rook)) => {
+                match mv.to {
+                    to if to == king_rook => true,
+                    to if to == queen_rook => false,
+                    _ => return None,
+                }
+            }
+            false if mv.from.abs_diff(mv.to) == 2 => mv.to > mv.from,
+            _ => return None,
+        };
+        let base = side * 56;
+        Some(match kingside {
+            true => Castle {
+                kingside,
+                king: (king, base + 6),
+                rook: (king_rook, base + 5),
+            },
+            false => Castle {
+                kingside,
+                king: (king, base + 2),
+                rook: (queen_rook, base + 3),
+            },
+        })
+    }
+
+    pub fn king_square(&self, side: usize) -> Option<usize> {
+        self.board
+            .iter()
+            .position(|p| *p == Some(ChessPiece::new(side, PieceVariant::King)))
+    }
+
+    // whether any piece of `by` could capture on `square`
+    pub fn is_attacked(&self, square: usize, by: usize) -> bool {
+        let holds = |sq: Option<usize>, variants: &[PieceVariant]| {
+            sq.and_then(|sq| self.board[sq])
+                .is_some_and(|p| p.side() == by && variants.contains(&p.variant()))
+        };
+        // a pawn attacks the square if it sits diagonally behind it from its own point of view
+        let back = -pawn_direction(by);
+        if holds(offset(square, 1, back), &[PieceVariant::Pawn])
+            || holds(offset(square, -1, back), &[PieceVariant::Pawn])
+        {
+            return true;
+        }
+        if KNIGHT_STEPS
+            .iter()
+            .any(|&(dx, dy)| holds(offset(square, dx, dy), &[PieceVariant::Knight]))
+        {
+            return true;
+        }
+        if KING_STEPS
+            .iter()
+            .any(|&(dx, dy)| holds(offset(square, dx, dy), &[PieceVariant::King]))
+        {
+            return true;
+        }
+        let sliders = [
+            (ROOK_DIRECTIONS, [PieceVariant::Rook, PieceVariant::Queen]),
+            (BISHOP_DIRECTIONS, [PieceVariant::Bishop, PieceVariant::Queen]),
+        ];
+        for (directions, variants) in sliders {
+            for (dx, dy) in directions {
+                let mut current = square;
+                while let Some(next) = offset(current, dx, dy) {
+                    if let Some(piece) = self.board[next] {
+                        if piece.side() == by && variants.contains(&piece.variant()) {
+                            return true;
+                        }
+                        break;
+                    }
+                    current = next;
+                }
+            }
+        }
+        false
+    }
+
+    pub fn in_check(&self) -> bool {
+        self.king_square(self.turn)
+            .is_some_and(|king| self.is_attacked(king, 1 - self.turn))
+    }
+
+    fn push_pawn_move(&self, moves: &mut Vec<Move>, from: usize, to: usize) {
+        if to / 8 == 0 || to / 8 == 7 {
+            for promotion in PROMOTIONS {
+                moves.push(Move {
+                    from,
+                    to,
+                    promotion: Some(promotion),
+                });
+            }
+        } else {
+            moves.push(Move {
+                from,
+                to,
+                promotion: None,
+            });
+        }
+    }
+
+    fn castling_moves(&self, moves: &mut Vec<Move>) {
+        let side = self.turn;
+        let (king, queen_rook, king_rook) = self.home.squares(side);
+        if self.board[king] != Some(ChessPiece::new(side, PieceVariant::King))
+            || self.is_attacked(king, 1 - side)
+        {
+            return;
+        }
+        let rook = Some(ChessPiece::new(side, PieceVariant::Rook));
+        for (allowed, rook_square) in [
+            (self.castling.kingside(side), king_rook),
+            (self.castling.queenside(side), queen_rook),
+        ] {
+            if !allowed || self.board[rook_square] != rook {
+                continue;
+            }
+            let mv = Move {
+                from: king,
+                to: match self.chess960_castling() {
+                    true => rook_square,
+                    false if rook_square > king => king + 2,
+                    false => king - 2,
+                },
+                promotion: None,
+            };
+            let Some(castle) = self.castle(mv) else {
+                continue;
+            };
+            // everything the king and rook cross has to be empty but for the two
+            // of them, and the king may not pass through check
+            let (king_to, rook_to) = (castle.king.1, castle.rook.1);
+            let low = king.min(king_to).min(rook_square).min(rook_to);
+            let high = king.max(king_to).max(rook_square).max(rook_to);
+            let clear =
+                (low..=high).all(|sq| sq == king || sq == rook_square || self.board[sq].is_none());
+            let safe = (king.min(king_to)..=king.max(king_to))
+                .all(|sq| sq == king || !self.is_attacked(sq, 1 - side));
+            if clear && safe {
+                moves.push(mv);
+            }
+        }
+    }
+
+    // moves that follow each piece's pattern, ignoring whether they expose the king
+    pub fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let mut moves = vec![];
+        let side = self.turn;
+        for from in 0..64 {
+            let piece = match self.board[from] {
+                Some(piece) if piece.side() == side => piece,
+                _ => continue,
+            };
+            let mut step = |to: usize| match self.board[to] {
+                Some(target) if target.side() == side => false,
+                target => {
+                    moves.push(Move {
+                        from,
+                        to,
+                        promotion: None,
+                    });
+                    target.is_none()
+                }
+            };
+            match piece.variant() {
+                PieceVariant::Knight => KNIGHT_STEPS.iter().for_each(|&(dx, dy)| {
+                    offset(from, dx, dy).map(&mut step);
+                }),
+                PieceVariant::King => KING_STEPS.iter().for_each(|&(dx, dy)| {
+                    offset(from, dx, dy).map(&mut step);
+                }),
+                PieceVariant::Pawn => {}
+                slider => {
+                    let directions: &[(i8, i8)] = match slider {
+                        PieceVariant::Rook => &ROOK_DIRECTIONS,
+                        PieceVariant::Bishop => &BISHOP_DIRECTIONS,
+                        _ => &KING_STEPS,
+                    };
+                    for &(dx, dy) in directions {
+                        let mut current = from;
+                        while let Some(next) = offset(current, dx, dy) {
+                            if !step(next) {
+                                break;
+                            }
+                            current = next;
+                        }
+                    }
+                }
+            }
+            if piece.variant() == PieceVariant::Pawn {
+                let direction = pawn_direction(side);
+                if let Some(ahead) = offset(from, 0, direction) {
+                    if self.board[ahead].is_none() {
+                        self.push_pawn_move(&mut moves, from, ahead);
+                        if from / 8 == pawn_rank(side) {
+                            let double = offset(ahead, 0, direction).unwrap();
+                            if self.board[double].is_none() {
+                                moves.push(Move {
+                                    from,
+                                    to: double,
+                                    promotion: None,
+                                });
+                            }
+                        }
+                    }
+                }
+                for dx in [-1, 1] {
+                    if let Some(to) = offset(from, dx, direction) {
+                        let enemy = self.board[to].is_some_and(|p| p.side() != side);
+                        if enemy || self.en_passant == Some(to) {
+                            self.push_pawn_move(&mut moves, from, to);
+                        }
+                    }
+                }
+            }
+        }
+        self.castling_moves(&mut moves);
+        self.drop_moves(&mut moves);
+        moves
+    }
+
+    // pieces in hand go on any empty square, pawns not on the first or last rank
+    fn drop_moves(&self, moves: &mut Vec<Move>) {
+        let Some(pockets) = &self.pockets else {
+            return;
+        };
+        let pocket = &pockets[self.turn];
+        for piece in POCKET_PIECES.into_iter().filter(|p| pocket.contains(p)) {
+            for to in (0..64).filter(|&to| self.board[to].is_none()) {
+                if piece == PieceVariant::Pawn && (to / 8 == 0 || to / 8 == 7) {
+                    continue;
+                }
+                moves.push(Move::drop(piece, to));
+            }
+        }
+    }
+
+    // whether a pseudo legal move keeps the mover's king out of check
+    pub fn is_legal(&self, mv: Move) -> bool {
+        let mut after = self.clone();
+        after.play(mv);
+        after
+            .king_square(self.turn)
+            .is_none_or(|king| !after.is_attacked(king, after.turn))
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        self.pseudo_legal_moves()
+            .into_iter()
+            .filter(|mv| self.is_legal(*mv))
+            .collect()
+    }
+
+    // a king or rook leaving its square, or a rook being captured on it,
+    // gives up castling on that side for good
+    fn update_castling_rights(&mut self, from: usize, to: usize) {
+        for side in [0, 1] {
+            let (king, queen_rook, king_rook) = self.home.squares(side);
+            for square in [from, to] {
+                if square == king || square == king_rook {
+                    match side {
+                        0 => self.castling.white_kingside = false,
+                        _ => self.castling.black_kingside = false,
+                    }
+                }
+                if square == king || square == queen_rook {
+                    match side {
+                        0 => self.castling.white_queenside = false,
+                        _ => self.castling.black_queenside = false,
+                    }
+                }
+            }
+        }
+    }
+
+    // applies a move without checking it, callers validate against legal_moves first
+    pub fn play(&mut self, mv: Move) -> Applied {
+        if let Some(variant) = mv.dropped() {
+            return self.play_drop(variant, mv.to);
+        }
+        let piece = self.board[mv.from].expect("no piece on the origin square");
+        let mut captured = None;
+        let mut castle_rook = None;
+        let mut promoted = None;
+        let mut to = mv.to;
+        if let Some(castle) = self.castle(mv) {
+            // both leave before either lands, they may well swap squares
+            let rook = self.board[castle.rook.0].take();
+            self.board[mv.from] = None;
+            self.board[castle.rook.1] = rook;
+            to = castle.king.1;
+            castle_rook = Some(castle.rook);
+        } else {
+            captured = self.board[mv.to].map(|p| (mv.to, p));
+            if piece.variant() == PieceVariant::Pawn
+                && Some(mv.to) == self.en_passant
+                && captured.is_none()
+            {
+                let victim = offset(mv.to, 0, -pawn_direction(piece.side())).unwrap();
+                captured = self.board[victim].take().map(|p| (victim, p));
+            }
+            self.board[mv.from] = None;
+        }
+        self.board[to] = Some(piece);
+        if let Some((at, taken)) = captured {
+            let back = match self.promoted & (1 << at) != 0 {
+                true => PieceVariant::Pawn,
+                false => taken.variant(),
+            };
+            if let Some(pockets) = &mut self.pockets {
+                pockets[piece.side()].push(back);
+            }
+        }
+        let was_promoted = self.promoted & (1 << mv.from) != 0;
+        self.promoted &= !(1 << mv.from | 1 << mv.to);
+        if was_promoted || mv.promotion.is_some() {
+            self.promoted |= 1 << to;
+        }
+        if let Some(variant) = mv.promotion {
+            let piece = ChessPiece::new(piece.side(), variant);
+            self.board[to] = Some(piece);
+            promoted = Some(piece);
+        }
+        self.en_passant = match piece.variant() {
+            PieceVariant::Pawn if mv.from.abs_diff(mv.to) == 16 => Some((mv.from + mv.to) / 2),
+            _ => None,
+        };
+        self.update_castling_rights(mv.from, mv.to);
+        if piece.variant() == PieceVariant::Pawn || captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        self.pass_turn();
+        Applied {
+            piece,
+            to,
+            captured,
+            castle_rook,
+            promoted,
+        }
+    }
+
+    fn play_drop(&mut self, variant: PieceVariant, to: usize) -> Applied {
+        let piece = ChessPiece::new(self.turn, variant);
+        if let Some(pockets) = &mut self.pockets {
+            let pocket = &mut pockets[self.turn];
+            if let Some(index) = pocket.iter().position(|p| *p == variant) {
+                pocket.remove(index);
+            }
+        }
+        self.board[to] = Some(piece);
+        self.en_passant = None;
+        self.halfmove_clock += 1;
+        self.pass_turn();
+        Applied {
+            piece,
+            to,
+            captured: None,
+            castle_rook: None,
+            promoted: None,
+        }
+    }
+
+    fn pass_turn(&mut self) {
+        if self.turn == 1 {
+            self.fullmove_number += 1;
+        }
+        self.turn = 1 - self.turn;
+    }
+
+    fn insufficient_material(&self) -> bool {
+        if let Some(pockets) = &self.pockets {
+            if pockets.iter().any(|pocket| !pocket.is_empty()) {
+                return false;
+            }
+        }
+        let mut minors = 0;
+        for piece in self.board.iter().flatten() {
+            match piece.variant() {
+                PieceVariant::King => {}
+                PieceVariant::Bishop | PieceVariant::Knight => minors += 1,
+                _ => return false,
+            }
+        }
+        minors <= 1
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        if self.legal_moves().is_empty() {
+            return Some(match self.in_check() {
+                true => Outcome::Checkmate {
+                    winner: 1 - self.turn,
+                },
+                false => Outcome::Stalemate,
+            });
+        }
+        if self.halfmove_clock >= 100 {
+            return Some(Outcome::FiftyMoves);
+        }
+        if self.insufficient_material() {
+            return Some(Outcome::InsufficientMaterial);
+        }
+        None
+    }
+
+    // the same for every way of reaching the position, move counters aside.
+    // an en passant square only counts while a pawn can actually take there
+    pub fn key(&self) -> u64 {
+        let mut key = 0;
+        for (square, piece) in self.board.iter().enumerate() {
+            if let Some(piece) = piece {
+                key ^= ZOBRIST.pieces[piece.side() * 6 + piece.variant() as usize][square];
+            }
+        }
+        if self.turn == 1 {
+            key ^= ZOBRIST.black_to_move;
+        }
+        let castling = &self.castling;
+        let rights = [
+            castling.white_kingside,
+            castling.white_queenside,
+            castling.black_kingside,
+            castling.black_queenside,
+        ];
+        for (right, zobrist) in rights.into_iter().zip(ZOBRIST.castling) {
+            if right {
+                key ^= zobrist;
+            }
+        }
+        if let Some(square) = self
+            .en_passant
+            .filter(|&square| self.can_take_en_passant(square))
+        {
+            key ^= ZOBRIST.en_passant[square % 8];
+        }
+        if let Some(pockets) = &self.pockets {
+            for (side, pocket) in pockets.iter().enumerate() {
+                let mut counts = [0; 6];
+                for &piece in pocket {
+                    counts[piece as usize] += 1;
+                }
+                for (variant, count) in counts.into_iter().enumerate() {
+                    if count > 0 {
+                        key ^= ZOBRIST.pockets[side][variant][(count - 1).min(15)];
+                    }
+                }
+            }
+        }
+        key
+    }
+
+    fn can_take_en_passant(&self, square: usize) -> bool {
+        let pawn = Some(ChessPiece::new(self.turn, PieceVariant::Pawn));
+        // the capturing pawns stand beside the one that just moved past
+        let rank = match self.turn {
+            0 => square - 8,
+            _ => square + 8,
+        };
+        let file = square % 8;
+        (file > 0 && self.board[rank - 1] == pawn) || (file < 7 && self.board[rank + 1] == pawn)
+    }
+}
+
+// legal destinations grouped by the square they start from
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SquareMoves {
+    pub from: usize,
+    pub to: Vec<usize>,
+}
+
+pub fn group_by_square(moves: &[Move]) -> Vec<SquareMoves> {
+    let mut grouped: Vec<SquareMoves> = vec![];
+    for mv in moves {
+        match grouped.iter_mut().find(|g| g.from == mv.from) {
+            Some(group) => {
+                // promotions share a destination, clients pick the piece themselves
+                if !group.to.contains(&mv.to) {
+                    group.to.push(mv.to);
+                }
+            }
+            None => grouped.push(SquareMoves {
+                from: mv.from,
+                to: vec![mv.to],
+            }),
+        }
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::notation::parse_fen;
+
+    fn perft(position: &Position, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        position
+            .legal_moves()
+            .into_iter()
+            .map(|mv| {
+                let mut next = position.clone();
+                next.play(mv);
+                perft(&next, depth - 1)
+            })
+            .sum()
+    }
+
+    fn empty() -> Position {
+        Position {
+            board: [None; 64],
+            castling: CastlingRights {
+                white_kingside: false,
+                white_queenside: false,
+                black_kingside: false,
+                black_queenside: false,
+            },
+            ..Position::default()
+        }
+    }
+
+    const START_KEY: u64 = 0x8f95_34d1_cb1a_3d27;
+
+    fn mv(from: usize, to: usize) -> Move {
+        Move {
+            from,
+            to,
+            promotion: None,
+        }
+    }
+
+    #[test]
+    fn perft_from_the_start_position() {
+        let start = Position::default();
+        assert_eq!(perft(&start, 1), 20);
+        assert_eq!(perft(&start, 2), 400);
+        assert_eq!(perft(&start, 3), 8902);
+    }
+
+    fn perft_from(fen: &str, counts: &[u64]) {
+        let position = parse_fen(fen).unwrap();
+        for (depth, &count) in counts.iter().enumerate() {
+            assert_eq!(
+                perft(&position, depth as u32 + 1),
+                count,
+                "{fen} depth {}",
+                depth + 1
+            );
+        }
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        perft_from(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862],
+        );
+    }
+
+    // en passant captures that would expose the king along the rank
+    #[test]
+    fn perft_en_passant_and_pins() {
+        perft_from(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2812, 43238],
+        );
+    }
+
+    // promotions with captures, and castling rights lost to a rook capture
+    #[test]
+    fn perft_promotions_and_castling_rights() {
+        perft_from(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467],
+        );
+        perft_from(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379],
+        );
+    }
+
+    #[test]
+    fn keys_follow_the_position_not_the_moves() {
+        let play = |moves: &[(usize, usize)]| {
+            let mut position = Position::default();
+            for &(from, to) in moves {
+                position.play(mv(from, to));
+            }
+            position
+        };
+        // 1. Nf3 Nf6 2. Nc3 and 1. Nc3 Nf6 2. Nf3
+        let one = play(&[(6, 21), (62, 45), (1, 18)]);
+        let other = play(&[(1, 18), (62, 45), (6, 21)]);
+        assert_eq!(one.key(), other.key());
+        assert_ne!(one.key(), Position::default().key());
+        // 1. e4 leaves an en passant square no black pawn can use
+        let e4 = play(&[(12, 28)]);
+        assert_eq!(e4.en_passant, Some(20));
+        let mut without = e4.clone();
+        without.en_passant = None;
+        assert_eq!(e4.key(), without.key());
+        // the keys are fixed, not seeded per process
+        assert_eq!(Position::default().key(), START_KEY);
+    }
+
+    #[test]
+    fn en_passant_removes_the_passed_pawn() {
+        let mut position = empty();
+        position.board[4] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[60] = Some(ChessPiece::Black(PieceVariant::King));
+        position.board[36] = Some(ChessPiece::White(PieceVariant::Pawn));
+        position.board[51] = Some(ChessPiece::Black(PieceVariant::Pawn));
+        position.turn = 1;
+        position.play(mv(51, 35));
+        assert_eq!(position.en_passant, Some(43));
+        assert!(position.legal_moves().contains(&mv(36, 43)));
+        let applied = position.play(mv(36, 43));
+        assert_eq!(
+            applied.captured,
+            Some((35, ChessPiece::Black(PieceVariant::Pawn)))
+        );
+        assert!(position.board[35].is_none());
+    }
+
+    #[test]
+    fn cannot_castle_through_an_attacked_square() {
+        let mut position = empty();
+        position.castling = CastlingRights::default();
+        position.board[4] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[7] = Some(ChessPiece::White(PieceVariant::Rook));
+        position.board[0] = Some(ChessPiece::White(PieceVariant::Rook));
+        position.board[60] = Some(ChessPiece::Black(PieceVariant::King));
+        position.board[61] = Some(ChessPiece::Black(PieceVariant::Rook));
+        let moves = position.legal_moves();
+        assert!(!moves.contains(&mv(4, 6)));
+        assert!(moves.contains(&mv(4, 2)));
+        let applied = position.play(mv(4, 2));
+        assert_eq!(applied.castle_rook, Some((0, 3)));
+        assert!(!position.castling.white_kingside);
+    }
+
+    #[test]
+    fn numbers_chess960_setups() {
+        let back_rank = |position: &Position| -> String {
+            (0..8)
+                .map(|file| match position.board[file].unwrap().variant() {
+                    PieceVariant::Bishop => 'B',
+                    PieceVariant::King => 'K',
+                    PieceVariant::Knight => 'N',
+                    PieceVariant::Pawn => 'P',
+                    PieceVariant::Queen => 'Q',
+                    PieceVariant::Rook => 'R',
+                })
+                .collect()
+        };
+        assert_eq!(back_rank(&Position::chess960(0)), "BBQNNRKR");
+        assert_eq!(back_rank(&Position::chess960(959)), "RKRNNQBB");
+        let standard = Position::chess960(STANDARD_INDEX);
+        assert_eq!(standard.board, Position::default().board);
+        assert!(!standard.chess960_castling());
+        let setups: std::collections::HashSet<String> = (0..CHESS960_POSITIONS)
+            .map(|index| back_rank(&Position::chess960(index)))
+            .collect();
+        assert_eq!(setups.len(), CHESS960_POSITIONS as usize);
+    }
+
+    #[test]
+    fn chess960_king_and_rook_can_swap() {
+        let mut position = empty();
+        position.home = Home {
+            king: 6,
+            queen_rook: 1,
+            king_rook: 5,
+        };
+        position.castling.white_kingside = true;
+        position.board[6] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[5] = Some(ChessPiece::White(PieceVariant::Rook));
+        position.board[60] = Some(ChessPiece::Black(PieceVariant::King));
+        let moves = position.legal_moves();
+        assert!(moves.contains(&mv(6, 5)));
+        let applied = position.play(mv(6, 5));
+        assert_eq!(applied.to, 6);
+        assert_eq!(
+            position.board[6],
+            Some(ChessPiece::White(PieceVariant::King))
+        );
+        assert_eq!(
+            position.board[5],
+            Some(ChessPiece::White(PieceVariant::Rook))
+        );
+        assert!(!position.castling.white_kingside);
+    }
+
+    #[test]
+    fn pinned_pieces_cannot_move() {
+        let mut position = empty();
+        position.board[4] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[12] = Some(ChessPiece::White(PieceVariant::Knight));
+        position.board[60] = Some(ChessPiece::Black(PieceVariant::Rook));
+        position.board[63] = Some(ChessPiece::Black(PieceVariant::King));
+        assert!(position.legal_moves().iter().all(|m| m.from != 12));
+    }
+
+    #[test]
+    fn detects_back_rank_mate_and_stalemate() {
+        let mut position = empty();
+        position.board[6] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[13] = Some(ChessPiece::White(PieceVariant::Pawn));
+        position.board[14] = Some(ChessPiece::White(PieceVariant::Pawn));
+        position.board[15] = Some(ChessPiece::White(PieceVariant::Pawn));
+        position.board[0] = Some(ChessPiece::Black(PieceVariant::Rook));
+        position.board[60] = Some(ChessPiece::Black(PieceVariant::King));
+        assert_eq!(position.outcome(), Some(Outcome::Checkmate { winner: 1 }));
+
+        let mut position = empty();
+        position.board[56] = Some(ChessPiece::Black(PieceVariant::King));
+        position.board[50] = Some(ChessPiece::White(PieceVariant::Queen));
+        position.board[0] = Some(ChessPiece::White(PieceVariant::King));
+        position.turn = 1;
+        assert_eq!(position.outcome(), Some(Outcome::Stalemate));
+    }
+
+    #[test]
+    fn groups_promotions_under_one_destination() {
+        let mut position = empty();
+        position.board[4] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[63] = Some(ChessPiece::Black(PieceVariant::King));
+        position.board[48] = Some(ChessPiece::White(PieceVariant::Pawn));
+        let moves: Vec<Move> = position
+            .legal_moves()
+            .into_iter()
+            .filter(|m| m.from == 48)
+            .collect();
+        assert_eq!(moves.len(), 4);
+        assert_eq!(
+            group_by_square(&moves),
+            vec![SquareMoves {
+                from: 48,
+                to: vec![56]
+            }]
+        );
+    }
+}
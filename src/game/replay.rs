@@ -0,0 +1,224 @@
+use serde::Serialize;
+
+use crate::message::Color;
+
+use super::{
+    notation::{fen, san_by},
+    position::{ChessPiece, PieceVariant, Position},
+    GameRecord, GameVariant, MoveError, MoveRecord, Variant,
+};
+
+// the position reached by playing recorded moves from the start
+pub fn replay(start: Position, moves: &[MoveRecord]) -> Position {
+    let mut position = start;
+    for record in moves {
+        position.play(record.as_move());
+    }
+    position
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum ReplayError {
+    // asked for a ply past the end of the game
+    NoSuchPly { plies: usize },
+    // the stored move list does not hold up under the game's rules
+    IllegalMove { ply: usize, error: MoveError },
+}
+
+// the board after some number of plies, as a replay shows it
+#[derive(Serialize, Clone, Debug)]
+pub struct ReplayPly {
+    pub ply: usize,
+    // plies in the whole game
+    pub plies: usize,
+    // the move that led here, None at the start
+    pub last_move: Option<MoveRecord>,
+    pub san: Option<String>,
+    pub fen: String,
+    pub board: Vec<Option<ChessPiece>>,
+    pub turn: Color,
+    pub check: bool,
+    pub pockets: Option<[Vec<PieceVariant>; 2]>,
+}
+
+// a finished game played back from its move list. every move goes through
+// the variant's rules as it did when it was made, so nothing but the moves
+// has to be stored
+pub struct Replay {
+    rules: Box<dyn Variant>,
+    position: Position,
+    moves: Vec<MoveRecord>,
+    ply: usize,
+    san: Option<String>,
+}
+
+impl Replay {
+    pub fn new(variant: GameVariant, moves: Vec<MoveRecord>) -> Self {
+        let rules = variant.rules();
+        Self {
+            position: rules.start(),
+            rules,
+            moves,
+            ply: 0,
+            san: None,
+        }
+    }
+
+    pub fn plies(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.ply == self.plies()
+    }
+
+    pub fn current(&self) -> ReplayPly {
+        ReplayPly {
+            ply: self.ply,
+            plies: self.plies(),
+            last_move: self.ply.checked_sub(1).map(|last| self.moves[last]),
+            san: self.san.clone(),
+            fen: fen(&self.position),
+            board: self.position.board.to_vec(),
+            turn: Color::from_index(self.position.turn),
+            check: self.rules.in_check(&self.position),
+            pockets: self.position.pockets.clone(),
+        }
+    }
+
+    // plays the next move, false once there are none left
+    pub fn advance(&mut self) -> Result<bool, ReplayError> {
+        let Some(&record) = self.moves.get(self.ply) else {
+            return Ok(false);
+        };
+        let illegal = |error| ReplayError::IllegalMove {
+            ply: self.ply + 1,
+            error,
+        };
+        let mv = record.as_move();
+        if let Some(piece) = mv.dropped() {
+            let in_pocket = self
+                .position
+                .pockets
+                .as_ref()
+                .is_some_and(|pockets| pockets[self.position.turn].contains(&piece));
+            if !in_pocket {
+                return Err(illegal(MoveError::NotInPocket));
+            }
+        }
+        self.rules.check_move(&self.position, mv).map_err(illegal)?;
+        let san = san_by(self.rules.as_ref(), &self.position, mv);
+        let applied = self.position.play(mv);
+        if applied.piece != record.piece {
+            return Err(illegal(MoveError::PieceMismatch));
+        }
+        self.rules.played(&self.position);
+        self.san = Some(san);
+        self.ply += 1;
+        Ok(true)
+    }
+
+    // moves forward to the given ply
+    pub fn seek(&mut self, ply: usize) -> Result<(), ReplayError> {
+        if ply > self.plies() {
+            return Err(ReplayError::NoSuchPly {
+                plies: self.plies(),
+            });
+        }
+        while self.ply < ply {
+            self.advance()?;
+        }
+        Ok(())
+    }
+}
+
+// the position after the given number of plies of a stored game
+pub fn position_at(record: &GameRecord, ply: usize) -> Result<ReplayPly, ReplayError> {
+    let mut replay = Replay::new(record.variant, record.moves.clone());
+    replay.seek(ply)?;
+    Ok(replay.current())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::record_from_uci;
+
+    const FOOLS_MATE: &str = "f2f3 e7e5 g2g4 d8h4";
+
+    #[test]
+    fn any_ply_of_a_game() {
+        let game = record_from_uci(GameVariant::Standard, FOOLS_MATE);
+        let start = position_at(&game, 0).unwrap();
+        assert_eq!(start.fen, fen(&Position::default()));
+        assert_eq!((start.last_move, start.san), (None, None));
+        let second = position_at(&game, 2).unwrap();
+        assert_eq!(second.san.as_deref(), Some("e5"));
+        assert_eq!(second.turn, Color::White);
+        let mate = position_at(&game, 4).unwrap();
+        assert_eq!(mate.san.as_deref(), Some("Qh4#"));
+        assert!(mate.check);
+        assert_eq!(mate.plies, 4);
+        assert_eq!(
+            position_at(&game, 5).unwrap_err(),
+            ReplayError::NoSuchPly { plies: 4 }
+        );
+    }
+
+    #[test]
+    fn rejects_moves_the_rules_do_not_allow() {
+        let mut game = record_from_uci(GameVariant::Standard, "f2f3 e7e5");
+        // the king cannot leave e1 for e2 while the pawn still stands there
+        game.moves.push(MoveRecord {
+            piece: ChessPiece::new(0, PieceVariant::King),
+            from: 4,
+            to: 12,
+            captured: None,
+            promotion: None,
+        });
+        assert!(position_at(&game, 2).is_ok());
+        assert!(matches!(
+            position_at(&game, 3),
+            Err(ReplayError::IllegalMove { ply: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn drops_come_out_of_the_pockets() {
+        // e4 d5 exd5 Qxd5 P@e4
+        let mut game = record_from_uci(GameVariant::Crazyhouse, "e2e4 d7d5 e4d5 d8d5");
+        game.moves.push(MoveRecord {
+            piece: ChessPiece::new(0, PieceVariant::Pawn),
+            from: 28,
+            to: 28,
+            captured: None,
+            promotion: Some(PieceVariant::Pawn),
+        });
+        let taken = position_at(&game, 3).unwrap();
+        assert_eq!(taken.pockets, Some([vec![PieceVariant::Pawn], vec![]]));
+        let dropped = position_at(&game, 5).unwrap();
+        assert_eq!(dropped.san.as_deref(), Some("P@e4"));
+        assert_eq!(dropped.pockets, Some([vec![], vec![PieceVariant::Pawn]]));
+        // after Nf6 white drops again, with nothing left to come out of
+        game.moves.push(MoveRecord {
+            piece: ChessPiece::new(1, PieceVariant::Knight),
+            from: 62,
+            to: 45,
+            captured: None,
+            promotion: None,
+        });
+        game.moves.push(MoveRecord {
+            from: 32,
+            to: 32,
+            ..game.moves[4]
+        });
+        assert!(position_at(&game, 6).is_ok());
+        assert_eq!(
+            position_at(&game, 7).unwrap_err(),
+            ReplayError::IllegalMove {
+                ply: 7,
+                error: MoveError::NotInPocket
+            }
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use std::{collections::hash_map::RandomState, hash::BuildHasher, mem, time::Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    position::{Move, Outcome, PieceVariant, Position, CHESS960_POSITIONS, STANDARD_INDEX},
+    MoveError,
+};
+
+// the rules a game is played by, picked when it is set up
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum GameVariant {
+    #[default]
+    Standard,
+    // fischer random by the number of its setup, from 0 to 959. asking
+    // without a number gets a random one once the game starts
+    Chess960(Option<u16>),
+    KingOfTheHill,
+    ThreeCheck,
+    Antichess,
+    Crazyhouse,
+}
+
+impl GameVariant {
+    // settles a random setup on an actual one
+    pub fn resolve(self) -> Self {
+        match self {
+            GameVariant::Chess960(None) => {
+                // std has no rng, but every RandomState is freshly keyed
+                let random = RandomState::new().hash_one(Instant::now());
+                GameVariant::Chess960(Some((random % u64::from(CHESS960_POSITIONS)) as u16))
+            }
+            GameVariant::Chess960(Some(index)) => {
+                GameVariant::Chess960(Some(index % CHESS960_POSITIONS))
+            }
+            other => other,
+        }
+    }
+
+    // a fresh set of rules for one game
+    pub fn rules(self) -> Box<dyn Variant> {
+        match self {
+            GameVariant::Standard => Box::new(Standard(Position::default())),
+            GameVariant::Chess960(index) => Box::new(Standard(Position::chess960(
+                index.unwrap_or(STANDARD_INDEX),
+            ))),
+            GameVariant::KingOfTheHill => Box::new(KingOfTheHill),
+            GameVariant::ThreeCheck => Box::new(ThreeCheck::default()),
+            GameVariant::Antichess => Box::new(Antichess),
+            GameVariant::Crazyhouse => Box::new(Crazyhouse),
+        }
+    }
+
+    pub fn start(self) -> Position {
+        self.rules().start()
+    }
+
+    // the variant's name as pgn tags spell it
+    pub fn name(self) -> &'static str {
+        match self {
+            GameVariant::Standard => "Standard",
+            GameVariant::Chess960(_) => "Chess960",
+            GameVariant::KingOfTheHill => "King of the Hill",
+            GameVariant::ThreeCheck => "Three-check",
+            GameVariant::Antichess => "Antichess",
+            GameVariant::Crazyhouse => "Crazyhouse",
+        }
+    }
+
+    // ratings are pooled by time control alone, so only standard chess
+    // counts towards them
+    pub fn rated(self) -> bool {
+        self == GameVariant::Standard
+    }
+
+    // whether only the setup differs from standard chess, so that any uci
+    // engine can play and judge it
+    pub fn standard_rules(self) -> bool {
+        matches!(self, GameVariant::Standard | GameVariant::Chess960(_))
+    }
+
+    // the same variant, whatever setup either picked
+    pub fn same_rules(self, other: Self) -> bool {
+        mem::discriminant(&self) == mem::discriminant(&other)
+    }
+
+    // what two seeks agree on, if they can be paired at all
+    pub fn pairs_with(self, other: Self) -> Option<Self> {
+        match (self, other) {
+            (GameVariant::Chess960(None), GameVariant::Chess960(_)) => Some(other),
+            (GameVariant::Chess960(_), GameVariant::Chess960(None)) => Some(self),
+            _ if self == other => Some(self),
+            _ => None,
+        }
+    }
+}
+
+// the rules of a game in progress. the defaults are those of standard chess,
+// a variant overrides what it changes
+pub trait Variant {
+    fn start(&self) -> Position;
+
+    // the moves the side to move may choose from
+    fn legal_moves(&self, position: &Position) -> Vec<Move> {
+        position.legal_moves()
+    }
+
+    // why a move is refused, if it is
+    fn check_move(&self, position: &Position, mv: Move) -> Result<(), MoveError> {
+        if !position.pseudo_legal_moves().contains(&mv) {
+            return Err(MoveError::InvalidPosition);
+        }
+        if !position.is_legal(mv) {
+            return Err(MoveError::KingInCheck);
+        }
+        Ok(())
+    }
+
+    // whether the side to move is in check, which not every variant knows
+    fn in_check(&self, position: &Position) -> bool {
+        position.in_check()
+    }
+
+    // sees the position after every move, for variants that keep count
+    fn played(&mut self, _position: &Position) {}
+
+    // how the game ended, if the last move ended it
+    fn outcome(&self, position: &Position) -> Option<Outcome> {
+        position.outcome()
+    }
+}
+
+// standard chess from whichever setup, chess960 included
+pub struct Standard(Position);
+
+impl Variant for Standard {
+    fn start(&self) -> Position {
+        self.0.clone()
+    }
+}
+
+// the centre squares d4, e4, d5 and e5
+const HILL: [usize; 4] = [27, 28, 35, 36];
+
+// bringing the king to the centre wins as well as mating
+pub struct KingOfTheHill;
+
+impl Variant for KingOfTheHill {
+    fn start(&self) -> Position {
+        Position::default()
+    }
+
+    fn outcome(&self, position: &Position) -> Option<Outcome> {
+        let mover = 1 - position.turn;
+        match position.king_square(mover) {
+            Some(king) if HILL.contains(&king) => Some(Outcome::VariantWin {
+                winner: mover,
+                reason: "KingOfTheHill",
+            }),
+            _ => position.outcome(),
+        }
+    }
+}
+
+// giving check a third time wins as well as mating
+#[derive(Default)]
+pub struct ThreeCheck {
+    checks: [u8; 2],
+}
+
+impl Variant for ThreeCheck {
+    fn start(&self) -> Position {
+        Position::default()
+    }
+
+    fn played(&mut self, position: &Position) {
+        if position.in_check() {
+            self.checks[1 - position.turn] += 1;
+        }
+    }
+
+    fn outcome(&self, position: &Position) -> Option<Outcome> {
+        let mover = 1 - position.turn;
+        match self.checks[mover] >= 3 {
+            true => Some(Outcome::VariantWin {
+                winner: mover,
+                reason: "ThreeChecks",
+            }),
+            false => position.outcome(),
+        }
+    }
+}
+
+// losing chess: captures are forced, the king is just another piece and
+// whoever runs out of pieces or moves first wins
+pub struct Antichess;
+
+impl Antichess {
+    fn moves(position: &Position) -> Vec<Move> {
+        let mut moves = position.pseudo_legal_moves();
+        // pawns may also promote to a king
+        let kings: Vec<Move> = moves
+            .iter()
+            .filter(|mv| mv.promotion == Some(PieceVariant::Queen))
+            .map(|mv| Move {
+                promotion: Some(PieceVariant::King),
+                ..*mv
+            })
+            .collect();
+        moves.extend(kings);
+        moves
+    }
+
+    fn is_capture(position: &Position, mv: &Move) -> bool {
+        position.board[mv.to].is_some()
+            || (position.board[mv.from].map(|p| p.variant()) == Some(PieceVariant::Pawn)
+                && Some(mv.to) == position.en_passant)
+    }
+}
+
+impl Variant for Antichess {
+    fn start(&self) -> Position {
+        let mut position = Position::default();
+        position.castling.white_kingside = false;
+        position.castling.white_queenside = false;
+        position.castling.black_kingside = false;
+        position.castling.black_queenside = false;
+        position
+    }
+
+    fn legal_moves(&self, position: &Position) -> Vec<Move> {
+        let moves = Antichess::moves(position);
+        match moves.iter().any(|mv| Antichess::is_capture(position, mv)) {
+            true => moves
+                .into_iter()
+                .filter(|mv| Antichess::is_capture(position, mv))
+                .collect(),
+            false => moves,
+        }
+    }
+
+    fn check_move(&self, position: &Position, mv: Move) -> Result<(), MoveError> {
+        if !Antichess::moves(position).contains(&mv) {
+            return Err(MoveError::InvalidPosition);
+        }
+        if !self.legal_moves(position).contains(&mv) {
+            return Err(MoveError::MustCapture);
+        }
+        Ok(())
+    }
+
+    fn in_check(&self, _position: &Position) -> bool {
+        false
+    }
+
+    fn outcome(&self, position: &Position) -> Option<Outcome> {
+        if self.legal_moves(position).is_empty() {
+            let side = position.turn;
+            let pieces_left = position.board.iter().flatten().any(|p| p.side() == side);
+            return Some(Outcome::VariantWin {
+                winner: side,
+                reason: match pieces_left {
+                    true => "Stalemate",
+                    false => "NoPiecesLeft",
+                },
+            });
+        }
+        if position.halfmove_clock >= 100 {
+            return Some(Outcome::FiftyMoves);
+        }
+        None
+    }
+}
+
+// captured pieces change sides and can be dropped back onto the board.
+// the position keeps the pockets, so drops count when looking for a mate
+pub struct Crazyhouse;
+
+impl Variant for Crazyhouse {
+    fn start(&self) -> Position {
+        Position {
+            pockets: Some([vec![], vec![]]),
+            ..Position::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::ChessPiece;
+
+    fn sq(name: &str) -> usize {
+        let bytes = name.as_bytes();
+        (bytes[1] - b'1') as usize * 8 + (bytes[0] - b'a') as usize
+    }
+
+    fn play(rules: &mut dyn Variant, position: &mut Position, from: &str, to: &str) {
+        let mv = Move {
+            from: sq(from),
+            to: sq(to),
+            promotion: None,
+        };
+        assert_eq!(
+            rules.check_move(position, mv).map_err(|e| format!("{e:?}")),
+            Ok(())
+        );
+        position.play(mv);
+        rules.played(position);
+    }
+
+    #[test]
+    fn king_of_the_hill_is_won_in_the_centre() {
+        let mut rules = GameVariant::KingOfTheHill.rules();
+        let mut position = rules.start();
+        for (from, to) in [("e2", "e4"), ("a7", "a6"), ("e1", "e2"), ("a6", "a5")] {
+            play(rules.as_mut(), &mut position, from, to);
+        }
+        play(rules.as_mut(), &mut position, "e2", "e3");
+        assert_eq!(rules.outcome(&position), None);
+        play(rules.as_mut(), &mut position, "a5", "a4");
+        play(rules.as_mut(), &mut position, "e3", "d4");
+        assert_eq!(
+            rules.outcome(&position),
+            Some(Outcome::VariantWin {
+                winner: 0,
+                reason: "KingOfTheHill"
+            })
+        );
+    }
+
+    #[test]
+    fn three_checks_win() {
+        let mut rules = GameVariant::ThreeCheck.rules();
+        let mut position = rules.start();
+        let moves = [
+            ("e2", "e4"),
+            ("d7", "d5"),
+            ("f1", "b5"),
+            ("c7", "c6"),
+            ("b5", "c6"),
+            ("b7", "c6"),
+            ("d1", "h5"),
+            ("g8", "f6"),
+            ("h5", "f7"),
+        ];
+        for (from, to) in moves {
+            assert_eq!(rules.outcome(&position), None);
+            play(rules.as_mut(), &mut position, from, to);
+        }
+        assert_eq!(
+            rules.outcome(&position),
+            Some(Outcome::VariantWin {
+                winner: 0,
+                reason: "ThreeChecks"
+            })
+        );
+    }
+
+    #[test]
+    fn antichess_forces_captures_and_ignores_check() {
+        let mut rules = GameVariant::Antichess.rules();
+        let mut position = rules.start();
+        play(rules.as_mut(), &mut position, "e2", "e4");
+        play(rules.as_mut(), &mut position, "d7", "d5");
+        let quiet = Move {
+            from: sq("a2"),
+            to: sq("a3"),
+            promotion: None,
+        };
+        assert!(matches!(
+            rules.check_move(&position, quiet),
+            Err(MoveError::MustCapture)
+        ));
+        assert_eq!(rules.legal_moves(&position).len(), 1);
+        play(rules.as_mut(), &mut position, "e4", "d5");
+        play(rules.as_mut(), &mut position, "d8", "d5");
+        play(rules.as_mut(), &mut position, "b1", "c3");
+        // only the queen has something to take, and nobody is ever in check
+        assert!(!rules.in_check(&position));
+        assert!(rules
+            .legal_moves(&position)
+            .iter()
+            .all(|mv| mv.from == sq("d5")));
+    }
+
+    #[test]
+    fn antichess_is_won_by_running_out_of_pieces() {
+        let rules = GameVariant::Antichess.rules();
+        let mut position = rules.start();
+        position.board = [None; 64];
+        position.board[sq("a8")] = Some(ChessPiece::Black(PieceVariant::Rook));
+        position.turn = 0;
+        assert_eq!(
+            rules.outcome(&position),
+            Some(Outcome::VariantWin {
+                winner: 0,
+                reason: "NoPiecesLeft"
+            })
+        );
+    }
+
+    #[test]
+    fn crazyhouse_drops_captured_pieces() {
+        let mut rules = GameVariant::Crazyhouse.rules();
+        let mut position = rules.start();
+        for (from, to) in [("e2", "e4"), ("d7", "d5"), ("e4", "d5"), ("d8", "d5")] {
+            play(rules.as_mut(), &mut position, from, to);
+        }
+        let pockets = position.pockets.clone().unwrap();
+        assert_eq!(
+            pockets,
+            [vec![PieceVariant::Pawn], vec![PieceVariant::Pawn]]
+        );
+        assert!(rules
+            .check_move(&position, Move::drop(PieceVariant::Pawn, sq("d8")))
+            .is_err());
+        assert!(rules
+            .check_move(&position, Move::drop(PieceVariant::Knight, sq("e4")))
+            .is_err());
+        let drop = Move::drop(PieceVariant::Pawn, sq("e4"));
+        assert!(rules.check_move(&position, drop).is_ok());
+        let applied = position.play(drop);
+        assert_eq!(applied.piece, ChessPiece::White(PieceVariant::Pawn));
+        assert_eq!(position.board[sq("e4")], Some(applied.piece));
+        assert!(position.pockets.as_ref().unwrap()[0].is_empty());
+    }
+
+    #[test]
+    fn crazyhouse_promoted_pieces_return_as_pawns() {
+        let rules = GameVariant::Crazyhouse.rules();
+        let mut position = rules.start();
+        position.board = [None; 64];
+        position.board[sq("a1")] = Some(ChessPiece::White(PieceVariant::King));
+        position.board[sq("h8")] = Some(ChessPiece::Black(PieceVariant::King));
+        position.board[sq("b7")] = Some(ChessPiece::White(PieceVariant::Pawn));
+        position.board[sq("c8")] = Some(ChessPiece::Black(PieceVariant::Rook));
+        position.play(Move {
+            from: sq("b7"),
+            to: sq("b8"),
+            promotion: Some(PieceVariant::Queen),
+        });
+        position.play(Move {
+            from: sq("c8"),
+            to: sq("b8"),
+            promotion: None,
+        });
+        assert_eq!(position.pockets.unwrap()[1], vec![PieceVariant::Pawn]);
+    }
+
+    #[test]
+    fn pairs_seeks_of_the_same_variant() {
+        use GameVariant::*;
+        assert_eq!(Antichess.pairs_with(Antichess), Some(Antichess));
+        assert_eq!(ThreeCheck.pairs_with(KingOfTheHill), None);
+        assert_eq!(
+            Chess960(None).pairs_with(Chess960(Some(7))),
+            Some(Chess960(Some(7)))
+        );
+        assert_eq!(Chess960(Some(3)).pairs_with(Chess960(Some(7))), None);
+        assert!(Chess960(Some(3)).same_rules(Chess960(None)));
+    }
+}
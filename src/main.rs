@@ -1,79 +1,259 @@
-use actix::{io::FramedWrite, spawn, Actor, Addr, StreamHandler};
+use actix::{io::FramedWrite, spawn, Actor, Addr, StreamHandler, SyncArbiter};
 use actix_web::{
     get,
-    http::StatusCode,
     middleware::Logger,
     web::{Data, Payload},
-    App, Error, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer, Responder,
+    App, Error, HttpRequest, HttpResponse, HttpServer,
 };
 use actix_web_actors::ws;
 use codec::FrameCodec;
-use env_logger;
 
+mod analysis;
+mod api;
+mod bot;
+mod chat;
 mod chessclient;
 mod codec;
+mod config;
+mod engine;
+mod explorer;
 mod game;
 mod message;
+mod metrics;
+mod profile;
+mod puzzle;
 mod server;
+mod storage;
+mod tls;
+mod tournament;
 
-use chessclient::{ChessClient, TcpClient};
-use serde_json::to_string;
-use server::Server;
-use tokio::{io::split, net::TcpListener};
+use analysis::Analyzer;
+use chat::{BlockList, ChatPolicy};
+use chessclient::{ByteStream, ChessClient, TcpClient};
+use config::{Config, HeartbeatConfig};
+use metrics::{Transport, METRICS};
+use server::{Server, Shutdown};
+use storage::Storage;
+use tokio::{io::split, net::TcpListener, time::timeout};
+use tokio_rustls::TlsAcceptor;
 use tokio_util::codec::FramedRead;
+use tournament::{PauseTournaments, Tournaments};
 
 #[get("/game")]
 async fn game_stream(
     req: HttpRequest,
     stream: Payload,
     srv: Data<Addr<Server>>,
+    tournaments: Data<Addr<Tournaments>>,
+    config: Data<Config>,
 ) -> Result<HttpResponse, Error> {
     let server = srv.get_ref().clone();
+    let tournaments = tournaments.get_ref().clone();
     log::info!("connected to stream!");
-    ws::start(ChessClient::new(server), &req, stream)
+    let client = ChessClient::new(server, tournaments, config.heartbeat);
+    ws::WsResponseBuilder::new(client, &req, stream)
+        .frame_size(config.max_frame_length)
+        .start()
 }
 
-#[get("/players")]
-async fn get_players(_req: HttpRequest, srv: Data<Addr<Server>>) -> impl Responder {
-    let result = srv.send(server::GetPlayers {}).await;
-    let string = match result {
-        Ok(players) => to_string(&players).unwrap(),
-        Err(_) => "empty".to_string(),
-    };
-    HttpResponseBuilder::new(StatusCode::OK).body(string)
+fn spawn_tcp_client<S: ByteStream>(
+    stream: S,
+    server: Addr<Server>,
+    tournaments: Addr<Tournaments>,
+    heartbeat: HeartbeatConfig,
+    max_frame_length: usize,
+    transport: Transport,
+    authenticated: bool,
+) {
+    TcpClient::create(|ctx| {
+        let (r, w) = split(stream);
+        let codec = FrameCodec::with_max_frame_length(max_frame_length);
+        TcpClient::add_stream(FramedRead::new(r, codec.clone()), ctx);
+        let writer = FramedWrite::new(w, codec, ctx);
+        TcpClient::new(
+            server,
+            tournaments,
+            writer,
+            heartbeat,
+            transport,
+            authenticated,
+        )
+    });
+}
+
+#[get("/metrics")]
+async fn get_metrics(srv: Data<Addr<Server>>) -> HttpResponse {
+    match srv.send(server::GetStats).await {
+        Ok(stats) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(METRICS.render(&stats)),
+        Err(_) => HttpResponse::ServiceUnavailable().finish(),
+    }
 }
 
-fn start_tcp_server(srv: Addr<Server>) {
+async fn start_tcp_server(
+    srv: Addr<Server>,
+    tournaments: Addr<Tournaments>,
+    config: Config,
+    tls: Option<TlsAcceptor>,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(&config.tcp.bind).await?;
+    log::info!(
+        "Started {} server at {}",
+        if tls.is_some() { "tls" } else { "tcp" },
+        config.tcp.bind
+    );
     spawn(async move {
-        log::info!("Started tcp server at 127.0.0.1:9000");
-        let listener = TcpListener::bind("127.0.0.1:9000").await.unwrap();
-        while let Ok((stream, _)) = listener.accept().await {
+        while let Ok((stream, peer)) = listener.accept().await {
             log::info!("client connected!");
             let server = srv.clone();
-            TcpClient::create(|ctx| {
-                let (r, w) = split(stream);
-                TcpClient::add_stream(FramedRead::new(r, FrameCodec), ctx);
-                TcpClient::new(server, FramedWrite::new(w, FrameCodec, ctx))
+            let tournaments = tournaments.clone();
+            let heartbeat = config.heartbeat;
+            let max_frame_length = config.max_frame_length;
+            let Some(acceptor) = tls.clone() else {
+                spawn_tcp_client(
+                    stream,
+                    server,
+                    tournaments,
+                    heartbeat,
+                    max_frame_length,
+                    Transport::Tcp,
+                    false,
+                );
+                continue;
+            };
+            // handshakes run on their own so a slow client cannot hold up the listener
+            spawn(async move {
+                match timeout(heartbeat.timeout(), acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let authenticated = stream.get_ref().1.peer_certificates().is_some();
+                        spawn_tcp_client(
+                            stream,
+                            server,
+                            tournaments,
+                            heartbeat,
+                            max_frame_length,
+                            Transport::Tls,
+                            authenticated,
+                        );
+                    }
+                    Ok(Err(err)) => log::warn!("tls handshake with {peer} failed: {err}"),
+                    Err(_) => log::warn!("tls handshake with {peer} timed out"),
+                }
             });
         }
     });
+    Ok(())
+}
+
+// resolves on SIGTERM or ctrl-c
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = term.recv() => Ok(()),
+            res = tokio::signal::ctrl_c() => res,
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
 }
 
 #[actix::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
-    let srv = Server::new().start();
-    start_tcp_server(srv.clone());
-    log::info!("Started at http://localhost:3000");
-    HttpServer::new(move || {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            log::error!("invalid configuration: {err}");
+            std::process::exit(2);
+        }
+    };
+    let storage = match Storage::open(&config.storage.dir) {
+        Ok(storage) => storage,
+        Err(err) => {
+            log::error!("cannot open storage: {err}");
+            std::process::exit(2);
+        }
+    };
+    let tls = match config.tcp.tls.enabled {
+        true => match tls::acceptor(&config.tcp.tls) {
+            Ok(acceptor) => Some(acceptor),
+            Err(err) => {
+                log::error!("invalid tls configuration: {err}");
+                std::process::exit(2);
+            }
+        },
+        false => None,
+    };
+    let chat = ChatPolicy::new(
+        &config.chat,
+        Box::new(BlockList::new(&config.chat.blocked_words)),
+    );
+    let analyzer = config.analysis.enabled.then(|| {
+        let (storage, analysis) = (storage.clone(), config.analysis);
+        SyncArbiter::start(analysis.workers, move || {
+            Analyzer::new(storage.clone(), &analysis)
+        })
+    });
+    let srv = Server::new(
+        config.game.time_control,
+        storage.clone(),
+        chat,
+        config.uci.clone(),
+        config.bots.clone(),
+        analyzer,
+    )
+    .start();
+    let tournaments = Tournaments::new(srv.clone(), config.game.time_control).start();
+    if config.tcp.enabled {
+        start_tcp_server(srv.clone(), tournaments.clone(), config.clone(), tls).await?;
+    }
+    if !config.http.enabled {
+        shutdown_signal().await?;
+        tournaments.do_send(PauseTournaments);
+        let _ = srv
+            .send(Shutdown {
+                grace: config.shutdown.grace(),
+            })
+            .await;
+        return Ok(());
+    }
+    log::info!("Started at http://{}", config.http.bind);
+    let http_config = config.clone();
+    let game_server = srv.clone();
+    let game_tournaments = tournaments.clone();
+    let http = HttpServer::new(move || {
         App::new()
             .service(game_stream)
-            .service(get_players)
-            .app_data(Data::new(srv.clone()))
+            .service(get_metrics)
+            .configure(api::configure)
+            .app_data(Data::new(game_server.clone()))
+            .app_data(Data::new(game_tournaments.clone()))
+            .app_data(Data::new(storage.clone()))
+            .app_data(Data::new(http_config.clone()))
             .wrap(Logger::default())
     })
-    .bind(("localhost", 3000))?
-    .workers(2)
-    .run()
-    .await
+    // signals are handled below so games get a chance to wrap up first
+    .disable_signals()
+    .bind(&config.http.bind)?
+    .workers(config.http.workers)
+    .run();
+    let handle = http.handle();
+    spawn(async move {
+        if let Err(err) = shutdown_signal().await {
+            log::error!("cannot listen for shutdown signals: {err}");
+            return;
+        }
+        tournaments.do_send(PauseTournaments);
+        let _ = srv
+            .send(Shutdown {
+                grace: config.shutdown.grace(),
+            })
+            .await;
+        handle.stop(true).await;
+    });
+    http.await
 }
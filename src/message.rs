@@ -1,52 +1,274 @@
 use crate::{
+    bot::ChallengeFilter,
+    chat::{ChatError, ChatLine, ChatScope},
     chessclient::Message,
-    game::{MoveDetails, MoveError},
+    codec::Encoding,
+    game::{
+        ChessPiece, DropDetails, GameSnapshot, GameVariant, MoveDetails, MoveError, Pos,
+        ReplayError, ReplayPly, SquareMoves, TimeControl,
+    },
+    profile::{ProfileView, Rating},
+    puzzle::{PuzzleReply, PuzzleView},
+    tournament::{TournamentError, TournamentSettings, TournamentView},
 };
 use actix::{Message as ActixMessage, Recipient};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Clone, Copy)]
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
 pub enum Color {
     White,
     Black,
 }
 
-#[derive(Serialize)]
+impl Color {
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            0 => Color::White,
+            _ => Color::Black,
+        }
+    }
+
+    pub fn index(self) -> usize {
+        match self {
+            Color::White => 0,
+            Color::Black => 1,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
 pub enum ClientResult {
     Ok,
     MoveError(MoveError),
     LoginError,
+    // the server is draining and no longer starts new games
+    ShuttingDown,
+    // nobody by that name has ever logged in
+    UnknownUser,
+    ChatError(ChatError),
+    // no uci engine is configured, or it failed to start
+    EngineUnavailable,
+    // only bot accounts can do that
+    NotABot,
+    // there is no finished game to ask for a rematch of
+    NoRematch,
+    // correspondence games need an account
+    NotLoggedIn,
+    // no such finished game to replay, or correspondence game with this player in it
+    NoSuchGame,
+    // correspondence games give from one day up to a limit for each move
+    InvalidDays,
+    // a live game has to be finished before opening a correspondence one
+    InGame,
+    TournamentError(TournamentError),
+    // replays go at one move every 100 ms to 10 s
+    InvalidPace,
+    ReplayError(ReplayError),
+    // no puzzle left that the player has not tried, or none in progress to move in
+    NoPuzzle,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Debug)]
 pub enum ClientMessage {
+    Hello(Encoding),
     Login(String),
+    // logs in to a bot account, registering it as one the first time. tcp only
+    BotLogin(String),
+    // bots take matching challenges from the queue without enqueueing, None stops that
+    AcceptChallenges(Option<ChallengeFilter>),
+    // queue for a game of standard chess
     Enqueue,
+    // queue for a game of the given variant
+    Seek(GameVariant),
+    // seek a correspondence game with this many days for every move
+    SeekCorrespondence {
+        days: u32,
+        variant: GameVariant,
+    },
+    // lists this player's correspondence games
+    ListCorrespondence,
+    // makes a correspondence game the current one, to move or chat in it
+    OpenCorrespondence(String),
+    // organise a tournament, which players then join by its id
+    CreateTournament(TournamentSettings),
+    JoinTournament(u64),
+    // only before the first round
+    LeaveTournament(u64),
+    // pairs the first round, for the organiser only
+    StartTournament(u64),
+    GetTournament(u64),
+    // in an arena game, before your first move: half the clock for an extra point on a win
+    Berserk,
+    // plays a finished game back from the given ply, one move every interval
+    WatchReplay {
+        game: String,
+        #[serde(default)]
+        from_ply: usize,
+        interval_ms: u64,
+    },
+    StopReplay,
+    // starts the next puzzle, giving up the one in progress
+    NextPuzzle,
+    // a move in the puzzle in progress
+    PuzzleMove(MoveDetails),
     Dequeue,
+    // a game against the built in engine at a strength from 1 to 8
+    PlayComputer(u8),
+    // a game against the configured uci engine binary
+    PlayUciEngine,
     LeaveGame,
+    // asks the last opponent for a rematch in the same variant, or takes up their offer
     PlayAgain,
+    // the same with another variant, which the opponent has to agree to
+    Rematch(GameVariant),
     MakeMove(MoveDetails),
+    // crazyhouse only, places a piece from the pocket
+    DropPiece(DropDetails),
+    RequestSnapshot,
+    // every legal move of the side to move, or only those from one square
+    GetLegalMoves(Option<Pos>),
+    PushLegalMoves(bool),
+    // the player card of any user, online or not
+    GetProfile(String),
+    // lobby updates are on by default, turning them back on resends the lobby
+    SubscribeLobby(bool),
+    Chat {
+        scope: ChatScope,
+        text: String,
+    },
+    // stop or start receiving the opponent's chat in the current game
+    MuteOpponent(bool),
     Disconnect,
     Ping,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub enum OutgoingMessage {
-    MovePiece { from: usize, to: usize },
-    RemovePiece { at: usize },
-    Check { checker: usize },
-    Checkmate { winner: usize },
+    MovePiece {
+        from: usize,
+        to: usize,
+    },
+    RemovePiece {
+        at: usize,
+    },
+    PromotePiece {
+        at: usize,
+        piece: ChessPiece,
+    },
+    // a piece set down on a square, such as a chess960 rook its king castled onto
+    PlacePiece {
+        at: usize,
+        piece: ChessPiece,
+    },
+    Check {
+        checker: usize,
+    },
+    Checkmate {
+        winner: usize,
+    },
     Result(ClientResult),
     GameStarted(Color),
     WinGame(String),
     LoseGame(String),
+    DrawGame(String),
+    LegalMoves(Vec<SquareMoves>),
+    Snapshot(Box<GameSnapshot>),
+    // the server is going away, games still running are adjourned after the grace period
+    ServerShutdown {
+        grace_secs: u64,
+    },
+    // the game was saved and resumes once both players are back after a restart
+    GameAdjourned,
+    Profile(Box<ProfileView>),
+    Lobby(LobbyEvent),
+    Chat {
+        scope: ChatScope,
+        line: ChatLine,
+    },
+    // the last opponent wants a rematch, answer with PlayAgain or a Rematch of your own
+    RematchOffered(GameVariant),
+    // the player's correspondence games, sent on login and on request
+    Correspondence(Vec<CorrespondenceGame>),
+    // pairings and standings, pushed to the players as every round starts and ends
+    Tournament(Box<TournamentView>),
+    // a player in this arena game went berserk
+    Berserk(Color),
+    // the next position of a replay, starting with the one it was asked to start from
+    Replay(Box<ReplayPly>),
+    ReplayFinished,
+    Puzzle(Box<PuzzleView>),
+    // the opponent's forced answer to a right move
+    PuzzleReply(PuzzleReply),
+    // the solution, whether it was found and the player's new puzzle rating
+    PuzzleFinished {
+        solved: bool,
+        solution: Vec<String>,
+        rating: Rating,
+    },
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct PlayerPresence {
+    pub username: String,
+    pub bot: bool,
+    // id of the game the player is in, None while they are free to play
+    pub game: Option<String>,
+}
+
+// a player waiting in the queue for an opponent
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Challenge {
+    // None for anonymous players
+    pub username: Option<String>,
+    pub time_control: TimeControl,
+    pub variant: GameVariant,
+}
+
+// a correspondence game as one of its players sees it
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct CorrespondenceGame {
+    pub id: String,
+    pub opponent: String,
+    pub color: Color,
+    pub variant: GameVariant,
+    pub days_per_move: u32,
+    pub your_move: bool,
+    // unix seconds by which the side to move has to have moved
+    pub deadline: u64,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub enum LobbyEvent {
+    // everything a client needs to draw the lobby, sent once on subscribing
+    Snapshot {
+        players: Vec<PlayerPresence>,
+        challenges: Vec<Challenge>,
+    },
+    Joined(String),
+    Left(String),
+    InGame {
+        username: String,
+        game: String,
+    },
+    Available(String),
+    ChallengeOpened(Challenge),
+    ChallengeClosed(Challenge),
+    // only to the player concerned: the opponent moved in a correspondence game
+    YourMove(CorrespondenceGame),
 }
 
+// resolves to whether the name was free and is now taken by this client
 #[derive(ActixMessage)]
-#[rtype(result = "()")]
+#[rtype(result = "bool")]
 pub struct Login {
     pub username: String,
     pub client: Recipient<Message>,
+    // whether the client wants lobby updates
+    pub lobby: bool,
+    // came through the bot handshake
+    pub bot: bool,
+    // presented a certificate signed by the configured client ca
+    pub authenticated: bool,
 }
 
 #[derive(ActixMessage)]
@@ -55,6 +277,19 @@ pub struct Logout {
     pub username: String,
 }
 
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct SubscribeLobby {
+    pub username: String,
+    pub enabled: bool,
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Connect {
+    pub player: Recipient<Message>,
+}
+
 #[derive(ActixMessage)]
 #[rtype(result = "()")]
 pub struct Disconnect {
@@ -0,0 +1,291 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{codec::FrameError, game::MoveError, server::ServerStats};
+
+// seconds of history behind the moves per second gauge
+const RATE_WINDOW: usize = 60;
+
+pub static METRICS: Registry = Registry::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    WebSocket,
+    Tcp,
+    Tls,
+}
+
+impl Transport {
+    const ALL: [Transport; 3] = [Transport::WebSocket, Transport::Tcp, Transport::Tls];
+
+    fn label(self) -> &'static str {
+        match self {
+            Transport::WebSocket => "websocket",
+            Transport::Tcp => "tcp",
+            Transport::Tls => "tls",
+        }
+    }
+}
+
+impl FrameError {
+    // None for failures that are not the client's bytes being undecodable
+    fn decode_label(&self) -> Option<&'static str> {
+        match self {
+            FrameError::Oversize { .. } => Some("oversize"),
+            FrameError::InvalidUtf8(_) => Some("invalid_utf8"),
+            FrameError::Json(_) => Some("json"),
+            FrameError::MessagePack(_) => Some("messagepack"),
+            FrameError::Serialize(_) | FrameError::Io(_) => None,
+        }
+    }
+}
+
+// moves counted into one bucket per second, oldest buckets get reused
+struct MoveWindow {
+    buckets: [(u64, u64); RATE_WINDOW],
+}
+
+impl MoveWindow {
+    fn record(&mut self, now: u64) {
+        let bucket = &mut self.buckets[now as usize % RATE_WINDOW];
+        if bucket.0 != now {
+            *bucket = (now, 0);
+        }
+        bucket.1 += 1;
+    }
+
+    fn per_second(&self, now: u64) -> f64 {
+        let recent: u64 = self
+            .buckets
+            .iter()
+            .filter(|(second, _)| now.saturating_sub(*second) < RATE_WINDOW as u64)
+            .map(|(_, count)| count)
+            .sum();
+        recent as f64 / RATE_WINDOW as f64
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// process wide counters, rendered in the prometheus text format on /metrics.
+// anything owned by the server actor is asked for at scrape time instead
+pub struct Registry {
+    connected: [AtomicI64; 3],
+    active_games: AtomicI64,
+    moves: AtomicU64,
+    move_window: Mutex<MoveWindow>,
+    heartbeat_timeouts: AtomicU64,
+    rejected_moves: Mutex<BTreeMap<String, u64>>,
+    frame_errors: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Registry {
+    pub const fn new() -> Self {
+        Self {
+            connected: [AtomicI64::new(0), AtomicI64::new(0), AtomicI64::new(0)],
+            active_games: AtomicI64::new(0),
+            moves: AtomicU64::new(0),
+            move_window: Mutex::new(MoveWindow {
+                buckets: [(0, 0); RATE_WINDOW],
+            }),
+            heartbeat_timeouts: AtomicU64::new(0),
+            rejected_moves: Mutex::new(BTreeMap::new()),
+            frame_errors: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn connected(&self, transport: Transport) -> &AtomicI64 {
+        &self.connected[Transport::ALL.iter().position(|t| *t == transport).unwrap()]
+    }
+
+    pub fn client_connected(&self, transport: Transport) {
+        self.connected(transport).fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self, transport: Transport) {
+        self.connected(transport).fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn game_started(&self) {
+        self.active_games.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn game_stopped(&self) {
+        self.active_games.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn move_played(&self) {
+        self.moves.fetch_add(1, Ordering::Relaxed);
+        self.move_window.lock().unwrap().record(now_secs());
+    }
+
+    pub fn move_rejected(&self, err: MoveError) {
+        *self
+            .rejected_moves
+            .lock()
+            .unwrap()
+            .entry(format!("{err:?}"))
+            .or_default() += 1;
+    }
+
+    pub fn heartbeat_timeout(&self) {
+        self.heartbeat_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn frame_error(&self, err: &FrameError) {
+        if let Some(label) = err.decode_label() {
+            *self.frame_errors.lock().unwrap().entry(label).or_default() += 1;
+        }
+    }
+
+    pub fn render(&self, stats: &ServerStats) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{name}{labels} {value}");
+            }
+        };
+        let plain = |value: String| vec![(String::new(), value)];
+
+        metric(
+            "chess_connected_clients",
+            "gauge",
+            "Open client connections by transport.",
+            Transport::ALL
+                .iter()
+                .map(|t| {
+                    let count = self.connected(*t).load(Ordering::Relaxed);
+                    (
+                        format!("{{transport=\"{}\"}}", t.label()),
+                        count.to_string(),
+                    )
+                })
+                .collect(),
+        );
+        metric(
+            "chess_logged_in_users",
+            "gauge",
+            "Users currently logged in.",
+            plain(stats.users.to_string()),
+        );
+        metric(
+            "chess_queue_length",
+            "gauge",
+            "Players waiting to be matched.",
+            plain(stats.queue.to_string()),
+        );
+        metric(
+            "chess_active_games",
+            "gauge",
+            "Game actors currently running.",
+            plain(self.active_games.load(Ordering::Relaxed).to_string()),
+        );
+        metric(
+            "chess_moves_total",
+            "counter",
+            "Moves played across all games.",
+            plain(self.moves.load(Ordering::Relaxed).to_string()),
+        );
+        metric(
+            "chess_moves_per_second",
+            "gauge",
+            "Moves per second averaged over the last minute.",
+            plain(format!(
+                "{}",
+                self.move_window.lock().unwrap().per_second(now_secs())
+            )),
+        );
+        metric(
+            "chess_rejected_moves_total",
+            "counter",
+            "Moves turned down, by reason.",
+            self.rejected_moves
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(kind, count)| (format!("{{reason=\"{kind}\"}}"), count.to_string()))
+                .collect(),
+        );
+        metric(
+            "chess_heartbeat_timeouts_total",
+            "counter",
+            "Clients dropped for missing heartbeats.",
+            plain(self.heartbeat_timeouts.load(Ordering::Relaxed).to_string()),
+        );
+        metric(
+            "chess_frame_errors_total",
+            "counter",
+            "Frames from clients that could not be decoded, by cause.",
+            self.frame_errors
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(kind, count)| (format!("{{kind=\"{kind}\"}}"), count.to_string()))
+                .collect(),
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let registry = Registry::new();
+        registry.client_connected(Transport::Tcp);
+        registry.client_connected(Transport::Tcp);
+        registry.client_disconnected(Transport::Tcp);
+        registry.client_connected(Transport::WebSocket);
+        registry.move_rejected(MoveError::KingInCheck);
+        registry.move_rejected(MoveError::KingInCheck);
+        registry.move_played();
+        registry.frame_error(&FrameError::Oversize { length: 9, max: 8 });
+        registry.frame_error(&FrameError::Io(std::io::ErrorKind::ConnectionReset.into()));
+        let text = registry.render(&ServerStats { users: 3, queue: 1 });
+
+        assert!(text.contains("# TYPE chess_connected_clients gauge\n"));
+        assert!(text.contains("chess_connected_clients{transport=\"tcp\"} 1\n"));
+        assert!(text.contains("chess_connected_clients{transport=\"websocket\"} 1\n"));
+        assert!(text.contains("chess_connected_clients{transport=\"tls\"} 0\n"));
+        assert!(text.contains("chess_logged_in_users 3\n"));
+        assert!(text.contains("chess_queue_length 1\n"));
+        assert!(text.contains("chess_moves_total 1\n"));
+        assert!(text.contains("chess_rejected_moves_total{reason=\"KingInCheck\"} 2\n"));
+        assert!(text.contains("chess_frame_errors_total{kind=\"oversize\"} 1\n"));
+        assert!(!text.contains("kind=\"io\""));
+    }
+
+    #[test]
+    fn move_rate_only_counts_the_last_minute() {
+        let mut window = MoveWindow {
+            buckets: [(0, 0); RATE_WINDOW],
+        };
+        for _ in 0..30 {
+            window.record(1_000);
+        }
+        for _ in 0..30 {
+            window.record(1_059);
+        }
+        assert_eq!(window.per_second(1_059), 1.0);
+        assert_eq!(window.per_second(1_060), 0.5);
+        // the slot for second 1_000 gets reused rather than added to
+        window.record(1_060);
+        assert_eq!(window.per_second(1_060), 31.0 / 60.0);
+    }
+}
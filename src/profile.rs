@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{GameResult, TimeControl},
+    server::GameSummary,
+};
+
+const INITIAL_RATING: i32 = 1500;
+// players settle faster while they have few rated games behind them
+const PROVISIONAL_GAMES: u32 = 30;
+const PROVISIONAL_K: f64 = 40.0;
+const ESTABLISHED_K: f64 = 20.0;
+const MAX_USERNAME_LENGTH: usize = 32;
+
+// names double as file names and url segments, so keep them plain
+pub fn valid_username(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_USERNAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+}
+
+impl Category {
+    // buckets by the expected length of a 40 move game
+    pub fn of(time_control: &TimeControl) -> Self {
+        if time_control.days_per_move.is_some() {
+            return Category::Correspondence;
+        }
+        match time_control.initial_secs + 40 * time_control.increment_secs {
+            0..=179 => Category::Bullet,
+            180..=479 => Category::Blitz,
+            480..=1499 => Category::Rapid,
+            _ => Category::Classical,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct Rating {
+    pub rating: i32,
+    pub games: u32,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: INITIAL_RATING,
+            games: 0,
+        }
+    }
+}
+
+impl Rating {
+    // score is 1 for a win, 0.5 for a draw and 0 for a loss
+    fn updated(self, opponent: i32, score: f64) -> Self {
+        let expected = 1.0 / (1.0 + 10f64.powf(f64::from(opponent - self.rating) / 400.0));
+        let k = match self.games < PROVISIONAL_GAMES {
+            true => PROVISIONAL_K,
+            false => ESTABLISHED_K,
+        };
+        Self {
+            rating: self.rating + (k * (score - expected)).round() as i32,
+            games: self.games + 1,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Debug)]
+pub struct Stats {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Profile {
+    pub username: String,
+    // unix seconds
+    pub created_at: u64,
+    // registered through the bot handshake. bots are only ever rated against bots
+    #[serde(default)]
+    pub bot: bool,
+    #[serde(default)]
+    pub ratings: BTreeMap<Category, Rating>,
+    #[serde(default)]
+    pub stats: Stats,
+    #[serde(default)]
+    pub puzzle_rating: Rating,
+    // every puzzle tried, so none comes round twice
+    #[serde(default)]
+    pub puzzles: BTreeSet<String>,
+}
+
+impl Profile {
+    pub fn new(username: String, created_at: u64) -> Self {
+        Self {
+            username,
+            created_at,
+            bot: false,
+            ratings: BTreeMap::new(),
+            stats: Stats::default(),
+            puzzle_rating: Rating::default(),
+            puzzles: BTreeSet::new(),
+        }
+    }
+
+    pub fn rating(&self, category: Category) -> Rating {
+        self.ratings.get(&category).copied().unwrap_or_default()
+    }
+
+    pub fn count(&mut self, result: GameResult, side: usize) {
+        match result {
+            GameResult::Draw => self.stats.draws += 1,
+            _ if result == GameResult::win_for(side) => self.stats.wins += 1,
+            _ => self.stats.losses += 1,
+        }
+    }
+}
+
+// rates a game between two profiles, white first
+pub fn rate(players: [&mut Profile; 2], category: Category, result: GameResult) {
+    let [white, black] = players;
+    let score = match result {
+        GameResult::WhiteWins => 1.0,
+        GameResult::BlackWins => 0.0,
+        GameResult::Draw => 0.5,
+    };
+    let (before_white, before_black) = (white.rating(category), black.rating(category));
+    white
+        .ratings
+        .insert(category, before_white.updated(before_black.rating, score));
+    black.ratings.insert(
+        category,
+        before_black.updated(before_white.rating, 1.0 - score),
+    );
+}
+
+// a puzzle attempt is rated as a game between the player and the puzzle,
+// so puzzles that few solve climb as players who solve them do
+pub fn rate_puzzle(player: &mut Profile, puzzle: &mut Rating, solved: bool) {
+    let score = if solved { 1.0 } else { 0.0 };
+    let before = player.puzzle_rating;
+    player.puzzle_rating = before.updated(puzzle.rating, score);
+    *puzzle = puzzle.updated(before.rating, 1.0 - score);
+}
+
+// what a player card shows, with the live status filled in by the server
+#[derive(Serialize, Clone)]
+pub struct ProfileView {
+    pub username: String,
+    pub created_at: u64,
+    pub bot: bool,
+    pub online: bool,
+    // id of the game the player is in right now
+    pub playing: Option<String>,
+    pub ratings: BTreeMap<Category, Rating>,
+    pub stats: Stats,
+    pub puzzle_rating: Rating,
+    pub recent_games: Vec<GameSummary>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_time_controls() {
+        let tc = |initial_secs, increment_secs| TimeControl {
+            initial_secs,
+            increment_secs,
+            days_per_move: None,
+        };
+        assert_eq!(Category::of(&tc(60, 0)), Category::Bullet);
+        assert_eq!(Category::of(&tc(120, 1)), Category::Bullet);
+        assert_eq!(Category::of(&tc(180, 0)), Category::Blitz);
+        assert_eq!(Category::of(&tc(180, 2)), Category::Blitz);
+        assert_eq!(Category::of(&tc(600, 0)), Category::Rapid);
+        assert_eq!(Category::of(&tc(900, 15)), Category::Classical);
+        assert_eq!(
+            Category::of(&TimeControl::correspondence(3)),
+            Category::Correspondence
+        );
+    }
+
+    #[test]
+    fn rating_moves_towards_the_result() {
+        let mut white = Profile::new("alice".to_owned(), 0);
+        let mut black = Profile::new("bob".to_owned(), 0);
+        rate(
+            [&mut white, &mut black],
+            Category::Blitz,
+            GameResult::WhiteWins,
+        );
+        assert_eq!(white.rating(Category::Blitz).rating, 1520);
+        assert_eq!(black.rating(Category::Blitz).rating, 1480);
+        assert_eq!(white.rating(Category::Blitz).games, 1);
+        // other categories are untouched
+        assert_eq!(white.rating(Category::Rapid), Rating::default());
+
+        // an upset moves the numbers more than the expected result did
+        rate(
+            [&mut black, &mut white],
+            Category::Blitz,
+            GameResult::WhiteWins,
+        );
+        assert_eq!(black.rating(Category::Blitz).rating, 1480 + 22);
+        assert_eq!(white.rating(Category::Blitz).rating, 1520 - 22);
+    }
+
+    #[test]
+    fn established_players_move_slower() {
+        let mut white = Profile::new("alice".to_owned(), 0);
+        let mut black = Profile::new("bob".to_owned(), 0);
+        let veteran = Rating {
+            rating: 1500,
+            games: PROVISIONAL_GAMES,
+        };
+        white.ratings.insert(Category::Rapid, veteran);
+        black.ratings.insert(Category::Rapid, veteran);
+        rate([&mut white, &mut black], Category::Rapid, GameResult::Draw);
+        assert_eq!(white.rating(Category::Rapid).rating, 1500);
+        rate(
+            [&mut white, &mut black],
+            Category::Rapid,
+            GameResult::BlackWins,
+        );
+        assert_eq!(white.rating(Category::Rapid).rating, 1490);
+    }
+
+    #[test]
+    fn puzzles_and_players_rate_each_other() {
+        let mut player = Profile::new("alice".to_owned(), 0);
+        let mut puzzle = Rating::default();
+        rate_puzzle(&mut player, &mut puzzle, true);
+        assert_eq!(player.puzzle_rating.rating, 1520);
+        assert_eq!(
+            puzzle,
+            Rating {
+                rating: 1480,
+                games: 1
+            }
+        );
+        rate_puzzle(&mut player, &mut puzzle, false);
+        assert!(player.puzzle_rating.rating < 1520);
+        assert!(puzzle.rating > 1480);
+        // game ratings are untouched
+        assert!(player.ratings.is_empty());
+    }
+
+    #[test]
+    fn counts_results_from_each_side() {
+        let mut profile = Profile::new("alice".to_owned(), 0);
+        profile.count(GameResult::WhiteWins, 0);
+        profile.count(GameResult::WhiteWins, 1);
+        profile.count(GameResult::Draw, 1);
+        assert_eq!(
+            profile.stats,
+            Stats {
+                wins: 1,
+                losses: 1,
+                draws: 1
+            }
+        );
+    }
+
+    #[test]
+    fn usernames_stay_plain() {
+        assert!(valid_username("magnus_c-1"));
+        assert!(!valid_username(""));
+        assert!(!valid_username("../etc"));
+        assert!(!valid_username("with space"));
+        assert!(!valid_username(&"a".repeat(33)));
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analysis::{score, Analysis, Judgement},
+    engine::{
+        uci::{parse_move, uci_move},
+        Limits,
+    },
+    game::{
+        fen, parse_fen, san, GameRecord, GameVariant, Move, MoveDetails, MoveError, Position,
+        Variant,
+    },
+    message::Color,
+    profile::Rating,
+};
+
+// the solver has to come out at least this far ahead with the right move
+const WINNING: i32 = 200;
+// and every other move has to leave them at least this much worse off
+const ONLY_MOVE_MARGIN: i32 = 150;
+// moves the solver makes in the longest solution
+const MAX_SOLVER_MOVES: usize = 3;
+
+// a position from a finished game where one side blundered and the other
+// has a single way to make them pay
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Puzzle {
+    pub id: String,
+    // the game it was found in, and the ply of the blunder
+    pub game: String,
+    pub ply: usize,
+    // the position after the blunder, with the solver to move
+    pub fen: String,
+    pub last_move: String,
+    // uci moves, the solver's first with the forced replies in between
+    pub solution: Vec<String>,
+    pub rating: Rating,
+}
+
+// what a player is shown when a puzzle starts
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct PuzzleView {
+    pub id: String,
+    pub fen: String,
+    pub last_move: String,
+    pub color: Color,
+    pub rating: i32,
+}
+
+// the opponent's forced answer to a right move
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct PuzzleReply {
+    pub uci: String,
+    pub san: String,
+    // the position after it, with the solver to move again
+    pub fen: String,
+}
+
+// the score a move leaves its player with, for every legal move, best first
+fn ranked(position: &Position, limits: Limits) -> Vec<(Move, i32)> {
+    let limits = Limits {
+        depth: limits.depth.saturating_sub(1).max(1),
+        ..limits
+    };
+    let mut moves: Vec<_> = position
+        .legal_moves()
+        .into_iter()
+        .map(|mv| {
+            let mut after = position.clone();
+            after.play(mv);
+            (mv, -score(&after, limits).0)
+        })
+        .collect();
+    moves.sort_by_key(|&(_, score)| -score);
+    moves
+}
+
+fn mated(position: &Position) -> bool {
+    position.in_check() && position.legal_moves().is_empty()
+}
+
+// the line that wins from a position, for as long as the solver's move is
+// the only one that does. None if not even the first move is
+fn solution(position: &Position, limits: Limits) -> Option<Vec<Move>> {
+    let mut position = position.clone();
+    let mut line = vec![];
+    for _ in 0..MAX_SOLVER_MOVES {
+        let ranked = ranked(&position, limits);
+        let Some(&(best, best_score)) = ranked.first() else {
+            break;
+        };
+        let mut after = position.clone();
+        after.play(best);
+        // any mate solves the puzzle, so a second one does not spoil it
+        let only = best_score >= WINNING
+            && ranked
+                .get(1)
+                .is_none_or(|&(_, second)| second <= best_score - ONLY_MOVE_MARGIN);
+        if !only && !mated(&after) {
+            // a line ends on the solver's move, not on the reply before it
+            line.pop();
+            break;
+        }
+        line.push(best);
+        position = after;
+        match score(&position, limits).1 {
+            Some(reply) if line.len() < 2 * MAX_SOLVER_MOVES - 1 => {
+                line.push(reply);
+                position.play(reply);
+            }
+            _ => break,
+        }
+    }
+    (!line.is_empty()).then_some(line)
+}
+
+// looks for puzzles after every blunder of an analysed game of standard chess
+pub fn mine(record: &GameRecord, analysis: &Analysis, limits: Limits) -> Vec<Puzzle> {
+    if record.variant != GameVariant::Standard {
+        return vec![];
+    }
+    let mut puzzles = vec![];
+    let mut position = Position::default();
+    for (played, analysed) in record.moves.iter().zip(&analysis.moves) {
+        position.play(played.as_move());
+        if analysed.judgement != Judgement::Blunder {
+            continue;
+        }
+        let Some(line) = solution(&position, limits) else {
+            continue;
+        };
+        puzzles.push(Puzzle {
+            id: format!("{}-{}", record.id, analysed.ply),
+            game: record.id.clone(),
+            ply: analysed.ply,
+            fen: fen(&position),
+            last_move: analysed.uci.clone(),
+            solution: line.into_iter().map(uci_move).collect(),
+            rating: Rating::default(),
+        });
+    }
+    puzzles
+}
+
+// every puzzle mined so far, by id
+#[derive(Default)]
+pub struct Puzzles {
+    puzzles: HashMap<String, Puzzle>,
+}
+
+impl Puzzles {
+    pub fn add(&mut self, puzzle: Puzzle) {
+        self.puzzles.insert(puzzle.id.clone(), puzzle);
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut Puzzle> {
+        self.puzzles.get_mut(id)
+    }
+
+    // the puzzle rated closest to the player among those they have not tried
+    pub fn pick(&self, rating: i32, tried: &BTreeSet<String>) -> Option<&Puzzle> {
+        self.puzzles
+            .values()
+            .filter(|puzzle| !tried.contains(&puzzle.id))
+            .min_by(|a, b| {
+                let distance = |puzzle: &Puzzle| (puzzle.rating.rating - rating).abs();
+                distance(a).cmp(&distance(b)).then_with(|| a.id.cmp(&b.id))
+            })
+    }
+}
+
+pub enum Progress {
+    Reply(PuzzleReply),
+    Solved,
+    Failed,
+}
+
+// a player working through a puzzle, one move at a time
+pub struct Attempt {
+    puzzle: Puzzle,
+    rules: Box<dyn Variant>,
+    position: Position,
+    // index into the solution of the move expected next
+    step: usize,
+}
+
+impl Attempt {
+    // None if the stored puzzle no longer reads back
+    pub fn new(puzzle: Puzzle) -> Option<Self> {
+        let position = parse_fen(&puzzle.fen)?;
+        Some(Self {
+            puzzle,
+            rules: GameVariant::Standard.rules(),
+            position,
+            step: 0,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.puzzle.id
+    }
+
+    pub fn solution(&self) -> Vec<String> {
+        self.puzzle.solution.clone()
+    }
+
+    pub fn view(&self) -> PuzzleView {
+        PuzzleView {
+            id: self.puzzle.id.clone(),
+            fen: self.puzzle.fen.clone(),
+            last_move: self.puzzle.last_move.clone(),
+            color: Color::from_index(self.position.turn),
+            rating: self.puzzle.rating.rating,
+        }
+    }
+
+    // a move that breaks the rules is refused and can be taken back, a legal
+    // one that is not the solution fails the puzzle
+    pub fn play(&mut self, details: &MoveDetails) -> Result<Progress, MoveError> {
+        let mv = details.to_move(&self.position)?;
+        self.rules.check_move(&self.position, mv)?;
+        let expected = self
+            .puzzle
+            .solution
+            .get(self.step)
+            .and_then(|text| parse_move(&self.position, text));
+        self.position.play(mv);
+        if mated(&self.position) {
+            return Ok(Progress::Solved);
+        }
+        if expected != Some(mv) {
+            return Ok(Progress::Failed);
+        }
+        let reply = self
+            .puzzle
+            .solution
+            .get(self.step + 1)
+            .and_then(|text| parse_move(&self.position, text));
+        let Some(reply) = reply else {
+            return Ok(Progress::Solved);
+        };
+        let san = san(&self.position, reply);
+        self.position.play(reply);
+        self.step += 2;
+        Ok(Progress::Reply(PuzzleReply {
+            uci: uci_move(reply),
+            san,
+            fen: fen(&self.position),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::analyse,
+        game::{record_from_uci, ChessPiece, PieceVariant, Pos},
+    };
+    use std::time::Duration;
+
+    fn limits() -> Limits {
+        Limits {
+            depth: 3,
+            nodes: u64::MAX,
+            time: Duration::from_secs(10),
+        }
+    }
+
+    fn puzzle(fen: &str, solution: &str) -> Puzzle {
+        Puzzle {
+            id: "test".to_owned(),
+            game: "test".to_owned(),
+            ply: 1,
+            fen: fen.to_owned(),
+            last_move: "e2e4".to_owned(),
+            solution: solution.split_whitespace().map(str::to_owned).collect(),
+            rating: Rating::default(),
+        }
+    }
+
+    fn details(position: &Position, text: &str) -> MoveDetails {
+        let mv = parse_move(position, text).unwrap();
+        MoveDetails {
+            piece: position.board[mv.from].unwrap(),
+            from: Pos::from_index(mv.from),
+            to: Pos::from_index(mv.to),
+            promotion: None,
+        }
+    }
+
+    #[test]
+    fn a_hung_queen_makes_a_puzzle() {
+        // 1. e4 d6 2. Qg4?? Bxg4
+        let game = record_from_uci(GameVariant::Standard, "e2e4 d7d6 d1g4");
+        let puzzles = mine(&game, &analyse(&game, limits()), limits());
+        assert_eq!(puzzles.len(), 1);
+        let puzzle = &puzzles[0];
+        assert_eq!((puzzle.id.as_str(), puzzle.ply), ("test-3", 3));
+        assert_eq!(puzzle.last_move, "d1g4");
+        assert_eq!(puzzle.solution, ["c8g4"]);
+        let start = parse_fen(&puzzle.fen).unwrap();
+        assert_eq!(start.turn, 1);
+    }
+
+    #[test]
+    fn moves_are_checked_against_the_solution() {
+        let start = Position::default();
+        let mut attempt = Attempt::new(puzzle(&fen(&start), "e2e4 e7e5 g1f3")).unwrap();
+        assert_eq!(attempt.view().color, Color::White);
+        let Ok(Progress::Reply(reply)) = attempt.play(&details(&start, "e2e4")) else {
+            panic!("e4 is the first move of the solution");
+        };
+        assert_eq!((reply.uci.as_str(), reply.san.as_str()), ("e7e5", "e5"));
+        let position = parse_fen(&reply.fen).unwrap();
+        // a move against the rules is refused without failing the puzzle
+        let illegal = MoveDetails {
+            piece: ChessPiece::new(0, PieceVariant::King),
+            from: Pos::from_index(4),
+            to: Pos::from_index(20),
+            promotion: None,
+        };
+        assert!(attempt.play(&illegal).is_err());
+        let mut wrong = Attempt::new(puzzle(&fen(&start), "e2e4 e7e5 g1f3")).unwrap();
+        wrong.play(&details(&start, "e2e4")).unwrap();
+        assert!(matches!(
+            wrong.play(&details(&position, "d2d4")),
+            Ok(Progress::Failed)
+        ));
+        assert!(matches!(
+            attempt.play(&details(&position, "g1f3")),
+            Ok(Progress::Solved)
+        ));
+    }
+
+    #[test]
+    fn any_mate_solves_it() {
+        let fen = "6k1/5ppp/8/8/8/8/5PPP/R3R1K1 w - - 0 1";
+        let position = parse_fen(fen).unwrap();
+        let mut attempt = Attempt::new(puzzle(fen, "a1a8")).unwrap();
+        assert!(matches!(
+            attempt.play(&details(&position, "e1e8")),
+            Ok(Progress::Solved)
+        ));
+    }
+
+    #[test]
+    fn picks_the_closest_puzzle_not_yet_tried() {
+        let mut puzzles = Puzzles::default();
+        for (id, rating) in [("easy", 1200), ("fair", 1550), ("hard", 1900)] {
+            puzzles.add(Puzzle {
+                id: id.to_owned(),
+                rating: Rating { rating, games: 0 },
+                ..puzzle("", "")
+            });
+        }
+        let tried = BTreeSet::new();
+        assert_eq!(puzzles.pick(1500, &tried).unwrap().id, "fair");
+        let tried = BTreeSet::from(["fair".to_owned()]);
+        assert_eq!(puzzles.pick(1500, &tried).unwrap().id, "easy");
+        let tried = BTreeSet::from(["easy", "fair", "hard"].map(str::to_owned));
+        assert!(puzzles.pick(1500, &tried).is_none());
+    }
+}
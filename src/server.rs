@@ -1,60 +1,866 @@
-use actix::{Actor, Context, Handler, Message as ActixMessage, Recipient};
-use std::collections::HashMap;
+use actix::{
+    Actor, ActorFutureExt, Addr, AsyncContext, Context, Handler, Message as ActixMessage,
+    MessageResult, Recipient, ResponseFuture, WrapFuture,
+};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{oneshot, Semaphore};
 
 use crate::{
+    analysis::{Analyze, Analyzer},
+    bot::{ChallengeFilter, Challenger},
+    chat::{ChatError, ChatLine, ChatPolicy, ChatScope},
     chessclient::Message,
-    game::Game,
-    message::{ClientResult, Disconnect, Login, Logout, OutgoingMessage},
+    config::{BotConfig, UciConfig},
+    engine::{
+        uci::{evaluate_game, UciEngine, UciPlayer},
+        Engine,
+    },
+    explorer::{Explorer, ExplorerFilter, MoveStats},
+    game::{
+        Adjourn, AdjournedGame, Game, GameChat, GameRecord, GameResult, GameVariant, Player,
+        Position, Rejoin, TimeControl,
+    },
+    message::{
+        Challenge, ClientResult, Color, Connect, CorrespondenceGame, Disconnect, LobbyEvent, Login,
+        Logout, OutgoingMessage, PlayerPresence, SubscribeLobby,
+    },
+    profile::{self, valid_username, Category, Profile, ProfileView, Rating},
+    puzzle::{Puzzle, Puzzles},
+    storage::Storage,
+    tournament::TournamentGameOver,
 };
 
+// finished games shown on a player card
+const RECENT_GAMES: usize = 10;
+// the longest deadline a correspondence seek can ask for
+pub const MAX_DAYS_PER_MOVE: u32 = 14;
+
+struct Seeker {
+    client: Recipient<Message>,
+    username: Option<String>,
+    bot: bool,
+    variant: GameVariant,
+    // Some for a correspondence seek
+    days_per_move: Option<u32>,
+}
+
+// the two sides of a finished game, kept so they can ask for a rematch
+struct Pairing {
+    // white first, as they sat
+    players: [Seeker; 2],
+    // seat that asked and the variant it wants
+    offer: Option<(usize, GameVariant)>,
+}
+
+struct LiveGame {
+    addr: Addr<Game>,
+    summary: LiveGameSummary,
+}
+
+// a correspondence game and how it stood when it last changed
+struct Correspondence {
+    addr: Addr<Game>,
+    saved: AdjournedGame,
+}
+
+impl Correspondence {
+    fn seen_by(&self, username: &str) -> Option<CorrespondenceGame> {
+        let saved = &self.saved;
+        let seat = saved.players.iter().position(|p| p == username)?;
+        let turn = saved.moves.len() % 2;
+        Some(CorrespondenceGame {
+            id: saved.id.clone(),
+            opponent: saved.players[1 - seat].clone(),
+            color: Color::from_index(seat),
+            variant: saved.variant,
+            days_per_move: saved.time_control.days_per_move.unwrap_or_default(),
+            your_move: turn == seat,
+            deadline: saved.saved_at + saved.clocks[turn] / 1000,
+        })
+    }
+}
+
+// sits in the seat of a correspondence player who is offline or elsewhere,
+// so the game has someone to talk to
+struct Absent;
+
+impl Actor for Absent {
+    type Context = Context<Self>;
+}
+
+impl Handler<Message> for Absent {
+    type Result = ();
+    fn handle(&mut self, _msg: Message, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
+#[derive(Serialize, Clone)]
+pub struct LiveGameSummary {
+    pub id: String,
+    // usernames of white and black, None for anonymous players
+    pub players: [Option<String>; 2],
+    pub time_control: TimeControl,
+    pub variant: GameVariant,
+    // unix seconds
+    pub started_at: u64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct GameSummary {
+    pub id: String,
+    pub players: [Option<String>; 2],
+    pub time_control: TimeControl,
+    pub variant: GameVariant,
+    pub started_at: u64,
+    pub ended_at: Option<u64>,
+    pub result: Option<GameResult>,
+    pub reason: Option<String>,
+}
+
+impl From<&GameRecord> for GameSummary {
+    fn from(record: &GameRecord) -> Self {
+        Self {
+            id: record.id.clone(),
+            players: record.players.clone(),
+            time_control: record.time_control,
+            variant: record.variant,
+            started_at: record.started_at,
+            ended_at: record.ended_at,
+            result: record.result,
+            reason: record.reason.clone(),
+        }
+    }
+}
+
 pub struct Server {
+    // every open connection, logged in or not
+    sessions: HashSet<Recipient<Message>>,
     users: HashMap<String, Recipient<Message>>,
-    waiting_for_game: Option<Recipient<Message>>,
+    // logged in users who turned lobby updates off
+    lobby_opt_out: HashSet<String>,
+    // games still in progress for each logged in player, so they can be rejoined
+    playing: HashMap<String, Addr<Game>>,
+    // every running game by id
+    games: HashMap<String, LiveGame>,
+    // correspondence games by id. they are not live games: nobody has to be
+    // connected, and shutting down leaves them on disk as they are
+    correspondence: HashMap<String, Correspondence>,
+    absent: Recipient<Message>,
+    // tournament games by id, with where to report their result
+    tournament_games: HashMap<String, Recipient<TournamentGameOver>>,
+    // finished games of each user, oldest first
+    history: HashMap<String, Vec<GameSummary>>,
+    // everyone who has ever logged in
+    profiles: HashMap<String, Profile>,
+    explorer: Explorer,
+    puzzles: Puzzles,
+    // logged in bots taking challenges on their own, with what they take
+    accepting: HashMap<String, ChallengeFilter>,
+    last_game_id: u64,
+    // whether the computer takes white in the next game against an engine
+    // or a bot, flipped with every such game
+    computer_white: bool,
+    // games saved by an earlier shutdown, waiting for both players to return
+    adjourned: Vec<AdjournedGame>,
+    // open seeks, oldest first
+    waiting: Vec<Seeker>,
+    pairings: Vec<Pairing>,
+    time_control: TimeControl,
+    storage: Storage,
+    chat: ChatPolicy,
+    uci: UciConfig,
+    bots: BotConfig,
+    // one engine evaluation at a time, however many games finish together
+    evaluations: Arc<Semaphore>,
+    // finished games are queued here for the built in engine, None if turned off
+    analyzer: Option<Addr<Analyzer>>,
+    shutting_down: bool,
+    // adjourn requests sent to games that have not answered yet
+    adjourning: usize,
+    drained: Option<oneshot::Sender<()>>,
 }
 
 impl Server {
-    pub fn new() -> Self {
+    pub fn new(
+        time_control: TimeControl,
+        storage: Storage,
+        chat: ChatPolicy,
+        uci: UciConfig,
+        bots: BotConfig,
+        analyzer: Option<Addr<Analyzer>>,
+    ) -> Self {
         Self {
+            sessions: HashSet::new(),
             users: HashMap::new(),
-            waiting_for_game: None,
+            lobby_opt_out: HashSet::new(),
+            playing: HashMap::new(),
+            games: HashMap::new(),
+            correspondence: HashMap::new(),
+            absent: Absent.start().recipient(),
+            tournament_games: HashMap::new(),
+            history: HashMap::new(),
+            profiles: HashMap::new(),
+            explorer: Explorer::default(),
+            puzzles: Puzzles::default(),
+            accepting: HashMap::new(),
+            last_game_id: 0,
+            computer_white: false,
+            adjourned: vec![],
+            waiting: vec![],
+            pairings: vec![],
+            time_control,
+            storage,
+            chat,
+            uci,
+            bots,
+            evaluations: Arc::new(Semaphore::new(1)),
+            analyzer,
+            shutting_down: false,
+            adjourning: 0,
+            drained: None,
+        }
+    }
+
+    // hex of the microsecond clock, bumped past the previous id so none repeat
+    fn new_game_id(&mut self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        self.last_game_id = now.max(self.last_game_id + 1);
+        format!("{:x}", self.last_game_id)
+    }
+
+    fn track(&mut self, addr: Addr<Game>, summary: LiveGameSummary) {
+        for username in summary.players.iter().flatten() {
+            self.step_away(username, None);
+            self.playing.insert(username.clone(), addr.clone());
+            self.broadcast_lobby(LobbyEvent::InGame {
+                username: username.clone(),
+                game: summary.id.clone(),
+            });
+        }
+        self.games
+            .insert(summary.id.clone(), LiveGame { addr, summary });
+    }
+
+    // white first. two bots play on their own clock
+    fn start_game(&mut self, players: [Seeker; 2], variant: GameVariant, ctx: &mut Context<Self>) {
+        if let Some(days) = players[0].days_per_move {
+            return self.start_correspondence(players, days, variant, ctx);
+        }
+        let time_control = match players.iter().all(|seeker| seeker.bot) {
+            true => self.bots.time_control,
+            false => self.time_control,
+        };
+        for seeker in players.iter() {
+            self.forget_rematch(&seeker.client);
+        }
+        let summary = LiveGameSummary {
+            id: self.new_game_id(),
+            players: [0, 1].map(|p| players[p].username.clone()),
+            time_control,
+            variant: variant.resolve(),
+            started_at: crate::game::unix_now(),
+        };
+        let players = players.map(|seeker| Player::new(seeker.client, seeker.username));
+        let game = Game::new(
+            ctx.address(),
+            summary.id.clone(),
+            players,
+            time_control,
+            summary.variant,
+        )
+        .start();
+        self.track(game, summary);
+    }
+
+    // players busy in a live game only hear of it through the lobby
+    fn start_correspondence(
+        &mut self,
+        players: [Seeker; 2],
+        days: u32,
+        variant: GameVariant,
+        ctx: &mut Context<Self>,
+    ) {
+        let id = self.new_game_id();
+        let [Some(white), Some(black)] = [0, 1].map(|p| players[p].username.clone()) else {
+            return;
+        };
+        let players = players.map(|seeker| {
+            let username = seeker.username.unwrap_or_default();
+            let client = match self.playing.contains_key(&username) {
+                true => self.absent.clone(),
+                false => {
+                    self.step_away(&username, None);
+                    seeker.client
+                }
+            };
+            Player::new(client, Some(username))
+        });
+        let time_control = TimeControl::correspondence(days);
+        let variant = variant.resolve();
+        let game = Game::new(ctx.address(), id.clone(), players, time_control, variant).start();
+        // the game reports its first save as soon as it starts
+        let saved = AdjournedGame {
+            id: id.clone(),
+            started_at: crate::game::unix_now(),
+            players: [white, black],
+            time_control,
+            variant,
+            clocks: [time_control.initial_secs * 1000; 2],
+            moves: vec![],
+            chat: vec![],
+            saved_at: crate::game::unix_now(),
+        };
+        self.correspondence
+            .insert(id, Correspondence { addr: game, saved });
+    }
+
+    // leaves the player's correspondence games, except the one given, to the
+    // stand in so their moves stop reaching the player's connection
+    fn step_away(&self, username: &str, except: Option<&str>) {
+        for game in self.correspondence.values() {
+            if game.saved.players.iter().any(|p| p == username)
+                && except != Some(game.saved.id.as_str())
+            {
+                game.addr.do_send(Rejoin {
+                    username: username.to_owned(),
+                    client: self.absent.clone(),
+                });
+            }
+        }
+    }
+
+    fn correspondence_of(&self, username: &str) -> Vec<CorrespondenceGame> {
+        let mut games: Vec<_> = self
+            .correspondence
+            .values()
+            .filter_map(|game| game.seen_by(username))
+            .collect();
+        games.sort_by_key(|game| (!game.your_move, game.deadline));
+        games
+    }
+
+    // the first idle bot whose filter takes a seek
+    fn bot_for(&self, seeker: &Seeker) -> Option<String> {
+        // bots only play live games
+        if seeker.days_per_move.is_some() {
+            return None;
+        }
+        let category = Category::of(&match seeker.bot {
+            true => self.bots.time_control,
+            false => self.time_control,
+        });
+        let challenger = match &seeker.username {
+            None => Challenger::Anonymous,
+            Some(username) => {
+                let rating = self
+                    .profiles
+                    .get(username)
+                    .map(|profile| profile.rating(category))
+                    .unwrap_or_default()
+                    .rating;
+                match seeker.bot {
+                    true => Challenger::Bot { rating },
+                    false => Challenger::Human { rating },
+                }
+            }
+        };
+        self.accepting
+            .iter()
+            .filter(|(username, filter)| {
+                seeker.username.as_ref() != Some(*username)
+                    && self.users.contains_key(*username)
+                    && !self.playing.contains_key(*username)
+                    && filter.accepts(challenger, category, seeker.variant)
+            })
+            .map(|(username, _)| username)
+            .min()
+            .cloned()
+    }
+
+    // hands open seeks to idle bots whose filters take them
+    fn match_waiting(&mut self, ctx: &mut Context<Self>) {
+        while let Some((index, bot)) = self
+            .waiting
+            .iter()
+            .enumerate()
+            .find_map(|(index, seeker)| Some((index, self.bot_for(seeker)?)))
+        {
+            let seeker = self.take_seeker(index);
+            let variant = seeker.variant;
+            let bot = Seeker {
+                client: self.users[&bot].clone(),
+                username: Some(bot),
+                bot: true,
+                variant,
+                days_per_move: None,
+            };
+            self.computer_white = !self.computer_white;
+            let players = match self.computer_white {
+                false => [seeker, bot],
+                true => [bot, seeker],
+            };
+            self.start_game(players, variant, ctx);
+        }
+    }
+
+    fn is_bot(&self, username: &str) -> bool {
+        self.profiles
+            .get(username)
+            .is_some_and(|profile| profile.bot)
+    }
+
+    fn archive(&mut self, mut record: GameRecord, ctx: &mut Context<Self>) {
+        record.ratings = self.ratings_of(&record);
+        if let Err(err) = self.storage.save_game(&record) {
+            error!("Could not save game {}: {err}", record.id);
+        }
+        self.remember(&record);
+        self.explorer.add(&record);
+        self.score(&record);
+        // so does the built in engine, which mines the game for puzzles as it goes
+        if let Some(analyzer) = &self.analyzer {
+            if !record.moves.is_empty() && record.variant.standard_rules() {
+                let analysed =
+                    analyzer
+                        .send(Analyze(record.clone()))
+                        .into_actor(self)
+                        .map(|res, act, _| {
+                            for puzzle in res.unwrap_or_default() {
+                                act.puzzles.add(puzzle);
+                            }
+                        });
+                ctx.spawn(analysed);
+            }
+        }
+        // uci engines only know the standard rules
+        if self.uci.evaluate_games && !record.moves.is_empty() && record.variant.standard_rules() {
+            self.evaluate(record);
+        }
+    }
+
+    // runs the uci engine over a finished game in the background
+    fn evaluate(&self, record: GameRecord) {
+        let (config, storage) = (self.uci.clone(), self.storage.clone());
+        let permits = self.evaluations.clone();
+        actix::spawn(async move {
+            let Ok(_permit) = permits.acquire().await else {
+                return;
+            };
+            match evaluate_game(&config, &record).await {
+                Ok(evaluations) => {
+                    if let Err(err) = storage.save_evaluations(&evaluations) {
+                        error!("Could not save evaluations of {}: {err}", record.id);
+                    }
+                }
+                Err(err) => warn!("Could not evaluate game {}: {err}", record.id),
+            }
+        });
+    }
+
+    // seats a computer player against a human, alternating colours between games
+    fn start_engine_game(
+        &mut self,
+        client: Recipient<Message>,
+        username: Option<String>,
+        engine: Recipient<Message>,
+        ctx: &mut Context<Self>,
+    ) {
+        self.forget_rematch(&client);
+        let id = self.new_game_id();
+        let human = Player::new(client, username.clone());
+        let computer = Player::new(engine, None);
+        self.computer_white = !self.computer_white;
+        let (players, names) = match self.computer_white {
+            false => ([human, computer], [username, None]),
+            true => ([computer, human], [None, username]),
+        };
+        let summary = LiveGameSummary {
+            id,
+            players: names,
+            time_control: self.time_control,
+            variant: GameVariant::Standard,
+            started_at: crate::game::unix_now(),
+        };
+        let game = Game::new(
+            ctx.address(),
+            summary.id.clone(),
+            players,
+            summary.time_control,
+            summary.variant,
+        )
+        .start();
+        self.track(game, summary);
+    }
+
+    fn remember(&mut self, record: &GameRecord) {
+        for username in record.players.iter().flatten() {
+            self.history
+                .entry(username.clone())
+                .or_default()
+                .push(GameSummary::from(record));
+        }
+    }
+
+    // what both players were rated going into a rated game
+    fn ratings_of(&self, record: &GameRecord) -> Option<[i32; 2]> {
+        let [Some(white), Some(black)] = &record.players else {
+            return None;
+        };
+        if !record.variant.rated() {
+            return None;
+        }
+        let (white, black) = (self.profiles.get(white)?, self.profiles.get(black)?);
+        let category = Category::of(&record.time_control);
+        (white.bot == black.bot).then(|| [white, black].map(|p| p.rating(category).rating))
+    }
+
+    // counts the result for each named player and rates standard games
+    // between two of them. people and bots are rated in separate pools
+    fn score(&mut self, record: &GameRecord) {
+        let Some(result) = record.result else {
+            return;
+        };
+        let mut changed = vec![];
+        for (side, username) in record.players.iter().enumerate() {
+            if let Some(profile) = username.as_ref().and_then(|u| self.profiles.get_mut(u)) {
+                profile.count(result, side);
+                changed.push(profile.username.clone());
+            }
+        }
+        if let [Some(white), Some(black)] = &record.players {
+            if let [Some(white), Some(black)] = self.profiles.get_disjoint_mut([white, black]) {
+                if white.bot == black.bot && record.variant.rated() {
+                    profile::rate([white, black], Category::of(&record.time_control), result);
+                }
+            }
+        }
+        for username in changed {
+            if let Err(err) = self.storage.save_profile(&self.profiles[&username]) {
+                error!("Could not save profile of {username}: {err}");
+            }
+        }
+    }
+
+    fn game_of(&self, username: &str) -> Option<String> {
+        let addr = self.playing.get(username)?;
+        self.games
+            .values()
+            .find(|game| game.addr == *addr)
+            .map(|game| game.summary.id.clone())
+    }
+
+    fn challenge(&self, seeker: &Seeker) -> Challenge {
+        Challenge {
+            username: seeker.username.clone(),
+            time_control: seeker
+                .days_per_move
+                .map_or(self.time_control, TimeControl::correspondence),
+            variant: seeker.variant,
+        }
+    }
+
+    fn lobby_snapshot(&self) -> LobbyEvent {
+        let mut players: Vec<_> = self
+            .users
+            .keys()
+            .map(|username| PlayerPresence {
+                username: username.clone(),
+                bot: self.is_bot(username),
+                game: self.game_of(username),
+            })
+            .collect();
+        players.sort_by(|a, b| a.username.cmp(&b.username));
+        LobbyEvent::Snapshot {
+            players,
+            challenges: self.waiting.iter().map(|s| self.challenge(s)).collect(),
+        }
+    }
+
+    fn broadcast_lobby(&self, event: LobbyEvent) {
+        for (username, client) in self.users.iter() {
+            if !self.lobby_opt_out.contains(username) {
+                client.do_send(Message {
+                    inner: OutgoingMessage::Lobby(event.clone()),
+                    game: None,
+                });
+            }
+        }
+    }
+
+    // takes a seek out of the queue and its challenge off the lobby
+    fn take_seeker(&mut self, index: usize) -> Seeker {
+        let seeker = self.waiting.remove(index);
+        self.broadcast_lobby(LobbyEvent::ChallengeClosed(self.challenge(&seeker)));
+        seeker
+    }
+
+    // drops every seek that matches
+    fn withdraw(&mut self, matches: impl Fn(&Seeker) -> bool) {
+        while let Some(index) = self.waiting.iter().position(&matches) {
+            self.take_seeker(index);
+        }
+    }
+
+    fn forget_rematch(&mut self, client: &Recipient<Message>) {
+        self.pairings
+            .retain(|pairing| pairing.players.iter().all(|p| p.client != *client));
+    }
+
+    // registers the account on its first login
+    fn ensure_profile(&mut self, username: &str, bot: bool) {
+        if self.profiles.contains_key(username) {
+            return;
+        }
+        // a profile that was skipped at startup is still on disk, and must
+        // not be written over with a new one
+        let profile = match self.storage.load_profile(username) {
+            Ok(Some(profile)) => profile,
+            Ok(None) => {
+                let profile = Profile {
+                    bot,
+                    ..Profile::new(username.to_owned(), crate::game::unix_now())
+                };
+                if let Err(err) = self.storage.save_profile(&profile) {
+                    error!("Could not save profile of {username}: {err}");
+                }
+                profile
+            }
+            Err(err) => {
+                error!("Could not load profile of {username}, playing unrated: {err}");
+                return;
+            }
+        };
+        self.profiles.insert(username.to_owned(), profile);
+    }
+
+    // restarts an adjourned game once both of its players are online and free
+    fn resume_adjourned(&mut self, username: &str, ctx: &mut Context<Self>) {
+        if self.shutting_down {
+            return;
+        }
+        let ready = self.adjourned.iter().position(|saved| {
+            saved.players.iter().any(|p| p == username)
+                && saved
+                    .players
+                    .iter()
+                    .all(|p| self.users.contains_key(p) && !self.playing.contains_key(p))
+        });
+        let Some(index) = ready else {
+            return;
+        };
+        let saved = self.adjourned.remove(index);
+        if let Err(err) = self.storage.remove_adjourned(&saved.id) {
+            warn!("Could not remove adjourned game {}: {err}", saved.id);
+        }
+        self.withdraw(|seeker| {
+            seeker
+                .username
+                .as_ref()
+                .is_some_and(|name| saved.players.contains(name))
+        });
+        info!("Resuming adjourned game {}", saved.id);
+        let players = saved
+            .players
+            .clone()
+            .map(|name| Player::new(self.users[&name].clone(), Some(name)));
+        let game = Game::resume(ctx.address(), players, &saved).start();
+        let summary = LiveGameSummary {
+            id: saved.id,
+            players: saved.players.map(Some),
+            time_control: saved.time_control,
+            variant: saved.variant,
+            started_at: saved.started_at,
+        };
+        self.track(game, summary);
+    }
+
+    fn adjourn_games(&mut self, ctx: &mut Context<Self>) {
+        for game in self.games.values() {
+            self.adjourning += 1;
+            let adjourn = game.addr.send(Adjourn).into_actor(self).map(|res, act, _| {
+                act.adjourning -= 1;
+                if let Ok(Some(saved)) = res {
+                    match act.storage.save_adjourned(&saved) {
+                        Ok(()) => info!("Adjourned game {}", saved.id),
+                        Err(err) => error!("Could not save adjourned game {}: {err}", saved.id),
+                    }
+                }
+                act.check_drained();
+            });
+            ctx.spawn(adjourn);
+        }
+    }
+
+    fn check_drained(&mut self) {
+        if self.shutting_down && self.games.is_empty() && self.adjourning == 0 {
+            if let Some(drained) = self.drained.take() {
+                let _ = drained.send(());
+            }
         }
     }
 }
 
 impl Actor for Server {
     type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        match self.storage.load_adjourned() {
+            Ok(adjourned) => self.adjourned = adjourned,
+            Err(err) => error!("Could not load adjourned games: {err}"),
+        }
+        match self.storage.load_correspondence() {
+            Ok(games) => {
+                for saved in games {
+                    // deadlines that passed while the server was down end the game at once
+                    let players = saved
+                        .players
+                        .clone()
+                        .map(|name| Player::new(self.absent.clone(), Some(name)));
+                    let addr = Game::resume(ctx.address(), players, &saved).start();
+                    self.correspondence
+                        .insert(saved.id.clone(), Correspondence { addr, saved });
+                }
+            }
+            Err(err) => error!("Could not load correspondence games: {err}"),
+        }
+        match self.storage.load_games() {
+            Ok(mut records) => {
+                records.sort_by_key(|record| record.ended_at);
+                for record in records.iter() {
+                    self.remember(record);
+                    self.explorer.add(record);
+                }
+            }
+            Err(err) => error!("Could not load finished games: {err}"),
+        }
+        match self.storage.load_puzzles() {
+            Ok(puzzles) => {
+                for puzzle in puzzles {
+                    self.puzzles.add(puzzle);
+                }
+            }
+            Err(err) => error!("Could not load puzzles: {err}"),
+        }
+        match self.storage.load_profiles() {
+            Ok(profiles) => {
+                self.profiles = profiles
+                    .into_iter()
+                    .map(|profile| (profile.username.clone(), profile))
+                    .collect()
+            }
+            Err(err) => error!("Could not load profiles: {err}"),
+        }
+    }
 }
 
-impl Handler<Login> for Server {
+impl Handler<Connect> for Server {
     type Result = ();
-    fn handle(&mut self, msg: Login, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: Connect, _ctx: &mut Self::Context) -> Self::Result {
+        self.sessions.insert(msg.player);
+    }
+}
+
+impl Handler<Login> for Server {
+    type Result = bool;
+    fn handle(&mut self, msg: Login, ctx: &mut Self::Context) -> Self::Result {
+        // an account stays whatever kind it was registered as
+        let accepted = valid_username(&msg.username)
+            && !self.users.contains_key(&msg.username)
+            && self
+                .profiles
+                .get(&msg.username)
+                .is_none_or(|profile| profile.bot == msg.bot)
+            && (!msg.bot || msg.authenticated || !self.bots.require_certificate);
         let res = Message {
-            inner: match self.users.get(&msg.username) {
-                Some(_) => OutgoingMessage::Result(ClientResult::LoginError),
-                None => OutgoingMessage::Result(ClientResult::Ok),
+            inner: match accepted {
+                false => OutgoingMessage::Result(ClientResult::LoginError),
+                true => OutgoingMessage::Result(ClientResult::Ok),
             },
             game: None,
         };
         msg.client.do_send(res);
+        if accepted {
+            self.broadcast_lobby(LobbyEvent::Joined(msg.username.clone()));
+            self.users.insert(msg.username.clone(), msg.client.clone());
+            self.ensure_profile(&msg.username, msg.bot);
+            match msg.lobby {
+                true => msg.client.do_send(Message {
+                    inner: OutgoingMessage::Lobby(self.lobby_snapshot()),
+                    game: None,
+                }),
+                false => {
+                    self.lobby_opt_out.insert(msg.username.clone());
+                }
+            }
+            let correspondence = self.correspondence_of(&msg.username);
+            if !correspondence.is_empty() {
+                msg.client.do_send(Message {
+                    inner: OutgoingMessage::Correspondence(correspondence),
+                    game: None,
+                });
+            }
+            match self.playing.get(&msg.username) {
+                Some(game) => game.do_send(Rejoin {
+                    username: msg.username,
+                    client: msg.client,
+                }),
+                None => self.resume_adjourned(&msg.username, ctx),
+            }
+        }
+        accepted
     }
 }
 
 impl Handler<Logout> for Server {
     type Result = ();
     fn handle(&mut self, msg: Logout, _ctx: &mut Self::Context) -> Self::Result {
-        self.users.remove(&msg.username);
+        if self.users.remove(&msg.username).is_some() {
+            self.lobby_opt_out.remove(&msg.username);
+            self.accepting.remove(&msg.username);
+            self.chat.forget(&msg.username);
+            self.broadcast_lobby(LobbyEvent::Left(msg.username));
+        }
     }
 }
 
-impl Handler<Disconnect> for Server {
+impl Handler<SubscribeLobby> for Server {
     type Result = ();
-    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) -> Self::Result {
-        if Some(msg.player) == self.waiting_for_game {
-            self.waiting_for_game = None;
+    fn handle(&mut self, msg: SubscribeLobby, _ctx: &mut Self::Context) -> Self::Result {
+        let Some(client) = self.users.get(&msg.username) else {
+            return;
+        };
+        match msg.enabled {
+            true => {
+                if self.lobby_opt_out.remove(&msg.username) {
+                    client.do_send(Message {
+                        inner: OutgoingMessage::Lobby(self.lobby_snapshot()),
+                        game: None,
+                    });
+                }
+            }
+            false => {
+                self.lobby_opt_out.insert(msg.username);
+            }
         }
     }
 }
 
+impl Handler<Disconnect> for Server {
+    type Result = ();
+    fn handle(&mut self, msg: Disconnect, _ctx: &mut Self::Context) -> Self::Result {
+        self.sessions.remove(&msg.player);
+        self.withdraw(|seeker| seeker.client == msg.player);
+        self.forget_rematch(&msg.player);
+    }
+}
+
 #[derive(ActixMessage)]
 #[rtype(result = "Vec<String>")]
 pub struct GetPlayers {}
@@ -64,42 +870,364 @@ impl Handler<GetPlayers> for Server {
     fn handle(&mut self, _msg: GetPlayers, _ctx: &mut Self::Context) -> Self::Result {
         self.users
             .keys()
-            .into_iter()
             .map(|name| name.to_string())
             .collect::<Vec<String>>()
     }
 }
 
+pub struct ServerStats {
+    pub users: usize,
+    pub queue: usize,
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "ServerStats")]
+pub struct GetStats;
+
+impl Handler<GetStats> for Server {
+    type Result = MessageResult<GetStats>;
+    fn handle(&mut self, _msg: GetStats, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(ServerStats {
+            users: self.users.len(),
+            queue: self.waiting.len(),
+        })
+    }
+}
+
 #[derive(ActixMessage)]
 #[rtype(result = "()")]
-pub struct FindGame(pub Recipient<Message>);
+pub struct FindGame {
+    pub client: Recipient<Message>,
+    pub username: Option<String>,
+    pub variant: GameVariant,
+    // Some to seek a correspondence game
+    pub days_per_move: Option<u32>,
+}
 
 impl Handler<FindGame> for Server {
     type Result = ();
-    fn handle(&mut self, msg: FindGame, _ctx: &mut Self::Context) -> Self::Result {
-        if let Some(other) = &self.waiting_for_game {
-            if other != &msg.0.clone() {
-                let game = Game::new([other.clone(), msg.0.clone()]).start();
-                other.do_send(Message {
-                    inner: crate::message::OutgoingMessage::GameStarted(
-                        crate::message::Color::White,
-                    ),
-                    game: Some(game.clone()),
-                });
-                msg.0.do_send(Message {
-                    inner: crate::message::OutgoingMessage::GameStarted(
-                        crate::message::Color::Black,
-                    ),
-                    game: Some(game),
+    fn handle(&mut self, msg: FindGame, ctx: &mut Self::Context) -> Self::Result {
+        let reply = |result| {
+            msg.client.do_send(Message {
+                inner: OutgoingMessage::Result(result),
+                game: None,
+            })
+        };
+        if self.shutting_down {
+            return reply(ClientResult::ShuttingDown);
+        }
+        let bot = msg.username.as_ref().is_some_and(|name| self.is_bot(name));
+        if let Some(days) = msg.days_per_move {
+            // games that outlive a connection need someone to come back to them
+            if msg.username.is_none() || bot {
+                return reply(ClientResult::NotLoggedIn);
+            }
+            if !(1..=MAX_DAYS_PER_MOVE).contains(&days) {
+                return reply(ClientResult::InvalidDays);
+            }
+        }
+        let seeker = Seeker {
+            client: msg.client,
+            username: msg.username,
+            bot,
+            variant: msg.variant,
+            days_per_move: msg.days_per_move,
+        };
+        if self.waiting.iter().any(|other| {
+            other.client == seeker.client
+                && other.variant == seeker.variant
+                && other.days_per_move == seeker.days_per_move
+        }) {
+            return;
+        }
+        // a new seek replaces the connection's earlier one
+        self.withdraw(|other| other.client == seeker.client);
+        let opponent = self
+            .waiting
+            .iter()
+            .enumerate()
+            .filter(|(_, other)| other.days_per_move == seeker.days_per_move)
+            .find_map(|(index, other)| Some((index, other.variant.pairs_with(seeker.variant)?)));
+        match opponent {
+            Some((index, variant)) => {
+                let other = self.take_seeker(index);
+                self.start_game([other, seeker], variant, ctx);
+            }
+            None => {
+                self.broadcast_lobby(LobbyEvent::ChallengeOpened(self.challenge(&seeker)));
+                self.waiting.push(seeker);
+                self.match_waiting(ctx);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Opponent {
+    // the in process engine at a strength level
+    Builtin(u8),
+    // the configured uci binary
+    Uci,
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct PlayEngine {
+    pub client: Recipient<Message>,
+    pub username: Option<String>,
+    pub opponent: Opponent,
+}
+
+impl Handler<PlayEngine> for Server {
+    type Result = ();
+    fn handle(&mut self, msg: PlayEngine, ctx: &mut Self::Context) -> Self::Result {
+        if self.shutting_down {
+            msg.client.do_send(Message {
+                inner: OutgoingMessage::Result(ClientResult::ShuttingDown),
+                game: None,
+            });
+            return;
+        }
+        self.withdraw(|seeker| seeker.client == msg.client);
+        match msg.opponent {
+            Opponent::Builtin(level) => {
+                let engine = Engine::new(level).start().recipient();
+                self.start_engine_game(msg.client, msg.username, engine, ctx);
+            }
+            Opponent::Uci if !self.uci.enabled() => msg.client.do_send(Message {
+                inner: OutgoingMessage::Result(ClientResult::EngineUnavailable),
+                game: None,
+            }),
+            Opponent::Uci => {
+                let config = self.uci.clone();
+                let start = async move { UciEngine::start(&config).await }
+                    .into_actor(self)
+                    .map(move |res, act, ctx| match res {
+                        Ok(engine) => {
+                            let player = UciPlayer::new(engine, act.uci.movetime()).start();
+                            act.start_engine_game(
+                                msg.client,
+                                msg.username,
+                                player.recipient(),
+                                ctx,
+                            );
+                        }
+                        Err(err) => {
+                            error!("{err}");
+                            msg.client.do_send(Message {
+                                inner: OutgoingMessage::Result(ClientResult::EngineUnavailable),
+                                game: None,
+                            });
+                        }
+                    });
+                ctx.spawn(start);
+            }
+        }
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct AcceptChallenges {
+    pub username: String,
+    pub client: Recipient<Message>,
+    pub filter: Option<ChallengeFilter>,
+}
+
+impl Handler<AcceptChallenges> for Server {
+    type Result = ();
+    fn handle(&mut self, msg: AcceptChallenges, ctx: &mut Self::Context) -> Self::Result {
+        let reply = |result| {
+            msg.client.do_send(Message {
+                inner: OutgoingMessage::Result(result),
+                game: None,
+            })
+        };
+        if !self.is_bot(&msg.username) {
+            return reply(ClientResult::NotABot);
+        }
+        match msg.filter {
+            Some(filter) => self.accepting.insert(msg.username.clone(), filter),
+            None => self.accepting.remove(&msg.username),
+        };
+        reply(ClientResult::Ok);
+        self.match_waiting(ctx);
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Rematch {
+    pub client: Recipient<Message>,
+    // None keeps the variant of the last game, or takes up the opponent's offer
+    pub variant: Option<GameVariant>,
+}
+
+impl Handler<Rematch> for Server {
+    type Result = ();
+    fn handle(&mut self, msg: Rematch, ctx: &mut Self::Context) -> Self::Result {
+        let reply = |result| {
+            msg.client.do_send(Message {
+                inner: OutgoingMessage::Result(result),
+                game: None,
+            })
+        };
+        if self.shutting_down {
+            return reply(ClientResult::ShuttingDown);
+        }
+        let found = self
+            .pairings
+            .iter()
+            .enumerate()
+            .find_map(|(index, pairing)| {
+                let seat = pairing
+                    .players
+                    .iter()
+                    .position(|p| p.client == msg.client)?;
+                Some((index, seat))
+            });
+        let Some((index, seat)) = found else {
+            return reply(ClientResult::NoRematch);
+        };
+        // computer opponents leave as soon as the game is over
+        if !self.pairings[index].players[1 - seat].client.connected() {
+            self.pairings.remove(index);
+            return reply(ClientResult::NoRematch);
+        }
+        let pairing = &mut self.pairings[index];
+        let agreed = match (pairing.offer, msg.variant) {
+            (Some((by, offered)), None) if by != seat => Some(offered),
+            (Some((by, offered)), Some(wanted)) if by != seat => offered.pairs_with(wanted),
+            _ => None,
+        };
+        match agreed {
+            Some(variant) => {
+                let Pairing {
+                    players: [white, black],
+                    ..
+                } = self.pairings.remove(index);
+                // colours swap for the rematch
+                self.start_game([black, white], variant, ctx);
+            }
+            // anything else is a new offer, or a counter offer
+            None => {
+                let variant = msg.variant.unwrap_or(pairing.players[seat].variant);
+                pairing.offer = Some((seat, variant));
+                pairing.players[1 - seat].client.do_send(Message {
+                    inner: OutgoingMessage::RematchOffered(variant),
+                    game: None,
                 });
-                self.waiting_for_game = None;
+                reply(ClientResult::Ok);
             }
-        } else {
-            self.waiting_for_game = Some(msg.0);
         }
     }
 }
 
+// a game paired by the tournament manager. both players have to be online
+// and free, the error marks those who are not
+#[derive(ActixMessage)]
+#[rtype(result = "Result<String, [bool; 2]>")]
+pub struct StartTournamentGame {
+    pub players: [String; 2],
+    pub time_control: TimeControl,
+    pub variant: GameVariant,
+    // arena games let the players go berserk
+    pub berserk: bool,
+    pub reply: Recipient<TournamentGameOver>,
+}
+
+impl Handler<StartTournamentGame> for Server {
+    type Result = Result<String, [bool; 2]>;
+    fn handle(&mut self, msg: StartTournamentGame, ctx: &mut Self::Context) -> Self::Result {
+        let missing = msg.players.clone().map(|name| {
+            self.shutting_down
+                || !self.users.contains_key(&name)
+                || self.playing.contains_key(&name)
+        });
+        if missing.contains(&true) {
+            return Err(missing);
+        }
+        self.withdraw(|seeker| {
+            seeker
+                .username
+                .as_ref()
+                .is_some_and(|name| msg.players.contains(name))
+        });
+        let summary = LiveGameSummary {
+            id: self.new_game_id(),
+            players: msg.players.clone().map(Some),
+            time_control: msg.time_control,
+            variant: msg.variant.resolve(),
+            started_at: crate::game::unix_now(),
+        };
+        let players = msg.players.map(|name| {
+            let client = self.users[&name].clone();
+            self.forget_rematch(&client);
+            Player::new(client, Some(name))
+        });
+        let game = Game::new(
+            ctx.address(),
+            summary.id.clone(),
+            players,
+            summary.time_control,
+            summary.variant,
+        );
+        let game = match msg.berserk {
+            true => game.with_berserk(),
+            false => game,
+        }
+        .start();
+        let id = summary.id.clone();
+        self.tournament_games.insert(id.clone(), msg.reply);
+        self.track(game, summary);
+        Ok(id)
+    }
+}
+
+// a push to whichever of these users are online
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Notify {
+    pub usernames: Vec<String>,
+    pub message: OutgoingMessage,
+}
+
+impl Handler<Notify> for Server {
+    type Result = ();
+    fn handle(&mut self, msg: Notify, _ctx: &mut Self::Context) -> Self::Result {
+        for client in msg.usernames.iter().filter_map(|name| self.users.get(name)) {
+            client.do_send(Message {
+                inner: msg.message.clone(),
+                game: None,
+            });
+        }
+    }
+}
+
+// each user's rating in a category, in the order asked
+#[derive(ActixMessage)]
+#[rtype(result = "Vec<i32>")]
+pub struct GetRatings {
+    pub usernames: Vec<String>,
+    pub category: Category,
+}
+
+impl Handler<GetRatings> for Server {
+    type Result = Vec<i32>;
+    fn handle(&mut self, msg: GetRatings, _ctx: &mut Self::Context) -> Self::Result {
+        msg.usernames
+            .iter()
+            .map(|name| {
+                self.profiles
+                    .get(name)
+                    .map(|profile| profile.rating(msg.category))
+                    .unwrap_or_default()
+                    .rating
+            })
+            .collect()
+    }
+}
+
 #[derive(ActixMessage)]
 #[rtype(result = "()")]
 pub struct CancelSearch(pub Recipient<Message>);
@@ -107,10 +1235,487 @@ pub struct CancelSearch(pub Recipient<Message>);
 impl Handler<CancelSearch> for Server {
     type Result = ();
     fn handle(&mut self, msg: CancelSearch, _ctx: &mut Self::Context) -> Self::Result {
-        if let Some(other) = &self.waiting_for_game {
-            if other == &msg.0 {
-                self.waiting_for_game = None;
+        self.withdraw(|seeker| seeker.client == msg.0);
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct GameOver {
+    pub game: Addr<Game>,
+    pub id: String,
+    // white and black as they were connected at the end
+    pub clients: [Recipient<Message>; 2],
+    // None when the game was adjourned rather than finished
+    pub record: Option<GameRecord>,
+}
+
+impl Handler<GameOver> for Server {
+    type Result = ();
+    fn handle(&mut self, msg: GameOver, ctx: &mut Self::Context) -> Self::Result {
+        let mut freed = vec![];
+        self.playing.retain(|username, game| {
+            let done = *game == msg.game;
+            // players who dropped out mid game already left the lobby
+            if done && self.users.contains_key(username) {
+                freed.push(username.clone());
+            }
+            !done
+        });
+        for username in freed {
+            self.broadcast_lobby(LobbyEvent::Available(username));
+        }
+        self.games.remove(&msg.id);
+        if let Some(reply) = self.tournament_games.remove(&msg.id) {
+            let record = msg.record.as_ref();
+            reply.do_send(TournamentGameOver {
+                game: msg.id,
+                result: record.and_then(|record| record.result),
+                berserk: record.and_then(|record| record.berserk).unwrap_or_default(),
+            });
+            if let Some(record) = msg.record {
+                self.archive(record, ctx);
+            }
+            self.match_waiting(ctx);
+            return self.check_drained();
+        }
+        if self.correspondence.remove(&msg.id).is_some() {
+            if let Err(err) = self.storage.remove_correspondence(&msg.id) {
+                warn!("Could not remove correspondence game {}: {err}", msg.id);
             }
+            if let Some(record) = msg.record {
+                self.archive(record, ctx);
+            }
+            return;
+        }
+        if let Some(record) = msg.record {
+            let [white, black] = msg.clients;
+            let seat = |client, username: &Option<String>| Seeker {
+                client,
+                bot: username.as_ref().is_some_and(|name| self.is_bot(name)),
+                username: username.clone(),
+                variant: record.variant,
+                days_per_move: None,
+            };
+            let players = [
+                seat(white, &record.players[0]),
+                seat(black, &record.players[1]),
+            ];
+            self.pairings.push(Pairing {
+                players,
+                offer: None,
+            });
+            self.archive(record, ctx);
         }
+        // bots that just finished may take the open challenge
+        self.match_waiting(ctx);
+        self.check_drained();
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "Vec<LiveGameSummary>")]
+pub struct ListGames;
+
+impl Handler<ListGames> for Server {
+    type Result = MessageResult<ListGames>;
+    fn handle(&mut self, _msg: ListGames, _ctx: &mut Self::Context) -> Self::Result {
+        let mut games: Vec<_> = self.games.values().map(|g| g.summary.clone()).collect();
+        games.sort_by_key(|game| std::cmp::Reverse(game.started_at));
+        MessageResult(games)
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "Option<Addr<Game>>")]
+pub struct LookupGame(pub String);
+
+impl Handler<LookupGame> for Server {
+    type Result = Option<Addr<Game>>;
+    fn handle(&mut self, msg: LookupGame, _ctx: &mut Self::Context) -> Self::Result {
+        match self.games.get(&msg.0) {
+            Some(game) => Some(game.addr.clone()),
+            None => self
+                .correspondence
+                .get(&msg.0)
+                .map(|game| game.addr.clone()),
+        }
+    }
+}
+
+// the moves played from a position in the finished games
+#[derive(ActixMessage)]
+#[rtype(result = "Vec<MoveStats>")]
+pub struct Explore {
+    pub position: Position,
+    pub filter: ExplorerFilter,
+}
+
+impl Handler<Explore> for Server {
+    type Result = MessageResult<Explore>;
+    fn handle(&mut self, msg: Explore, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.explorer.explore(&msg.position, &msg.filter))
+    }
+}
+
+// the next puzzle for a player, rated near them and not tried before
+#[derive(ActixMessage)]
+#[rtype(result = "Option<Puzzle>")]
+pub struct NextPuzzle(pub String);
+
+impl Handler<NextPuzzle> for Server {
+    type Result = Option<Puzzle>;
+    fn handle(&mut self, msg: NextPuzzle, _ctx: &mut Self::Context) -> Self::Result {
+        let profile = self.profiles.get(&msg.0)?;
+        self.puzzles
+            .pick(profile.puzzle_rating.rating, &profile.puzzles)
+            .cloned()
+    }
+}
+
+// how a player did at a puzzle, resolves to their new puzzle rating
+#[derive(ActixMessage)]
+#[rtype(result = "Option<Rating>")]
+pub struct PuzzleResult {
+    pub username: String,
+    pub id: String,
+    pub solved: bool,
+}
+
+impl Handler<PuzzleResult> for Server {
+    type Result = Option<Rating>;
+    fn handle(&mut self, msg: PuzzleResult, _ctx: &mut Self::Context) -> Self::Result {
+        let profile = self.profiles.get_mut(&msg.username)?;
+        let puzzle = self.puzzles.get_mut(&msg.id)?;
+        // only the first try at a puzzle is rated
+        if profile.puzzles.insert(msg.id) {
+            profile::rate_puzzle(profile, &mut puzzle.rating, msg.solved);
+            if let Err(err) = self.storage.save_puzzle(puzzle) {
+                error!("Could not save puzzle {}: {err}", puzzle.id);
+            }
+            if let Err(err) = self.storage.save_profile(profile) {
+                error!("Could not save profile of {}: {err}", msg.username);
+            }
+        }
+        Some(profile.puzzle_rating)
+    }
+}
+
+// a finished game from the archive
+#[derive(ActixMessage)]
+#[rtype(result = "Option<GameRecord>")]
+pub struct LoadGame(pub String);
+
+impl Handler<LoadGame> for Server {
+    type Result = Option<GameRecord>;
+    fn handle(&mut self, msg: LoadGame, _ctx: &mut Self::Context) -> Self::Result {
+        match self.storage.load_game(&msg.0) {
+            Ok(record) => record,
+            Err(err) => {
+                error!("Could not load game {}: {err}", msg.0);
+                None
+            }
+        }
+    }
+}
+
+// a correspondence game changed. it is saved, and the player whose move it
+// now is hears about it if they are online
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct SaveCorrespondence(pub AdjournedGame);
+
+impl Handler<SaveCorrespondence> for Server {
+    type Result = ();
+    fn handle(&mut self, msg: SaveCorrespondence, _ctx: &mut Self::Context) -> Self::Result {
+        let saved = msg.0;
+        // a late save from a game that already ended
+        let Some(game) = self.correspondence.get_mut(&saved.id) else {
+            return;
+        };
+        if let Err(err) = self.storage.save_correspondence(&saved) {
+            error!("Could not save correspondence game {}: {err}", saved.id);
+        }
+        // a chat line or a resume changes nothing for whoever is to move,
+        // a new game does
+        let moved = saved.moves.len() != game.saved.moves.len();
+        game.saved = saved;
+        if !moved && !game.saved.moves.is_empty() {
+            return;
+        }
+        let to_move = &game.saved.players[game.saved.moves.len() % 2];
+        if let (Some(client), Some(view)) = (self.users.get(to_move), game.seen_by(to_move)) {
+            client.do_send(Message {
+                inner: OutgoingMessage::Lobby(LobbyEvent::YourMove(view)),
+                game: None,
+            });
+        }
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct ListCorrespondence {
+    pub username: String,
+    pub client: Recipient<Message>,
+}
+
+impl Handler<ListCorrespondence> for Server {
+    type Result = ();
+    fn handle(&mut self, msg: ListCorrespondence, _ctx: &mut Self::Context) -> Self::Result {
+        msg.client.do_send(Message {
+            inner: OutgoingMessage::Correspondence(self.correspondence_of(&msg.username)),
+            game: None,
+        });
+    }
+}
+
+// seats the connection in one of its correspondence games, which answers
+// with a snapshot. the player's other correspondence games carry on without it
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct OpenCorrespondence {
+    pub username: String,
+    pub client: Recipient<Message>,
+    pub id: String,
+}
+
+impl Handler<OpenCorrespondence> for Server {
+    type Result = ();
+    fn handle(&mut self, msg: OpenCorrespondence, _ctx: &mut Self::Context) -> Self::Result {
+        let reply = |result| {
+            msg.client.do_send(Message {
+                inner: OutgoingMessage::Result(result),
+                game: None,
+            })
+        };
+        let game = self
+            .correspondence
+            .get(&msg.id)
+            .filter(|game| game.saved.players.contains(&msg.username));
+        let Some(game) = game else {
+            return reply(ClientResult::NoSuchGame);
+        };
+        if self.playing.contains_key(&msg.username) {
+            return reply(ClientResult::InGame);
+        }
+        game.addr.do_send(Rejoin {
+            username: msg.username.clone(),
+            client: msg.client,
+        });
+        self.step_away(&msg.username, Some(&msg.id));
+    }
+}
+
+#[derive(Serialize)]
+pub struct HistoryPage {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub games: Vec<GameSummary>,
+}
+
+// a user's finished games, newest first
+#[derive(ActixMessage)]
+#[rtype(result = "HistoryPage")]
+pub struct GetHistory {
+    pub username: String,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Handler<GetHistory> for Server {
+    type Result = MessageResult<GetHistory>;
+    fn handle(&mut self, msg: GetHistory, _ctx: &mut Self::Context) -> Self::Result {
+        let history = self.history.get(&msg.username).map_or(&[][..], |h| &h[..]);
+        MessageResult(HistoryPage {
+            total: history.len(),
+            offset: msg.offset,
+            limit: msg.limit,
+            games: history
+                .iter()
+                .rev()
+                .skip(msg.offset)
+                .take(msg.limit)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Chat {
+    pub username: String,
+    pub client: Recipient<Message>,
+    pub scope: ChatScope,
+    // the sender's current game, needed for the game scope
+    pub game: Option<Addr<Game>>,
+    pub text: String,
+}
+
+impl Handler<Chat> for Server {
+    type Result = ();
+    fn handle(&mut self, msg: Chat, _ctx: &mut Self::Context) -> Self::Result {
+        let reject = |err| {
+            msg.client.do_send(Message {
+                inner: OutgoingMessage::Result(ClientResult::ChatError(err)),
+                game: None,
+            })
+        };
+        if msg.scope == ChatScope::Game && msg.game.is_none() {
+            return reject(ChatError::NotInGame);
+        }
+        let text = match self.chat.check(&msg.username, &msg.text, Instant::now()) {
+            Ok(text) => text,
+            Err(err) => return reject(err),
+        };
+        let line = ChatLine {
+            from: msg.username.clone(),
+            text,
+            at: crate::game::unix_now(),
+        };
+        match msg.game {
+            Some(game) if msg.scope == ChatScope::Game => game.do_send(GameChat {
+                player: msg.client,
+                line,
+            }),
+            _ => {
+                for (username, client) in self.users.iter() {
+                    // senders always see their own line, even with the lobby muted
+                    if *username == msg.username || !self.lobby_opt_out.contains(username) {
+                        client.do_send(Message {
+                            inner: OutgoingMessage::Chat {
+                                scope: ChatScope::Lobby,
+                                line: line.clone(),
+                            },
+                            game: None,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "Option<ProfileView>")]
+pub struct GetProfile(pub String);
+
+impl Handler<GetProfile> for Server {
+    type Result = Option<ProfileView>;
+    fn handle(&mut self, msg: GetProfile, _ctx: &mut Self::Context) -> Self::Result {
+        let profile = self.profiles.get(&msg.0)?;
+        let playing = self.game_of(&msg.0);
+        let history = self.history.get(&msg.0).map_or(&[][..], |h| &h[..]);
+        Some(ProfileView {
+            username: profile.username.clone(),
+            created_at: profile.created_at,
+            bot: profile.bot,
+            online: self.users.contains_key(&msg.0),
+            playing,
+            ratings: profile.ratings.clone(),
+            stats: profile.stats,
+            puzzle_rating: profile.puzzle_rating,
+            recent_games: history.iter().rev().take(RECENT_GAMES).cloned().collect(),
+        })
+    }
+}
+
+// stops matchmaking, warns every connection and gives running games the
+// grace period to finish before adjourning them. resolves once no game is left
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Shutdown {
+    pub grace: Duration,
+}
+
+impl Handler<Shutdown> for Server {
+    type Result = ResponseFuture<()>;
+    fn handle(&mut self, msg: Shutdown, ctx: &mut Self::Context) -> Self::Result {
+        let (drained, done) = oneshot::channel();
+        self.drained = Some(drained);
+        self.shutting_down = true;
+        self.withdraw(|_| true);
+        self.pairings.clear();
+        info!(
+            "Shutting down, {} game(s) have {}s to finish",
+            self.games.len(),
+            msg.grace.as_secs()
+        );
+        for client in self.sessions.iter() {
+            client.do_send(Message {
+                inner: OutgoingMessage::ServerShutdown {
+                    grace_secs: msg.grace.as_secs(),
+                },
+                game: None,
+            });
+        }
+        ctx.run_later(msg.grace, |act, ctx| act.adjourn_games(ctx));
+        self.check_drained();
+        Box::pin(async move {
+            let _ = done.await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chat::BlockList, config::ChatConfig, game::record_from_uci};
+
+    // a server on its own storage, not started
+    fn server(name: &str) -> (Server, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("chess-server-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let server = Server::new(
+            TimeControl::default(),
+            Storage::open(&dir).unwrap(),
+            ChatPolicy::new(&ChatConfig::default(), Box::new(BlockList::new(&[]))),
+            UciConfig::default(),
+            BotConfig::default(),
+            None,
+        );
+        (server, dir)
+    }
+
+    #[actix::test]
+    async fn only_standard_games_are_rated() {
+        let (mut server, dir) = server("rated");
+        server.ensure_profile("alice", false);
+        server.ensure_profile("bob", false);
+        let category = Category::of(&TimeControl::default());
+        for variant in [GameVariant::KingOfTheHill, GameVariant::Standard] {
+            let mut record = record_from_uci(variant, "e2e4 e7e5");
+            record.players = [Some("alice".to_owned()), Some("bob".to_owned())];
+            record.result = Some(GameResult::WhiteWins);
+            let ratings = server.ratings_of(&record);
+            let before = server.profiles["alice"].rating(category).rating;
+            server.score(&record);
+            let after = server.profiles["alice"].rating(category).rating;
+            assert_eq!(ratings.is_some(), variant == GameVariant::Standard);
+            assert_eq!(after > before, variant == GameVariant::Standard);
+        }
+        // the result still counts
+        assert_eq!(server.profiles["alice"].stats.wins, 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[actix::test]
+    async fn profiles_on_disk_are_never_replaced() {
+        let (mut server, dir) = server("profiles");
+        // saved while the server was not looking, as if skipped at startup
+        let mut alice = Profile::new("alice".to_owned(), 0);
+        alice.stats.wins = 7;
+        server.storage.save_profile(&alice).unwrap();
+        server.ensure_profile("alice", false);
+        assert_eq!(server.profiles["alice"].stats.wins, 7);
+        // one that cannot be read is left as it is
+        let bob = dir.join("users").join("bob.json");
+        std::fs::write(&bob, b"{").unwrap();
+        server.ensure_profile("bob", false);
+        assert!(!server.profiles.contains_key("bob"));
+        assert_eq!(std::fs::read(&bob).unwrap(), b"{");
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
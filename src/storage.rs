@@ -0,0 +1,330 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use log::error;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    analysis::Analysis,
+    engine::uci::Evaluations,
+    game::{AdjournedGame, GameRecord},
+    profile::Profile,
+    puzzle::Puzzle,
+};
+
+const ADJOURNED_DIR: &str = "adjourned";
+const GAMES_DIR: &str = "games";
+const USERS_DIR: &str = "users";
+const EVALUATIONS_DIR: &str = "evaluations";
+const CORRESPONDENCE_DIR: &str = "correspondence";
+const ANALYSIS_DIR: &str = "analysis";
+const PUZZLES_DIR: &str = "puzzles";
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(PathBuf, io::Error),
+    Json(PathBuf, serde_json::Error),
+    InvalidId(String),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(path, err) => write!(f, "{}: {err}", path.display()),
+            StorageError::Json(path, err) => write!(f, "{}: {err}", path.display()),
+            StorageError::InvalidId(id) => write!(f, "`{id}` is not a valid storage id"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+// json files under the data directory, one per saved game, user or evaluation
+#[derive(Clone, Debug)]
+pub struct Storage {
+    dir: PathBuf,
+}
+
+impl Storage {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let storage = Self { dir: dir.into() };
+        for sub in [
+            ADJOURNED_DIR,
+            GAMES_DIR,
+            USERS_DIR,
+            EVALUATIONS_DIR,
+            CORRESPONDENCE_DIR,
+            ANALYSIS_DIR,
+            PUZZLES_DIR,
+        ] {
+            let path = storage.dir.join(sub);
+            fs::create_dir_all(&path).map_err(|err| StorageError::Io(path, err))?;
+        }
+        Ok(storage)
+    }
+
+    // ids end up in file names, so anything that could escape the directory is refused
+    fn path(&self, sub: &str, id: &str) -> Option<PathBuf> {
+        let safe = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        safe.then(|| self.dir.join(sub).join(format!("{id}.json")))
+    }
+
+    // written to a temporary file first so a crash never leaves half a file behind
+    fn save<T: Serialize>(&self, sub: &str, id: &str, value: &T) -> Result<(), StorageError> {
+        let path = self
+            .path(sub, id)
+            .ok_or_else(|| StorageError::InvalidId(id.to_owned()))?;
+        let body =
+            serde_json::to_vec(value).map_err(|err| StorageError::Json(path.clone(), err))?;
+        write_atomic(&path, &body)
+    }
+
+    fn load<T: DeserializeOwned>(&self, sub: &str, id: &str) -> Result<Option<T>, StorageError> {
+        let Some(path) = self.path(sub, id) else {
+            return Ok(None);
+        };
+        match fs::read(&path) {
+            Ok(body) => serde_json::from_slice(&body)
+                .map(Some)
+                .map_err(|err| StorageError::Json(path, err)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(StorageError::Io(path, err)),
+        }
+    }
+
+    // every readable file in a directory. one that cannot be read is logged
+    // and left alone, rather than costing the rest
+    fn load_all<T: DeserializeOwned>(&self, sub: &str) -> Result<Vec<T>, StorageError> {
+        let dir = self.dir.join(sub);
+        let entries = fs::read_dir(&dir).map_err(|err| StorageError::Io(dir.clone(), err))?;
+        let mut values = vec![];
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(err) => {
+                    error!("Skipping {}", StorageError::Io(dir.clone(), err));
+                    continue;
+                }
+            };
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let loaded = fs::read(&path)
+                .map_err(|err| StorageError::Io(path.clone(), err))
+                .and_then(|body| {
+                    serde_json::from_slice(&body).map_err(|err| StorageError::Json(path, err))
+                });
+            match loaded {
+                Ok(value) => values.push(value),
+                Err(err) => error!("Skipping {err}"),
+            }
+        }
+        Ok(values)
+    }
+
+    fn remove(&self, sub: &str, id: &str) -> Result<(), StorageError> {
+        let Some(path) = self.path(sub, id) else {
+            return Ok(());
+        };
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(StorageError::Io(path, err)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn save_adjourned(&self, game: &AdjournedGame) -> Result<(), StorageError> {
+        self.save(ADJOURNED_DIR, &game.id, game)
+    }
+
+    pub fn load_adjourned(&self) -> Result<Vec<AdjournedGame>, StorageError> {
+        self.load_all(ADJOURNED_DIR)
+    }
+
+    pub fn remove_adjourned(&self, id: &str) -> Result<(), StorageError> {
+        self.remove(ADJOURNED_DIR, id)
+    }
+
+    // correspondence games in progress, kept in the adjourned format
+    pub fn save_correspondence(&self, game: &AdjournedGame) -> Result<(), StorageError> {
+        self.save(CORRESPONDENCE_DIR, &game.id, game)
+    }
+
+    pub fn load_correspondence(&self) -> Result<Vec<AdjournedGame>, StorageError> {
+        self.load_all(CORRESPONDENCE_DIR)
+    }
+
+    pub fn remove_correspondence(&self, id: &str) -> Result<(), StorageError> {
+        self.remove(CORRESPONDENCE_DIR, id)
+    }
+
+    pub fn save_game(&self, game: &GameRecord) -> Result<(), StorageError> {
+        self.save(GAMES_DIR, &game.id, game)
+    }
+
+    pub fn load_game(&self, id: &str) -> Result<Option<GameRecord>, StorageError> {
+        self.load(GAMES_DIR, id)
+    }
+
+    pub fn load_games(&self) -> Result<Vec<GameRecord>, StorageError> {
+        self.load_all(GAMES_DIR)
+    }
+
+    pub fn save_profile(&self, profile: &Profile) -> Result<(), StorageError> {
+        self.save(USERS_DIR, &profile.username, profile)
+    }
+
+    pub fn load_profile(&self, username: &str) -> Result<Option<Profile>, StorageError> {
+        self.load(USERS_DIR, username)
+    }
+
+    pub fn load_profiles(&self) -> Result<Vec<Profile>, StorageError> {
+        self.load_all(USERS_DIR)
+    }
+
+    pub fn save_evaluations(&self, evaluations: &Evaluations) -> Result<(), StorageError> {
+        self.save(EVALUATIONS_DIR, &evaluations.id, evaluations)
+    }
+
+    pub fn load_evaluations(&self, id: &str) -> Result<Option<Evaluations>, StorageError> {
+        self.load(EVALUATIONS_DIR, id)
+    }
+
+    pub fn save_analysis(&self, analysis: &Analysis) -> Result<(), StorageError> {
+        self.save(ANALYSIS_DIR, &analysis.id, analysis)
+    }
+
+    pub fn load_analysis(&self, id: &str) -> Result<Option<Analysis>, StorageError> {
+        self.load(ANALYSIS_DIR, id)
+    }
+
+    pub fn save_puzzle(&self, puzzle: &Puzzle) -> Result<(), StorageError> {
+        self.save(PUZZLES_DIR, &puzzle.id, puzzle)
+    }
+
+    pub fn load_puzzles(&self) -> Result<Vec<Puzzle>, StorageError> {
+        self.load_all(PUZZLES_DIR)
+    }
+}
+
+fn write_atomic(path: &Path, body: &[u8]) -> Result<(), StorageError> {
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, body).map_err(|err| StorageError::Io(temp.clone(), err))?;
+    fs::rename(&temp, path).map_err(|err| StorageError::Io(path.to_owned(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{record_from_uci, GameVariant, TimeControl};
+
+    fn temp_storage(name: &str) -> Storage {
+        let dir = std::env::temp_dir().join(format!("chess-storage-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Storage::open(dir).unwrap()
+    }
+
+    fn adjourned(id: &str) -> AdjournedGame {
+        AdjournedGame {
+            id: id.to_owned(),
+            started_at: 0,
+            players: ["alice".to_owned(), "bob".to_owned()],
+            time_control: TimeControl::default(),
+            variant: GameVariant::Standard,
+            clocks: [1_000, 2_000],
+            moves: vec![],
+            chat: vec![],
+            saved_at: 0,
+        }
+    }
+
+    #[test]
+    fn adjourned_games_round_trip() {
+        let storage = temp_storage("round-trip");
+        storage.save_adjourned(&adjourned("one")).unwrap();
+        storage.save_adjourned(&adjourned("two")).unwrap();
+        let mut loaded = storage.load_adjourned().unwrap();
+        loaded.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].id, "one");
+        assert_eq!(loaded[0].clocks, [1_000, 2_000]);
+
+        storage.remove_adjourned("one").unwrap();
+        storage.remove_adjourned("missing").unwrap();
+        assert_eq!(storage.load_adjourned().unwrap().len(), 1);
+        fs::remove_dir_all(&storage.dir).unwrap();
+    }
+
+    #[test]
+    fn correspondence_games_are_kept_apart() {
+        let storage = temp_storage("correspondence");
+        let game = AdjournedGame {
+            time_control: TimeControl::correspondence(3),
+            saved_at: 1_700_000_000,
+            ..adjourned("daily")
+        };
+        storage.save_correspondence(&game).unwrap();
+        assert!(storage.load_adjourned().unwrap().is_empty());
+        let loaded = storage.load_correspondence().unwrap();
+        assert_eq!(loaded[0].time_control.days_per_move, Some(3));
+        assert_eq!(loaded[0].saved_at, 1_700_000_000);
+
+        storage.remove_correspondence("daily").unwrap();
+        assert!(storage.load_correspondence().unwrap().is_empty());
+        fs::remove_dir_all(&storage.dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_files_are_skipped() {
+        let storage = temp_storage("corrupt");
+        storage.save_adjourned(&adjourned("good")).unwrap();
+        fs::write(storage.path(ADJOURNED_DIR, "bad").unwrap(), b"{").unwrap();
+        let loaded = storage.load_adjourned().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].id, "good");
+        fs::write(storage.path(USERS_DIR, "bob").unwrap(), b"{").unwrap();
+        assert!(matches!(
+            storage.load_profile("bob"),
+            Err(StorageError::Json(..))
+        ));
+        fs::remove_dir_all(&storage.dir).unwrap();
+    }
+
+    #[test]
+    fn finished_games_are_looked_up_by_id() {
+        let storage = temp_storage("games");
+        let record = GameRecord {
+            id: "18a2b3c4d5e".to_owned(),
+            players: [Some("alice".to_owned()), None],
+            ..record_from_uci(GameVariant::Standard, "")
+        };
+        storage.save_game(&record).unwrap();
+        let loaded = storage.load_game("18a2b3c4d5e").unwrap().unwrap();
+        assert_eq!(loaded.players, record.players);
+        assert!(storage.load_game("missing").unwrap().is_none());
+        assert!(storage.load_game("../adjourned/x").unwrap().is_none());
+        assert_eq!(storage.load_games().unwrap().len(), 1);
+        fs::remove_dir_all(&storage.dir).unwrap();
+    }
+
+    #[test]
+    fn profiles_round_trip() {
+        let storage = temp_storage("profiles");
+        let mut profile = Profile::new("alice".to_owned(), 1_682_899_200);
+        profile.stats.wins = 3;
+        storage.save_profile(&profile).unwrap();
+        profile.stats.wins = 4;
+        storage.save_profile(&profile).unwrap();
+        let loaded = storage.load_profiles().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].created_at, 1_682_899_200);
+        assert_eq!(loaded[0].stats.wins, 4);
+        fs::remove_dir_all(&storage.dir).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rustls::{
+    server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient},
+    Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use rustls_pemfile::Item;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::TlsConfig;
+
+#[derive(Debug)]
+pub enum TlsError {
+    Read(PathBuf, io::Error),
+    NoCertificates(PathBuf),
+    NoPrivateKey(PathBuf),
+    Rustls(rustls::Error),
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Read(path, err) => write!(f, "cannot read {}: {err}", path.display()),
+            TlsError::NoCertificates(path) => {
+                write!(f, "no certificates found in {}", path.display())
+            }
+            TlsError::NoPrivateKey(path) => write!(f, "no private key found in {}", path.display()),
+            TlsError::Rustls(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<rustls::Error> for TlsError {
+    fn from(err: rustls::Error) -> Self {
+        TlsError::Rustls(err)
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| TlsError::Read(path.to_owned(), err))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .map_err(|err| TlsError::Read(path.to_owned(), err))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_owned()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+// takes the first key in the file, whichever of the usual encodings it is in
+fn load_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let mut reader = open(path)?;
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|err| TlsError::Read(path.to_owned(), err))?
+        {
+            Some(Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key)) => {
+                return Ok(PrivateKey(key))
+            }
+            Some(_) => continue,
+            None => return Err(TlsError::NoPrivateKey(path.to_owned())),
+        }
+    }
+}
+
+// with a client ca configured, clients may present a certificate signed by it
+// to prove they are one of our trusted bots. whether every client has to do
+// so is up to `require_client_cert`
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, TlsError> {
+    let certs = load_certs(&config.cert)?;
+    let key = load_key(&config.key)?;
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let server_config = match &config.client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca)? {
+                roots.add(&cert)?;
+            }
+            let verifier = match config.require_client_cert {
+                true => AllowAnyAuthenticatedClient::new(roots).boxed(),
+                false => AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+            };
+            builder
+                .with_client_cert_verifier(verifier)
+                .with_single_cert(certs, key)?
+        }
+        None => builder.with_no_client_auth().with_single_cert(certs, key)?,
+    };
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, Certificate as Generated, CertificateParams, IsCa};
+    use rustls::{ClientConfig, ServerName};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    struct Pki {
+        dir: PathBuf,
+        ca: Generated,
+    }
+
+    impl Pki {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("chess-tls-{name}-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let mut params = CertificateParams::new(vec![]);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = Generated::from_params(params).unwrap();
+            std::fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+            Self { dir, ca }
+        }
+
+        // writes a leaf certificate signed by this ca, returns its paths
+        fn issue(&self, name: &str, subject: &str) -> (PathBuf, PathBuf) {
+            let leaf =
+                Generated::from_params(CertificateParams::new(vec![subject.to_owned()])).unwrap();
+            let cert = self.dir.join(format!("{name}.pem"));
+            let key = self.dir.join(format!("{name}.key"));
+            std::fs::write(&cert, leaf.serialize_pem_with_signer(&self.ca).unwrap()).unwrap();
+            std::fs::write(&key, leaf.serialize_private_key_pem()).unwrap();
+            (cert, key)
+        }
+
+        fn ca_path(&self) -> PathBuf {
+            self.dir.join("ca.pem")
+        }
+
+        fn server_config(&self, client_ca: Option<PathBuf>, require: bool) -> TlsConfig {
+            let (cert, key) = self.issue("server", "localhost");
+            TlsConfig {
+                enabled: true,
+                cert,
+                key,
+                client_ca,
+                require_client_cert: require,
+            }
+        }
+
+        fn connector(&self, identity: Option<(PathBuf, PathBuf)>) -> TlsConnector {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(&self.ca_path()).unwrap() {
+                roots.add(&cert).unwrap();
+            }
+            let builder = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots);
+            let config = match identity {
+                Some((cert, key)) => builder
+                    .with_client_auth_cert(load_certs(&cert).unwrap(), load_key(&key).unwrap())
+                    .unwrap(),
+                None => builder.with_no_client_auth(),
+            };
+            TlsConnector::from(Arc::new(config))
+        }
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    // runs a handshake over an in-memory pipe, resolving to whether the
+    // server saw a client certificate, or None if the handshake failed
+    async fn handshake(acceptor: TlsAcceptor, connector: TlsConnector) -> Option<bool> {
+        let (client, server) = duplex(16 * 1024);
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server).await.ok()?;
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.ok()?;
+            assert_eq!(&buf, b"ping");
+            Some(stream.get_ref().1.peer_certificates().is_some())
+        });
+        let name = ServerName::try_from("localhost").unwrap();
+        // the client end stays open until the server is done with it
+        let _client = match connector.connect(name, client).await {
+            Ok(mut stream) => {
+                // a rejected client may only find out once it writes
+                let _ = stream.write_all(b"ping").await;
+                let _ = stream.flush().await;
+                Some(stream)
+            }
+            Err(_) => None,
+        };
+        server.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn plain_tls_handshake() {
+        let pki = Pki::new("plain");
+        let acceptor = acceptor(&pki.server_config(None, false)).unwrap();
+        assert_eq!(handshake(acceptor, pki.connector(None)).await, Some(false));
+    }
+
+    #[tokio::test]
+    async fn optional_client_certificates() {
+        let pki = Pki::new("optional");
+        let config = pki.server_config(Some(pki.ca_path()), false);
+        let bot = pki.issue("bot", "bot");
+        let accept = || acceptor(&config).unwrap();
+        assert_eq!(handshake(accept(), pki.connector(None)).await, Some(false));
+        assert_eq!(
+            handshake(accept(), pki.connector(Some(bot))).await,
+            Some(true)
+        );
+    }
+
+    #[tokio::test]
+    async fn required_client_certificates() {
+        let pki = Pki::new("required");
+        let config = pki.server_config(Some(pki.ca_path()), true);
+        let bot = pki.issue("bot", "bot");
+        let accept = || acceptor(&config).unwrap();
+        assert_eq!(handshake(accept(), pki.connector(None)).await, None);
+        assert_eq!(
+            handshake(accept(), pki.connector(Some(bot))).await,
+            Some(true)
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_certificates_from_other_authorities() {
+        let pki = Pki::new("trusted");
+        let other = Pki::new("untrusted");
+        let config = pki.server_config(Some(pki.ca_path()), false);
+        let impostor = other.issue("bot", "bot");
+        // the client still has to trust our server, only its identity is foreign
+        let connector = {
+            let mut roots = RootCertStore::empty();
+            roots.add(&load_certs(&pki.ca_path()).unwrap()[0]).unwrap();
+            let config = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_client_auth_cert(
+                    load_certs(&impostor.0).unwrap(),
+                    load_key(&impostor.1).unwrap(),
+                )
+                .unwrap();
+            TlsConnector::from(Arc::new(config))
+        };
+        assert_eq!(handshake(acceptor(&config).unwrap(), connector).await, None);
+    }
+
+    #[test]
+    fn reports_missing_key() {
+        let pki = Pki::new("missing");
+        let mut config = pki.server_config(None, false);
+        config.key = pki.ca_path();
+        assert!(matches!(acceptor(&config), Err(TlsError::NoPrivateKey(_))));
+    }
+}
@@ -0,0 +1,145 @@
+use serde::Serialize;
+
+use crate::game::GameResult;
+
+// where an arena player stands, by seed
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct ArenaPlayer {
+    pub score: u32,
+    pub games: u32,
+    pub wins: u32,
+    // wins in a row, two or more puts the player on fire
+    pub streak: u32,
+    pub berserks: u32,
+    #[serde(skip)]
+    pub last_opponent: Option<usize>,
+    // +1 for every game with white, -1 with black
+    #[serde(skip)]
+    pub balance: i32,
+    // in a game right now
+    #[serde(skip)]
+    pub playing: bool,
+    // left the arena for now and is not paired until joining again
+    #[serde(skip)]
+    pub paused: bool,
+}
+
+impl ArenaPlayer {
+    pub fn on_fire(&self) -> bool {
+        self.streak >= 2
+    }
+
+    // scores a finished game for one side, returning the points it earned.
+    // a win is 2 and a draw 1, both doubled on fire, and a berserk win adds 1
+    pub fn score(
+        &mut self,
+        result: GameResult,
+        side: usize,
+        opponent: usize,
+        berserk: bool,
+    ) -> u32 {
+        let base = match result {
+            GameResult::Draw => 1,
+            result if result == GameResult::win_for(side) => 2,
+            _ => 0,
+        };
+        let won = base == 2;
+        let mut points = if self.on_fire() { base * 2 } else { base };
+        if berserk {
+            self.berserks += 1;
+            if won {
+                points += 1;
+            }
+        }
+        self.streak = if won { self.streak + 1 } else { 0 };
+        self.wins += u32::from(won);
+        self.games += 1;
+        self.score += points;
+        self.last_opponent = Some(opponent);
+        self.balance += if side == 0 { 1 } else { -1 };
+        points
+    }
+}
+
+// pairs waiting players with whoever is closest in score, white to the one
+// who has had it less. nobody meets the opponent they just played while
+// anyone else is free, and an odd one out waits for the next game to end
+pub fn pair(waiting: &[usize], players: &[ArenaPlayer]) -> Vec<(usize, usize)> {
+    let mut waiting = waiting.to_vec();
+    waiting.sort_by_key(|&p| (std::cmp::Reverse(players[p].score), p));
+    let mut pairs = vec![];
+    while waiting.len() >= 2 {
+        let top = waiting.remove(0);
+        let fresh = |&p: &usize| {
+            players[top].last_opponent != Some(p) && players[p].last_opponent != Some(top)
+        };
+        let opponent = match waiting.iter().position(fresh) {
+            Some(index) => index,
+            // only the last opponent is free, better a rematch than a wait
+            None if waiting.len() == 1 => 0,
+            None => continue,
+        };
+        let other = waiting.remove(opponent);
+        pairs.push(match players[top].balance <= players[other].balance {
+            true => (top, other),
+            false => (other, top),
+        });
+    }
+    pairs
+}
+
+// best first: score, then fewer games for it, then seed
+pub fn leaderboard(players: &[ArenaPlayer]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..players.len()).collect();
+    order.sort_by_key(|&p| (std::cmp::Reverse(players[p].score), players[p].games, p));
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streaks_double_and_berserk_adds_a_point() {
+        let mut player = ArenaPlayer::default();
+        let win = GameResult::WhiteWins;
+        assert_eq!(player.score(win, 0, 1, false), 2);
+        assert_eq!(player.score(win, 0, 1, false), 2);
+        assert!(player.on_fire());
+        assert_eq!(player.score(GameResult::Draw, 0, 1, false), 2);
+        // a draw ends the streak
+        assert!(!player.on_fire());
+        assert_eq!(player.score(win, 0, 1, true), 3);
+        assert_eq!(player.score(GameResult::BlackWins, 0, 1, true), 0);
+        assert_eq!((player.score, player.games, player.wins), (9, 5, 3));
+        assert_eq!(player.berserks, 2);
+    }
+
+    #[test]
+    fn pairs_by_score_without_instant_rematches() {
+        let mut players = vec![ArenaPlayer::default(); 5];
+        players[0].score = 8;
+        players[1].score = 7;
+        players[2].score = 2;
+        players[3].score = 1;
+        players[0].last_opponent = Some(1);
+        players[1].last_opponent = Some(0);
+        players[0].balance = 1;
+        // 0 skips 1, whom it just played, for the next best, who gets white
+        let pairs = pair(&[0, 1, 2, 3, 4], &players);
+        assert_eq!(pairs, [(2, 0), (1, 3)]);
+        // with only the two of them free they meet again
+        assert_eq!(pair(&[0, 1], &players), [(1, 0)]);
+    }
+
+    #[test]
+    fn leaderboard_prefers_fewer_games_on_equal_scores() {
+        let mut players = vec![ArenaPlayer::default(); 3];
+        players[0].score = 4;
+        players[0].games = 3;
+        players[1].score = 4;
+        players[1].games = 2;
+        players[2].score = 6;
+        assert_eq!(leaderboard(&players), [2, 1, 0]);
+    }
+}
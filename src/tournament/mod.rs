@@ -0,0 +1,1113 @@
+use actix::{
+    Actor, ActorFutureExt, Addr, AsyncContext, Context, Handler, Message as ActixMessage,
+    MessageResult, Recipient, WrapFuture,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{
+    chessclient::Message,
+    game::{unix_now, GameResult, GameVariant, TimeControl},
+    message::{ClientResult, OutgoingMessage},
+    profile::Category,
+    server::{GetRatings, Notify, Server, StartTournamentGame},
+};
+
+mod arena;
+mod pairing;
+
+use arena::{leaderboard, ArenaPlayer};
+use pairing::{round_robin, standings, swiss_round, Board, Score, Standing};
+
+// time between the last game of a round ending and the next round starting
+const ROUND_BREAK: Duration = Duration::from_secs(5);
+const MAX_SWISS_ROUNDS: u32 = 20;
+// how often arenas pair the players waiting in them
+const ARENA_PAIRING: Duration = Duration::from_secs(2);
+const MAX_ARENA_MINUTES: u32 = 24 * 60;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum TournamentKind {
+    // everyone plays everyone once
+    RoundRobin,
+    Swiss { rounds: u32 },
+    // players are paired again as soon as their game ends, until time runs out
+    Arena { minutes: u32 },
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct TournamentSettings {
+    pub name: String,
+    pub kind: TournamentKind,
+    #[serde(default)]
+    pub variant: GameVariant,
+    // None plays at the server's time control
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum TournamentError {
+    NoSuchTournament,
+    // registration is closed once the first round is paired
+    AlreadyStarted,
+    // only whoever created the tournament can start it
+    NotOrganizer,
+    AlreadyJoined,
+    NotJoined,
+    TooFewPlayers,
+    // no name, no swiss rounds or arena minutes, or a correspondence time control
+    InvalidSettings,
+    // berserk is for arena games, before the player's first move
+    CannotBerserk,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TournamentStatus {
+    Registering,
+    Running,
+    Finished,
+}
+
+struct Tournament {
+    id: u64,
+    settings: TournamentSettings,
+    time_control: TimeControl,
+    organizer: String,
+    // in registration order, then by seed once started
+    players: Vec<String>,
+    status: TournamentStatus,
+    rounds: Vec<Vec<Board>>,
+    // every pairing of a round robin, made when it starts
+    schedule: Vec<Vec<(usize, Option<usize>)>>,
+    // Some once an arena starts
+    arena: Option<Arena>,
+}
+
+struct Arena {
+    // unix seconds
+    ends_at: u64,
+    // by the same index as the tournament's players
+    players: Vec<ArenaPlayer>,
+    // players between games, ready to be paired
+    waiting: Vec<usize>,
+    // every game started, oldest first
+    games: Vec<Board>,
+}
+
+impl Arena {
+    fn over(&self) -> bool {
+        unix_now() >= self.ends_at
+    }
+
+    // back in the queue after a game, unless they left or time is up
+    fn requeue(&mut self, player: usize) {
+        self.players[player].playing = false;
+        if !self.players[player].paused && !self.over() && !self.waiting.contains(&player) {
+            self.waiting.push(player);
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BoardView {
+    pub white: String,
+    // None for a bye
+    pub black: Option<String>,
+    pub game: Option<String>,
+    pub score: Option<Score>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct StandingView {
+    pub rank: usize,
+    pub username: String,
+    pub points: f32,
+    pub buchholz: f32,
+    pub sonneborn_berger: f32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TournamentView {
+    pub id: u64,
+    pub name: String,
+    pub kind: TournamentKind,
+    pub variant: GameVariant,
+    pub time_control: TimeControl,
+    pub organizer: String,
+    pub status: TournamentStatus,
+    pub players: Vec<String>,
+    pub rounds: Vec<Vec<BoardView>>,
+    pub standings: Vec<StandingView>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arena: Option<ArenaView>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ArenaStanding {
+    pub rank: usize,
+    pub username: String,
+    pub on_fire: bool,
+    #[serde(flatten)]
+    pub player: ArenaPlayer,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ArenaView {
+    pub ends_at: u64,
+    pub leaderboard: Vec<ArenaStanding>,
+    pub games: Vec<BoardView>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TournamentSummary {
+    pub id: u64,
+    pub name: String,
+    pub kind: TournamentKind,
+    pub status: TournamentStatus,
+    pub players: usize,
+    // rounds paired so far
+    pub round: usize,
+}
+
+impl Tournament {
+    fn total_rounds(&self) -> usize {
+        match self.settings.kind {
+            TournamentKind::RoundRobin => self.schedule.len(),
+            TournamentKind::Swiss { rounds } => rounds as usize,
+            TournamentKind::Arena { .. } => 0,
+        }
+    }
+
+    fn standings(&self) -> Vec<Standing> {
+        let swiss = matches!(self.settings.kind, TournamentKind::Swiss { .. });
+        standings(self.players.len(), &self.rounds, swiss)
+    }
+
+    fn view(&self) -> TournamentView {
+        let name = |player: usize| self.players[player].clone();
+        let board = |board: &Board| BoardView {
+            white: name(board.white),
+            black: board.black.map(name),
+            game: board.game.clone(),
+            score: board.score,
+        };
+        let arena = self.arena.as_ref().map(|arena| ArenaView {
+            ends_at: arena.ends_at,
+            leaderboard: leaderboard(&arena.players)
+                .into_iter()
+                .enumerate()
+                .map(|(rank, player)| ArenaStanding {
+                    rank: rank + 1,
+                    username: name(player),
+                    on_fire: arena.players[player].on_fire(),
+                    player: arena.players[player].clone(),
+                })
+                .collect(),
+            games: arena.games.iter().map(board).collect(),
+        });
+        let standings = match self.settings.kind {
+            TournamentKind::Arena { .. } => vec![],
+            _ => self.standings(),
+        };
+        TournamentView {
+            id: self.id,
+            name: self.settings.name.clone(),
+            kind: self.settings.kind,
+            variant: self.settings.variant,
+            time_control: self.time_control,
+            organizer: self.organizer.clone(),
+            status: self.status,
+            players: self.players.clone(),
+            rounds: self
+                .rounds
+                .iter()
+                .map(|round| round.iter().map(board).collect())
+                .collect(),
+            standings: standings
+                .into_iter()
+                .enumerate()
+                .map(|(rank, standing)| StandingView {
+                    rank: rank + 1,
+                    username: name(standing.player),
+                    points: standing.points,
+                    buchholz: standing.buchholz,
+                    sonneborn_berger: standing.sonneborn_berger,
+                })
+                .collect(),
+            arena,
+        }
+    }
+
+    fn summary(&self) -> TournamentSummary {
+        TournamentSummary {
+            id: self.id,
+            name: self.settings.name.clone(),
+            kind: self.settings.kind,
+            status: self.status,
+            players: self.players.len(),
+            round: self.rounds.len(),
+        }
+    }
+}
+
+// runs tournaments next to the server, which starts their games and reports
+// how each one ended
+pub struct Tournaments {
+    server: Addr<Server>,
+    // used when a tournament does not pick its own
+    time_control: TimeControl,
+    round_break: Duration,
+    tournaments: Vec<Tournament>,
+    last_id: u64,
+    // set once the server starts shutting down, nothing new is paired after
+    paused: bool,
+}
+
+impl Tournaments {
+    pub fn new(server: Addr<Server>, time_control: TimeControl) -> Self {
+        Self {
+            server,
+            time_control,
+            round_break: ROUND_BREAK,
+            tournaments: vec![],
+            last_id: 0,
+            paused: false,
+        }
+    }
+
+    fn find(&mut self, id: u64) -> Result<&mut Tournament, TournamentError> {
+        self.tournaments
+            .iter_mut()
+            .find(|t| t.id == id)
+            .ok_or(TournamentError::NoSuchTournament)
+    }
+
+    // pushes the tournament as it stands to everyone playing in it
+    fn publish(&self, id: u64) {
+        if let Some(tournament) = self.tournaments.iter().find(|t| t.id == id) {
+            self.server.do_send(Notify {
+                usernames: tournament.players.clone(),
+                message: OutgoingMessage::Tournament(Box::new(tournament.view())),
+            });
+        }
+    }
+
+    // pairs the next round and asks the server for its games, or ends the
+    // tournament after the last one
+    fn next_round(&mut self, id: u64, ctx: &mut Context<Self>) {
+        if self.paused {
+            return;
+        }
+        let Ok(tournament) = self.find(id) else {
+            return;
+        };
+        if tournament.rounds.len() >= tournament.total_rounds() {
+            tournament.status = TournamentStatus::Finished;
+            info!("Tournament {} finished", tournament.settings.name);
+            return self.publish(id);
+        }
+        let round = tournament.rounds.len();
+        let pairs = match tournament.settings.kind {
+            TournamentKind::RoundRobin => tournament.schedule[round].clone(),
+            TournamentKind::Swiss { .. } | TournamentKind::Arena { .. } => {
+                swiss_round(tournament.players.len(), &tournament.rounds)
+            }
+        };
+        info!(
+            "Tournament {} round {} paired",
+            tournament.settings.name,
+            round + 1
+        );
+        let reply = ctx.address().recipient();
+        let starts: Vec<_> = pairs
+            .iter()
+            .enumerate()
+            .filter_map(|(board, &(white, black))| {
+                let start = StartTournamentGame {
+                    players: [white, black?].map(|p| tournament.players[p].clone()),
+                    time_control: tournament.time_control,
+                    variant: tournament.settings.variant,
+                    berserk: false,
+                    reply: reply.clone(),
+                };
+                Some((board, start))
+            })
+            .collect();
+        tournament
+            .rounds
+            .push(pairs.into_iter().map(Board::new).collect());
+        for (board, start) in starts {
+            let started = self
+                .server
+                .send(start)
+                .into_actor(self)
+                .map(move |res, act, ctx| {
+                    let paused = act.paused;
+                    let Ok(tournament) = act.find(id) else {
+                        return;
+                    };
+                    let board = &mut tournament.rounds[round][board];
+                    match res {
+                        Ok(Ok(game)) => board.game = Some(game),
+                        // a server shutting down refuses everyone, which is
+                        // nobody's fault
+                        _ if paused => {}
+                        // whoever was offline or busy loses the game
+                        Ok(Err(missing)) => board.score = Some(Score::Forfeit(missing)),
+                        Err(_) => board.score = Some(Score::Forfeit([true, true])),
+                    }
+                    act.check_round(id, ctx);
+                });
+            ctx.spawn(started);
+        }
+        self.publish(id);
+    }
+
+    // moves on once every board of the current round has a result
+    fn check_round(&mut self, id: u64, ctx: &mut Context<Self>) {
+        let Ok(tournament) = self.find(id) else {
+            return;
+        };
+        let finished = tournament
+            .rounds
+            .last()
+            .is_some_and(|round| round.iter().all(Board::done));
+        if tournament.status != TournamentStatus::Running || !finished {
+            return;
+        }
+        self.publish(id);
+        ctx.run_later(self.round_break, move |act, ctx| act.next_round(id, ctx));
+    }
+
+    // opens an arena for its players, who are all waiting for a first game
+    fn start_arena(&mut self, id: u64, minutes: u32, ctx: &mut Context<Self>) {
+        let Ok(tournament) = self.find(id) else {
+            return;
+        };
+        let players = tournament.players.len();
+        tournament.arena = Some(Arena {
+            ends_at: unix_now() + u64::from(minutes) * 60,
+            players: vec![ArenaPlayer::default(); players],
+            waiting: (0..players).collect(),
+            games: vec![],
+        });
+        let length = Duration::from_secs(u64::from(minutes) * 60);
+        ctx.run_later(length, move |act, _| act.close_arena(id));
+        self.pair_arenas(ctx);
+    }
+
+    // pairs whoever is waiting in every arena still open
+    fn pair_arenas(&mut self, ctx: &mut Context<Self>) {
+        if self.paused {
+            return;
+        }
+        let reply = ctx.address().recipient();
+        let mut starts = vec![];
+        let mut closing = vec![];
+        for tournament in self.tournaments.iter_mut() {
+            if tournament.status != TournamentStatus::Running {
+                continue;
+            }
+            let Some(arena) = tournament.arena.as_mut() else {
+                continue;
+            };
+            // time ran out with no game left to finish it off
+            if arena.over() {
+                closing.push(tournament.id);
+                continue;
+            }
+            for (white, black) in arena::pair(&arena.waiting, &arena.players) {
+                arena.waiting.retain(|&p| p != white && p != black);
+                arena.players[white].playing = true;
+                arena.players[black].playing = true;
+                let start = StartTournamentGame {
+                    players: [white, black].map(|p| tournament.players[p].clone()),
+                    time_control: tournament.time_control,
+                    variant: tournament.settings.variant,
+                    berserk: true,
+                    reply: reply.clone(),
+                };
+                starts.push((tournament.id, white, black, start));
+            }
+        }
+        for id in closing {
+            self.close_arena(id);
+        }
+        let mut paired = vec![];
+        for (id, white, black, start) in starts {
+            paired.push(id);
+            let started = self
+                .server
+                .send(start)
+                .into_actor(self)
+                .map(move |res, act, _| {
+                    let Some(arena) = act.find(id).ok().and_then(|t| t.arena.as_mut()) else {
+                        return;
+                    };
+                    let missing = match res {
+                        Ok(Ok(game)) => {
+                            arena.games.push(Board {
+                                game: Some(game),
+                                ..Board::new((white, Some(black)))
+                            });
+                            return;
+                        }
+                        Ok(Err(missing)) => missing,
+                        Err(_) => [false, false],
+                    };
+                    // whoever was offline or busy sits out until they join again
+                    for (player, gone) in [white, black].into_iter().zip(missing) {
+                        arena.players[player].paused |= gone;
+                        arena.requeue(player);
+                    }
+                });
+            ctx.spawn(started);
+        }
+        paired.dedup();
+        for id in paired {
+            self.publish(id);
+        }
+    }
+
+    // time is up: nobody new is paired and the arena ends with its last game
+    fn close_arena(&mut self, id: u64) {
+        let Ok(tournament) = self.find(id) else {
+            return;
+        };
+        let Some(arena) = tournament.arena.as_mut() else {
+            return;
+        };
+        arena.waiting.clear();
+        if tournament.status == TournamentStatus::Finished {
+            return;
+        }
+        if arena.players.iter().any(|p| p.playing) {
+            return;
+        }
+        tournament.status = TournamentStatus::Finished;
+        info!("Tournament {} finished", tournament.settings.name);
+        self.publish(id);
+    }
+}
+
+impl Actor for Tournaments {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(ARENA_PAIRING, |act, ctx| act.pair_arenas(ctx));
+    }
+}
+
+fn reply(client: &Recipient<Message>, result: Result<(), TournamentError>) {
+    let result = match result {
+        Ok(()) => ClientResult::Ok,
+        Err(err) => ClientResult::TournamentError(err),
+    };
+    client.do_send(Message {
+        inner: OutgoingMessage::Result(result),
+        game: None,
+    });
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct CreateTournament {
+    pub username: String,
+    pub client: Recipient<Message>,
+    pub settings: TournamentSettings,
+}
+
+impl Handler<CreateTournament> for Tournaments {
+    type Result = ();
+    fn handle(&mut self, msg: CreateTournament, _ctx: &mut Self::Context) -> Self::Result {
+        let settings = msg.settings;
+        let time_control = settings.time_control.unwrap_or(self.time_control);
+        let valid = !settings.name.trim().is_empty()
+            && time_control.initial_secs > 0
+            && time_control.days_per_move.is_none()
+            && match settings.kind {
+                TournamentKind::Swiss { rounds } => (1..=MAX_SWISS_ROUNDS).contains(&rounds),
+                TournamentKind::Arena { minutes } => (1..=MAX_ARENA_MINUTES).contains(&minutes),
+                TournamentKind::RoundRobin => true,
+            };
+        if !valid {
+            return reply(&msg.client, Err(TournamentError::InvalidSettings));
+        }
+        self.last_id += 1;
+        let tournament = Tournament {
+            id: self.last_id,
+            settings,
+            time_control,
+            organizer: msg.username,
+            players: vec![],
+            status: TournamentStatus::Registering,
+            rounds: vec![],
+            schedule: vec![],
+            arena: None,
+        };
+        info!("Tournament {} created", tournament.settings.name);
+        msg.client.do_send(Message {
+            inner: OutgoingMessage::Tournament(Box::new(tournament.view())),
+            game: None,
+        });
+        self.tournaments.push(tournament);
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct JoinTournament {
+    pub username: String,
+    pub client: Recipient<Message>,
+    pub id: u64,
+    // false to leave again before it starts. a running arena can be
+    // joined late, and left and rejoined between games
+    pub join: bool,
+}
+
+impl Handler<JoinTournament> for Tournaments {
+    type Result = ();
+    fn handle(&mut self, msg: JoinTournament, _ctx: &mut Self::Context) -> Self::Result {
+        let result = self.find(msg.id).and_then(|tournament| {
+            if let Some(arena) = tournament.arena.as_mut().filter(|arena| !arena.over()) {
+                let joined = tournament.players.iter().position(|p| *p == msg.username);
+                let player = match (msg.join, joined) {
+                    (true, None) => {
+                        tournament.players.push(msg.username);
+                        arena.players.push(ArenaPlayer::default());
+                        arena.players.len() - 1
+                    }
+                    (true, Some(player)) if arena.players[player].paused => player,
+                    (true, Some(_)) => return Err(TournamentError::AlreadyJoined),
+                    (false, Some(player)) => {
+                        arena.players[player].paused = true;
+                        arena.waiting.retain(|&p| p != player);
+                        return Ok(());
+                    }
+                    (false, None) => return Err(TournamentError::NotJoined),
+                };
+                arena.players[player].paused = false;
+                if !arena.players[player].playing {
+                    arena.requeue(player);
+                }
+                return Ok(());
+            }
+            if tournament.status != TournamentStatus::Registering {
+                return Err(TournamentError::AlreadyStarted);
+            }
+            let joined = tournament.players.iter().position(|p| *p == msg.username);
+            match (msg.join, joined) {
+                (true, Some(_)) => Err(TournamentError::AlreadyJoined),
+                (true, None) => {
+                    tournament.players.push(msg.username);
+                    Ok(())
+                }
+                (false, Some(index)) => {
+                    tournament.players.remove(index);
+                    Ok(())
+                }
+                (false, None) => Err(TournamentError::NotJoined),
+            }
+        });
+        reply(&msg.client, result);
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct StartTournament {
+    pub username: String,
+    pub client: Recipient<Message>,
+    pub id: u64,
+}
+
+impl Handler<StartTournament> for Tournaments {
+    type Result = ();
+    fn handle(&mut self, msg: StartTournament, ctx: &mut Self::Context) -> Self::Result {
+        let checked = self.find(msg.id).and_then(|tournament| {
+            if tournament.organizer != msg.username {
+                return Err(TournamentError::NotOrganizer);
+            }
+            if tournament.status != TournamentStatus::Registering {
+                return Err(TournamentError::AlreadyStarted);
+            }
+            if tournament.players.len() < 2 {
+                return Err(TournamentError::TooFewPlayers);
+            }
+            tournament.status = TournamentStatus::Running;
+            Ok(GetRatings {
+                usernames: tournament.players.clone(),
+                category: Category::of(&tournament.time_control),
+            })
+        });
+        let ratings = match checked {
+            Ok(ratings) => ratings,
+            Err(err) => return reply(&msg.client, Err(err)),
+        };
+        reply(&msg.client, Ok(()));
+        let id = msg.id;
+        // seeded by rating, registration order breaking ties
+        let seed = self
+            .server
+            .send(ratings)
+            .into_actor(self)
+            .map(move |res, act, ctx| {
+                let Ok(tournament) = act.find(id) else {
+                    return;
+                };
+                if let Ok(ratings) = res {
+                    let mut seeded: Vec<_> = tournament.players.drain(..).zip(ratings).collect();
+                    seeded.sort_by_key(|(_, rating)| std::cmp::Reverse(*rating));
+                    tournament.players = seeded.into_iter().map(|(name, _)| name).collect();
+                }
+                if tournament.settings.kind == TournamentKind::RoundRobin {
+                    tournament.schedule = round_robin(tournament.players.len());
+                }
+                info!(
+                    "Tournament {} started with {} players",
+                    tournament.settings.name,
+                    tournament.players.len()
+                );
+                match tournament.settings.kind {
+                    TournamentKind::Arena { minutes } => act.start_arena(id, minutes, ctx),
+                    _ => act.next_round(id, ctx),
+                }
+            });
+        ctx.spawn(seed);
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct GetTournament {
+    pub client: Recipient<Message>,
+    pub id: u64,
+}
+
+impl Handler<GetTournament> for Tournaments {
+    type Result = ();
+    fn handle(&mut self, msg: GetTournament, _ctx: &mut Self::Context) -> Self::Result {
+        match self.find(msg.id) {
+            Ok(tournament) => msg.client.do_send(Message {
+                inner: OutgoingMessage::Tournament(Box::new(tournament.view())),
+                game: None,
+            }),
+            Err(err) => reply(&msg.client, Err(err)),
+        }
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "Vec<TournamentSummary>")]
+pub struct ListTournaments;
+
+impl Handler<ListTournaments> for Tournaments {
+    type Result = MessageResult<ListTournaments>;
+    fn handle(&mut self, _msg: ListTournaments, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(
+            self.tournaments
+                .iter()
+                .rev()
+                .map(Tournament::summary)
+                .collect(),
+        )
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "Option<TournamentView>")]
+pub struct LookupTournament(pub u64);
+
+impl Handler<LookupTournament> for Tournaments {
+    type Result = Option<TournamentView>;
+    fn handle(&mut self, msg: LookupTournament, _ctx: &mut Self::Context) -> Self::Result {
+        self.find(msg.0).ok().map(|tournament| tournament.view())
+    }
+}
+
+// sent by the server when a tournament game ends, or is adjourned by a
+// shutdown
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct TournamentGameOver {
+    pub game: String,
+    // None for a game stopped without a result
+    pub result: Option<GameResult>,
+    // which sides went berserk
+    pub berserk: [bool; 2],
+}
+
+impl Handler<TournamentGameOver> for Tournaments {
+    type Result = ();
+    fn handle(&mut self, msg: TournamentGameOver, ctx: &mut Self::Context) -> Self::Result {
+        let arena_game = self.tournaments.iter_mut().find_map(|tournament| {
+            let arena = tournament.arena.as_mut()?;
+            let index = arena
+                .games
+                .iter()
+                .position(|board| board.game.as_ref() == Some(&msg.game))?;
+            let board = &mut arena.games[index];
+            let players = [board.white, board.black?];
+            let Some(result) = msg.result else {
+                // nobody scores a game that never finished, and both players go
+                // back in the queue so the arena can still close
+                arena.games.remove(index);
+                for player in players {
+                    arena.requeue(player);
+                }
+                return Some(tournament.id);
+            };
+            board.score = Some(Score::Played(result));
+            for (side, player) in players.into_iter().enumerate() {
+                let opponent = players[1 - side];
+                arena.players[player].score(result, side, opponent, msg.berserk[side]);
+                arena.requeue(player);
+            }
+            Some(tournament.id)
+        });
+        if let Some(id) = arena_game {
+            self.publish(id);
+            // the last game of an arena whose time is up
+            if self
+                .find(id)
+                .is_ok_and(|t| t.arena.as_ref().is_some_and(Arena::over))
+            {
+                self.close_arena(id);
+            }
+            return;
+        }
+        let paused = self.paused;
+        let found = self.tournaments.iter_mut().find_map(|tournament| {
+            let board = tournament
+                .rounds
+                .last_mut()?
+                .iter_mut()
+                .find(|board| board.game.as_ref() == Some(&msg.game))?;
+            // a game stopped without a result counts as lost by both, which
+            // keeps the round from waiting on it. one adjourned by a shutdown
+            // leaves its board open instead
+            board.score = match msg.result {
+                Some(result) => Some(Score::Played(result)),
+                None if paused => None,
+                None => Some(Score::Forfeit([true, true])),
+            };
+            Some(tournament.id)
+        });
+        match found {
+            Some(id) => self.check_round(id, ctx),
+            None => warn!("Result of game {} belongs to no tournament round", msg.game),
+        }
+    }
+}
+
+// sent when the server starts shutting down. running games are left to the
+// server, but no new round or arena game is paired
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct PauseTournaments;
+
+impl Handler<PauseTournaments> for Tournaments {
+    type Result = ();
+    fn handle(&mut self, _msg: PauseTournaments, _ctx: &mut Self::Context) -> Self::Result {
+        info!("Tournaments paused");
+        self.paused = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chat::{BlockList, ChatPolicy},
+        config::{BotConfig, ChatConfig, UciConfig},
+        game::{Adjourn, ForfeitGame},
+        message::Login,
+        server::{LookupGame, Shutdown},
+        storage::Storage,
+    };
+    use std::collections::HashMap;
+
+    // a connection that ignores whatever it is sent
+    struct Client;
+
+    impl Actor for Client {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Message> for Client {
+        type Result = ();
+        fn handle(&mut self, _msg: Message, _ctx: &mut Self::Context) -> Self::Result {}
+    }
+
+    struct Field {
+        server: Addr<Server>,
+        tournaments: Addr<Tournaments>,
+        clients: HashMap<String, Recipient<Message>>,
+        dir: std::path::PathBuf,
+    }
+
+    // a server with the online players logged in, and the rest known to the
+    // tournament only
+    async fn field(name: &str, online: &[&str], offline: &[&str]) -> Field {
+        let dir =
+            std::env::temp_dir().join(format!("chess-tournament-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let storage = Storage::open(&dir).unwrap();
+        let chat = ChatConfig::default();
+        let server = Server::new(
+            TimeControl::default(),
+            storage.clone(),
+            ChatPolicy::new(&chat, Box::new(BlockList::new(&[]))),
+            UciConfig::default(),
+            BotConfig::default(),
+            None,
+        )
+        .start();
+        let mut tournaments = Tournaments::new(server.clone(), TimeControl::default());
+        tournaments.round_break = Duration::ZERO;
+        let tournaments = tournaments.start();
+        let mut clients = HashMap::new();
+        for &username in online.iter().chain(offline) {
+            clients.insert(username.to_owned(), Client.start().recipient());
+        }
+        for &username in online {
+            let login = Login {
+                username: username.to_owned(),
+                client: clients[username].clone(),
+                lobby: false,
+                bot: false,
+                authenticated: false,
+            };
+            assert!(server.send(login).await.unwrap());
+        }
+        Field {
+            server,
+            tournaments,
+            clients,
+            dir,
+        }
+    }
+
+    impl Field {
+        // the first player creates the tournament and starts it once
+        // everyone has joined
+        async fn start(&self, kind: TournamentKind, players: &[&str]) -> u64 {
+            let organizer = players[0].to_owned();
+            let create = CreateTournament {
+                username: organizer.clone(),
+                client: self.clients[&organizer].clone(),
+                settings: TournamentSettings {
+                    name: "test".to_owned(),
+                    kind,
+                    variant: GameVariant::Standard,
+                    time_control: None,
+                },
+            };
+            self.tournaments.send(create).await.unwrap();
+            let id = 1;
+            for &username in players {
+                let join = JoinTournament {
+                    username: username.to_owned(),
+                    client: self.clients[username].clone(),
+                    id,
+                    join: true,
+                };
+                self.tournaments.send(join).await.unwrap();
+            }
+            let start = StartTournament {
+                client: self.clients[&organizer].clone(),
+                username: organizer,
+                id,
+            };
+            self.tournaments.send(start).await.unwrap();
+            id
+        }
+
+        async fn view(&self, id: u64) -> TournamentView {
+            let lookup = LookupTournament(id);
+            self.tournaments.send(lookup).await.unwrap().unwrap()
+        }
+
+        // black resigns every game as soon as it starts
+        async fn resign_black(&self, id: u64) {
+            let view = self.view(id).await;
+            for board in view.rounds.last().into_iter().flatten() {
+                let (Some(game), Some(black), None) = (&board.game, &board.black, board.score)
+                else {
+                    continue;
+                };
+                let live = self.server.send(LookupGame(game.clone())).await.unwrap();
+                if let Some(game) = live {
+                    game.do_send(ForfeitGame(self.clients[black].clone()));
+                }
+            }
+        }
+
+        async fn play_out(&self, id: u64) -> TournamentView {
+            for _ in 0..500 {
+                let view = self.view(id).await;
+                if view.status == TournamentStatus::Finished {
+                    return view;
+                }
+                self.resign_black(id).await;
+                actix::clock::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("tournament {id} never finished");
+        }
+
+        fn clean_up(&self) {
+            std::fs::remove_dir_all(&self.dir).unwrap();
+        }
+    }
+
+    // every board of the latest round has its game
+    async fn wait_for_games(field: &Field, id: u64) -> Vec<String> {
+        for _ in 0..500 {
+            let view = field.view(id).await;
+            let boards = view.rounds.last().into_iter().flatten();
+            let games: Option<Vec<String>> = boards.map(|b| b.game.clone()).collect();
+            if let Some(games) = games.filter(|games| !games.is_empty()) {
+                return games;
+            }
+            actix::clock::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("tournament {id} never started its games");
+    }
+
+    // the newest game of an arena, once it is not the one given
+    async fn wait_for_arena_game(field: &Field, id: u64, not: Option<&String>) -> String {
+        for _ in 0..500 {
+            let view = field.view(id).await;
+            let games = view.arena.map(|arena| arena.games).unwrap_or_default();
+            if let Some(game) = games.last().and_then(|board| board.game.clone()) {
+                if Some(&game) != not {
+                    return game;
+                }
+            }
+            actix::clock::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("arena {id} paired no new game");
+    }
+
+    fn points(view: &TournamentView) -> f32 {
+        view.standings.iter().map(|standing| standing.points).sum()
+    }
+
+    #[actix::test]
+    async fn swiss_gives_byes_and_forfeits_the_absent() {
+        let field = field("swiss", &["ann", "bob", "cid", "dee"], &["eve"]).await;
+        let players = ["ann", "bob", "cid", "dee", "eve"];
+        let id = field
+            .start(TournamentKind::Swiss { rounds: 2 }, &players)
+            .await;
+        let view = field.play_out(id).await;
+        assert_eq!(view.rounds.len(), 2);
+        // the lowest seed sits out the first round
+        assert_eq!(view.rounds[0].last().unwrap().white, "eve");
+        for round in &view.rounds {
+            assert_eq!(round.iter().filter(|b| b.black.is_none()).count(), 1);
+            for board in round.iter().filter(|b| b.black.is_some()) {
+                let absent = [Some(&board.white), board.black.as_ref()]
+                    .map(|name| name.is_some_and(|name| name == "eve"));
+                let expected = match absent.contains(&true) {
+                    true => Score::Forfeit(absent),
+                    false => Score::Played(GameResult::WhiteWins),
+                };
+                assert_eq!(board.score, Some(expected));
+            }
+        }
+        // the second round pairs her, and she is not there to play
+        let forfeits = view.rounds[1]
+            .iter()
+            .filter(|b| matches!(b.score, Some(Score::Forfeit(_))));
+        assert_eq!(forfeits.count(), 1);
+        // two boards and a bye a round, a point each
+        assert_eq!(points(&view), 6.0);
+        field.clean_up();
+    }
+
+    #[actix::test]
+    async fn round_robin_meets_everyone_once_with_a_bye_each() {
+        let field = field("round-robin", &["ann", "bob", "cid"], &[]).await;
+        let id = field
+            .start(TournamentKind::RoundRobin, &["ann", "bob", "cid"])
+            .await;
+        let view = field.play_out(id).await;
+        assert_eq!(view.rounds.len(), 3);
+        let mut met = vec![];
+        let mut byes = vec![];
+        for round in &view.rounds {
+            for board in round {
+                match &board.black {
+                    Some(black) => {
+                        assert_eq!(board.score, Some(Score::Played(GameResult::WhiteWins)));
+                        let mut pair = [board.white.clone(), black.clone()];
+                        pair.sort();
+                        met.push(pair);
+                    }
+                    None => byes.push(board.white.clone()),
+                }
+            }
+        }
+        met.sort();
+        met.dedup();
+        assert_eq!(met.len(), 3);
+        byes.sort();
+        assert_eq!(byes, ["ann", "bob", "cid"]);
+        assert_eq!(points(&view), 6.0);
+        field.clean_up();
+    }
+
+    #[actix::test]
+    async fn a_shutdown_pauses_between_rounds_without_forfeits() {
+        let players = ["ann", "bob", "cid", "dee"];
+        let field = field("shutdown", &players, &[]).await;
+        let id = field
+            .start(TournamentKind::Swiss { rounds: 3 }, &players)
+            .await;
+        let games = wait_for_games(&field, id).await;
+        field.tournaments.send(PauseTournaments).await.unwrap();
+        // resolves once the grace period is over and every game is adjourned
+        let shutdown = Shutdown {
+            grace: Duration::from_millis(50),
+        };
+        actix::clock::timeout(Duration::from_secs(5), field.server.send(shutdown))
+            .await
+            .unwrap()
+            .unwrap();
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let view = field.view(id).await;
+        // no round after the first was paired, so none was forfeited
+        assert_eq!(view.status, TournamentStatus::Running);
+        assert_eq!(view.rounds.len(), 1);
+        for board in &view.rounds[0] {
+            assert_eq!(board.score, None);
+        }
+        assert_eq!(points(&view), 0.0);
+        let storage = Storage::open(&field.dir).unwrap();
+        assert_eq!(storage.load_adjourned().unwrap().len(), games.len());
+        field.clean_up();
+    }
+
+    #[actix::test]
+    async fn an_arena_game_without_a_result_requeues_its_players() {
+        let players = ["ann", "bob"];
+        let field = field("arena", &players, &[]).await;
+        let id = field
+            .start(TournamentKind::Arena { minutes: 1 }, &players)
+            .await;
+        let first = wait_for_arena_game(&field, id, None).await;
+        let live = field.server.send(LookupGame(first.clone())).await.unwrap();
+        live.unwrap().send(Adjourn).await.unwrap();
+        // the pair is free for a new game, and the stopped one is gone
+        let next = wait_for_arena_game(&field, id, Some(&first)).await;
+        let view = field.view(id).await;
+        let games = view.arena.unwrap().games;
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].game.as_ref(), Some(&next));
+        assert!(games[0].score.is_none());
+        field.clean_up();
+    }
+}
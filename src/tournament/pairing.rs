@@ -0,0 +1,371 @@
+use serde::Serialize;
+
+use crate::game::GameResult;
+
+// how many pairings a swiss round may try before it gives up on avoiding rematches
+const PAIRING_BUDGET: usize = 100_000;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Score {
+    Played(GameResult),
+    // true for each side that was not there to play, and so lost
+    Forfeit([bool; 2]),
+}
+
+impl Score {
+    // half points for white and black
+    fn halves(self) -> [u32; 2] {
+        match self {
+            Score::Played(GameResult::WhiteWins) => [2, 0],
+            Score::Played(GameResult::BlackWins) => [0, 2],
+            Score::Played(GameResult::Draw) => [1, 1],
+            Score::Forfeit(missing) => missing.map(|gone| if gone { 0 } else { 2 }),
+        }
+    }
+}
+
+// one game of a round, players by seed. black is None for a bye, which
+// scores a full point
+#[derive(Clone, Debug)]
+pub struct Board {
+    pub white: usize,
+    pub black: Option<usize>,
+    pub game: Option<String>,
+    pub score: Option<Score>,
+}
+
+impl Board {
+    pub fn new((white, black): (usize, Option<usize>)) -> Self {
+        Self {
+            white,
+            black,
+            game: None,
+            score: None,
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.black.is_none() || self.score.is_some()
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct Standing {
+    pub player: usize,
+    pub points: f32,
+    // sum of the opponents' points
+    pub buchholz: f32,
+    // sum of the opponents' points, weighted by the result against each
+    pub sonneborn_berger: f32,
+}
+
+// every round of a round robin, with a bye each round for an odd field.
+// the circle method: the first player stays put while the rest rotate
+pub fn round_robin(players: usize) -> Vec<Vec<(usize, Option<usize>)>> {
+    let mut seats: Vec<Option<usize>> = (0..players).map(Some).collect();
+    if players % 2 == 1 {
+        seats.push(None);
+    }
+    let n = seats.len();
+    (0..n.saturating_sub(1))
+        .map(|round| {
+            let pairs = (0..n / 2).filter_map(|board| {
+                let (a, b) = (seats[board], seats[n - 1 - board]);
+                // alternating colours down the boards, and for the fixed seat by round
+                let swap = match board {
+                    0 => round % 2 == 1,
+                    _ => board % 2 == 1,
+                };
+                match (a, b) {
+                    (Some(a), None) | (None, Some(a)) => Some((a, None)),
+                    (Some(a), Some(b)) if swap => Some((b, Some(a))),
+                    (Some(a), Some(b)) => Some((a, Some(b))),
+                    (None, None) => None,
+                }
+            });
+            let pairs = pairs.collect();
+            seats[1..].rotate_right(1);
+            pairs
+        })
+        .collect()
+}
+
+// what the finished boards so far say about one player
+#[derive(Clone, Default)]
+struct Record {
+    halves: u32,
+    opponents: Vec<usize>,
+    // +1 for every game with white, -1 with black
+    balance: i32,
+    last_white: Option<bool>,
+    had_bye: bool,
+}
+
+fn records(players: usize, rounds: &[Vec<Board>]) -> Vec<Record> {
+    let mut records = vec![Record::default(); players];
+    for board in rounds.iter().flatten() {
+        let Some(black) = board.black else {
+            records[board.white].halves += 2;
+            records[board.white].had_bye = true;
+            continue;
+        };
+        // a board still in progress has its colours settled already
+        for (side, player, opponent) in [(0, board.white, black), (1, black, board.white)] {
+            let record = &mut records[player];
+            record.opponents.push(opponent);
+            record.balance += if side == 0 { 1 } else { -1 };
+            record.last_white = Some(side == 0);
+            if let Some(score) = board.score {
+                record.halves += score.halves()[side];
+            }
+        }
+    }
+    records
+}
+
+// the table after the finished boards, best first. swiss events break ties
+// on buchholz first, round robins on sonneborn-berger, where everyone has
+// met the same opponents
+pub fn standings(players: usize, rounds: &[Vec<Board>], buchholz_first: bool) -> Vec<Standing> {
+    let records = records(players, rounds);
+    let points = |player: usize| records[player].halves as f32 / 2.0;
+    let mut table: Vec<_> = (0..players)
+        .map(|player| Standing {
+            player,
+            points: points(player),
+            buchholz: 0.0,
+            sonneborn_berger: 0.0,
+        })
+        .collect();
+    for board in rounds.iter().flatten() {
+        let (Some(black), Some(score)) = (board.black, board.score) else {
+            continue;
+        };
+        let halves = score.halves();
+        for (side, player, opponent) in [(0, board.white, black), (1, black, board.white)] {
+            table[player].buchholz += points(opponent);
+            table[player].sonneborn_berger += points(opponent) * halves[side] as f32 / 2.0;
+        }
+    }
+    table.sort_by(|a, b| {
+        let ties = match buchholz_first {
+            true => [
+                a.buchholz,
+                a.sonneborn_berger,
+                b.buchholz,
+                b.sonneborn_berger,
+            ],
+            false => [
+                a.sonneborn_berger,
+                a.buchholz,
+                b.sonneborn_berger,
+                b.buchholz,
+            ],
+        };
+        b.points
+            .total_cmp(&a.points)
+            .then(ties[2].total_cmp(&ties[0]))
+            .then(ties[3].total_cmp(&ties[1]))
+            .then(a.player.cmp(&b.player))
+    });
+    table
+}
+
+// the next round of a swiss event. players meet others on the same score,
+// the top half of a score group against the bottom half, without rematches
+// where that can be helped and giving white to whoever had more black
+pub fn swiss_round(players: usize, rounds: &[Vec<Board>]) -> Vec<(usize, Option<usize>)> {
+    let records = records(players, rounds);
+    let mut order: Vec<usize> = (0..players).collect();
+    // seeds break ties, so the order is stable from round to round
+    order.sort_by_key(|&player| std::cmp::Reverse(records[player].halves));
+    let mut pairs = vec![];
+    if players % 2 == 1 {
+        let bye = order
+            .iter()
+            .rposition(|&player| !records[player].had_bye)
+            .unwrap_or(order.len() - 1);
+        pairs.push((order.remove(bye), None));
+    }
+    let mut budget = PAIRING_BUDGET;
+    let matched = pair(&order, &records, false, &mut budget)
+        .or_else(|| pair(&order, &records, true, &mut budget))
+        .unwrap_or_default();
+    let mut boards: Vec<_> = matched
+        .into_iter()
+        .enumerate()
+        .map(|(board, (a, b))| colours(a, b, board, &records))
+        .collect();
+    boards.append(&mut pairs);
+    boards
+}
+
+fn pair(
+    rest: &[usize],
+    records: &[Record],
+    rematches: bool,
+    budget: &mut usize,
+) -> Option<Vec<(usize, usize)>> {
+    let Some((&top, others)) = rest.split_first() else {
+        return Some(vec![]);
+    };
+    // rematches allowed always pair, so the budget only limits the first try
+    if !rematches {
+        *budget = budget.checked_sub(1)?;
+    }
+    let score = records[top].halves;
+    let group = others
+        .iter()
+        .take_while(|&&p| records[p].halves == score)
+        .count()
+        + 1;
+    let mut candidates: Vec<_> = others
+        .iter()
+        .enumerate()
+        .filter(|(_, &p)| rematches || !records[top].opponents.contains(&p))
+        .map(|(index, &p)| {
+            // within the group the bottom half comes first, in order
+            let preference = match index + 1 >= group / 2 {
+                true => index,
+                false => others.len() + group - index,
+            };
+            let clash = colour_clash(&records[top], &records[p]);
+            (records[p].halves.abs_diff(score), clash, preference, p)
+        })
+        .collect();
+    candidates.sort();
+    for (.., opponent) in candidates {
+        let left: Vec<_> = others.iter().copied().filter(|&p| p != opponent).collect();
+        if let Some(mut pairs) = pair(&left, records, rematches, budget) {
+            pairs.insert(0, (top, opponent));
+            return Some(pairs);
+        }
+        if *budget == 0 {
+            return None;
+        }
+    }
+    None
+}
+
+// both players are due the same colour
+fn colour_clash(a: &Record, b: &Record) -> bool {
+    let due = |record: &Record| match record.balance {
+        0 => record.last_white.map(|white| !white),
+        balance => Some(balance < 0),
+    };
+    matches!((due(a), due(b)), (Some(x), Some(y)) if x == y)
+}
+
+// white to the player with fewer whites, then to whoever had black last,
+// then alternating down the boards in favour of the higher placed player
+fn colours(a: usize, b: usize, board: usize, records: &[Record]) -> (usize, Option<usize>) {
+    let (ra, rb) = (&records[a], &records[b]);
+    let a_white = match ra.balance.cmp(&rb.balance) {
+        std::cmp::Ordering::Less => true,
+        std::cmp::Ordering::Greater => false,
+        std::cmp::Ordering::Equal => match (ra.last_white, rb.last_white) {
+            (Some(false), Some(true)) => true,
+            (Some(true), Some(false)) => false,
+            _ => board.is_multiple_of(2),
+        },
+    };
+    match a_white {
+        true => (a, Some(b)),
+        false => (b, Some(a)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn played(pairs: Vec<(usize, Option<usize>)>, result: GameResult) -> Vec<Board> {
+        pairs
+            .into_iter()
+            .map(|pair| Board {
+                score: pair.1.map(|_| Score::Played(result)),
+                ..Board::new(pair)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_meets_everyone_once() {
+        for players in 2..=9 {
+            let rounds = round_robin(players);
+            assert_eq!(rounds.len(), players - 1 + players % 2);
+            let mut met = HashSet::new();
+            let mut whites = vec![0i32; players];
+            for round in &rounds {
+                let seated: HashSet<_> = round
+                    .iter()
+                    .flat_map(|&(white, black)| [Some(white), black])
+                    .flatten()
+                    .collect();
+                assert_eq!(seated.len(), players);
+                for &(white, black) in round {
+                    if let Some(black) = black {
+                        assert!(met.insert((white.min(black), white.max(black))));
+                        whites[white] += 1;
+                        whites[black] -= 1;
+                    }
+                }
+            }
+            assert_eq!(met.len(), players * (players - 1) / 2);
+            assert!(whites.iter().all(|balance| balance.abs() <= 2));
+        }
+    }
+
+    #[test]
+    fn swiss_avoids_rematches_and_balances_colours() {
+        let mut rounds = vec![];
+        for _ in 0..4 {
+            let pairs = swiss_round(6, &rounds);
+            assert_eq!(pairs.len(), 3);
+            rounds.push(played(pairs, GameResult::WhiteWins));
+        }
+        let records = records(6, &rounds);
+        for record in &records {
+            let unique: HashSet<_> = record.opponents.iter().collect();
+            assert_eq!(unique.len(), 4);
+            assert!(record.balance.abs() <= 2);
+        }
+    }
+
+    #[test]
+    fn swiss_first_round_pairs_top_half_with_bottom_half() {
+        let pairs = swiss_round(5, &[]);
+        // the lowest seed sits out, then 1-3 and 2-4 with alternating colours
+        assert_eq!(pairs, [(0, Some(2)), (3, Some(1)), (4, None)]);
+        let rounds = vec![played(pairs, GameResult::Draw)];
+        // the same player does not get a second bye
+        assert!(!swiss_round(5, &rounds).contains(&(4, None)));
+    }
+
+    #[test]
+    fn tiebreaks_order_equal_scores() {
+        // 0 beats 1, 2 beats 3, then 0 draws 2 and 1 beats 3
+        let rounds = vec![
+            played(vec![(0, Some(1)), (2, Some(3))], GameResult::WhiteWins),
+            vec![
+                Board {
+                    score: Some(Score::Played(GameResult::Draw)),
+                    ..Board::new((0, Some(2)))
+                },
+                Board {
+                    score: Some(Score::Forfeit([false, true])),
+                    ..Board::new((1, Some(3)))
+                },
+            ],
+        ];
+        let table = standings(4, &rounds, true);
+        let order: Vec<_> = table.iter().map(|s| s.player).collect();
+        assert_eq!(order, [0, 2, 1, 3]);
+        assert_eq!(table[0].points, 1.5);
+        // 0 met 1 and 2, with 1 + 1.5 points between them
+        assert_eq!(table[0].buchholz, 2.5);
+        assert_eq!(table[0].sonneborn_berger, 1.0 + 0.75);
+        assert_eq!(table[1].buchholz, 1.5);
+    }
+}